@@ -1,41 +1,169 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::body::StreamBody;
+use axum::http::StatusCode;
+use bytes::Bytes;
+use iroh_resolver::block_cache::{CacheConfig, CachingLoader};
+use iroh_resolver::budget::{Budget, BudgetLoader, DeadlineExceeded, ResolveOptions};
+use iroh_resolver::loader_chain::LoaderChain;
+use iroh_resolver::proof::PathProof;
+use iroh_resolver::render::RenderFormat;
 use iroh_resolver::resolver::CidOrDomain;
+use iroh_resolver::resolver::Metadata;
+use iroh_resolver::resolver::Out;
 use iroh_resolver::resolver::OutPrettyReader;
+use iroh_resolver::resolver::Path;
 use iroh_resolver::resolver::Resolver;
+use tokio::io::DuplexStream;
 use tokio_util::io::ReaderStream;
-use tracing::info;
+use tracing::{info, warn};
 
+use crate::config::Config;
 use crate::core::State;
 use crate::response::ResponseFormat;
 
 #[derive(Debug)]
 pub struct Client {
-    resolver: Resolver<iroh_rpc_client::Client>,
+    loader: Loader,
+    resolver: Resolver<Loader>,
 }
 
-pub type PrettyStreamBody = StreamBody<ReaderStream<OutPrettyReader<iroh_rpc_client::Client>>>;
+type Loader = CachingLoader<LoaderChain>;
+
+/// Maximum duration of resolving a path and reading its content, must be shorter than the
+/// request timeout.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Size of the buffer between writing a CAR file and streaming it.
+const CAR_BUFFER_SIZE: usize = 64 * 1024;
+
+pub type PrettyStreamBody = StreamBody<ReaderStream<OutPrettyReader<BudgetLoader<Loader>>>>;
+pub type CarStreamBody = StreamBody<ReaderStream<DuplexStream>>;
 
 impl Client {
-    pub fn new(rpc_client: &iroh_rpc_client::Client) -> Self {
+    pub fn new(rpc_client: &iroh_rpc_client::Client, config: &Config) -> Self {
+        let cache_config = if config.cache {
+            CacheConfig::default()
+        } else {
+            CacheConfig::disabled()
+        };
+        // without fetching, only content that is in the local store is served
+        let chain = if config.fetch {
+            LoaderChain::network(rpc_client.clone())
+        } else {
+            LoaderChain::local(rpc_client.clone())
+        };
+        let loader = CachingLoader::new(chain, cache_config);
+
         Self {
-            resolver: Resolver::new(rpc_client.clone()),
+            resolver: Resolver::with_ipns(loader.clone(), rpc_client.clone()),
+            loader,
         }
     }
 
-    #[tracing::instrument(skip(rpc_client))]
+    #[tracing::instrument(skip(self, state))]
     pub async fn get_file(
         &self,
         path: &str,
-        rpc_client: &iroh_rpc_client::Client,
         start_time: std::time::Instant,
         state: Arc<State>,
-    ) -> Result<PrettyStreamBody, String> {
+    ) -> Result<(PrettyStreamBody, Metadata), (StatusCode, String)> {
         info!("get file {}", path);
-        state.metrics.cache_miss.inc();
-        let p: iroh_resolver::resolver::Path =
-            path.parse().map_err(|e: anyhow::Error| e.to_string())?;
+        let (res, budget) = self.resolve(path, start_time, &state).await?;
+        let metadata = res.metadata().clone();
+        // the content is read within the same limits as the path was resolved
+        let reader = res.pretty(budget.loader(self.loader.clone()));
+        let stream = ReaderStream::new(reader);
+        let body = StreamBody::new(stream);
+
+        Ok((body, metadata))
+    }
+
+    /// Resolves the path, streaming a CAR file with the blocks that prove the path, followed
+    /// by the blocks of the content, so that clients can verify the response.
+    #[tracing::instrument(skip(self, state))]
+    pub async fn get_car(
+        &self,
+        path: &str,
+        start_time: std::time::Instant,
+        state: Arc<State>,
+    ) -> Result<(CarStreamBody, Metadata), (StatusCode, String)> {
+        info!("get car {}", path);
+        let (res, proof, budget) = self.resolve_with_proof(path, start_time, &state).await?;
+        let metadata = res.metadata().clone();
+        let content = match metadata.resolved_path.last() {
+            Some((_, cid)) => *cid,
+            None => proof.root(),
+        };
+        let loader = budget.loader(self.loader.clone());
+        let (writer, reader) = tokio::io::duplex(CAR_BUFFER_SIZE);
+        tokio::task::spawn(async move {
+            if let Err(e) = proof.write_car_with_content(content, loader, writer).await {
+                warn!("failed to write car for {}: {:?}", proof.path(), e);
+            }
+        });
+        let body = StreamBody::new(ReaderStream::new(reader));
+
+        Ok((body, metadata))
+    }
+
+    /// Resolves the path to any IPLD block, converted to the given format.
+    #[tracing::instrument(skip(self, state))]
+    pub async fn get_ipld(
+        &self,
+        path: &str,
+        format: RenderFormat,
+        start_time: std::time::Instant,
+        state: Arc<State>,
+    ) -> Result<(Bytes, Metadata), (StatusCode, String)> {
+        info!("get ipld {} as {:?}", path, format);
+        let (res, _) = self.resolve(path, start_time, &state).await?;
+        let body = res
+            .render(format)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        Ok((body, res.metadata().clone()))
+    }
+
+    /// Resolves the path, returning the budget that limits reading the content as well.
+    async fn resolve(
+        &self,
+        path: &str,
+        start_time: std::time::Instant,
+        state: &State,
+    ) -> Result<(Out, Budget), (StatusCode, String)> {
+        let (p, budget) = self.start_resolve(path, start_time, state)?;
+        let res = self.resolver.resolve_with_budget(p, &budget).await;
+        let res = self.finish_resolve(res, state)?;
+
+        Ok((res, budget))
+    }
+
+    /// Like [`Client::resolve`], additionally returning the blocks that prove the path.
+    async fn resolve_with_proof(
+        &self,
+        path: &str,
+        start_time: std::time::Instant,
+        state: &State,
+    ) -> Result<(Out, PathProof, Budget), (StatusCode, String)> {
+        let (p, budget) = self.start_resolve(path, start_time, state)?;
+        let res = budget.run(None, self.resolver.resolve_with_proof(p)).await;
+        let (res, proof) = self.finish_resolve(res, state)?;
+
+        Ok((res, proof, budget))
+    }
+
+    /// Parses the path and starts the budget for resolving it.
+    fn start_resolve(
+        &self,
+        path: &str,
+        start_time: std::time::Instant,
+        state: &State,
+    ) -> Result<(Path, Budget), (StatusCode, String)> {
+        let p: Path = path
+            .parse()
+            .map_err(|e: anyhow::Error| (StatusCode::BAD_REQUEST, e.to_string()))?;
         // todo(arqu): this is wrong but currently don't have access to the data stream
         state
             .metrics
@@ -45,12 +173,27 @@ impl Client {
             .metrics
             .hist_ttfb
             .observe(start_time.elapsed().as_millis() as f64);
-        let res = self.resolver.resolve(p).await.map_err(|e| e.to_string())?;
-        let reader = res.pretty(rpc_client.clone());
-        let stream = ReaderStream::new(reader);
-        let body = StreamBody::new(stream);
+        let budget = ResolveOptions::default().timeout(RESOLVE_TIMEOUT).start();
 
-        Ok(body)
+        Ok((p, budget))
+    }
+
+    /// Records the cache statistics of resolving and maps errors to their status code.
+    fn finish_resolve<T>(
+        &self,
+        res: anyhow::Result<T>,
+        state: &State,
+    ) -> Result<T, (StatusCode, String)> {
+        let stats = self.loader.take_stats();
+        state.metrics.cache_hit.inc_by(stats.hits);
+        state.metrics.cache_miss.inc_by(stats.misses);
+        res.map_err(|e| {
+            if e.is::<DeadlineExceeded>() {
+                (StatusCode::GATEWAY_TIMEOUT, e.to_string())
+            } else {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }
+        })
     }
 }
 
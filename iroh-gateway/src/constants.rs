@@ -2,6 +2,7 @@ use axum::http::{header::HeaderName, HeaderValue};
 
 // Headers
 pub static HEADER_X_IPFS_PATH: HeaderName = HeaderName::from_static("x-ipfs-path");
+pub static HEADER_X_IPFS_ROOTS: HeaderName = HeaderName::from_static("x-ipfs-roots");
 pub static HEADER_X_CONTENT_TYPE_OPTIONS: HeaderName =
     HeaderName::from_static("x-content-type-options");
 pub static HEADER_X_TRACE_ID: HeaderName = HeaderName::from_static("x-trace-id");
@@ -26,5 +27,9 @@ pub static CONTENT_TYPE_IPLD_RAW: HeaderValue =
     HeaderValue::from_static("application/vnd.ipld.raw");
 pub static CONTENT_TYPE_IPLD_CAR: HeaderValue =
     HeaderValue::from_static("application/vnd.ipld.car; version=1");
+pub static CONTENT_TYPE_IPLD_DAG_JSON: HeaderValue =
+    HeaderValue::from_static("application/vnd.ipld.dag-json");
+pub static CONTENT_TYPE_IPLD_DAG_CBOR: HeaderValue =
+    HeaderValue::from_static("application/vnd.ipld.dag-cbor");
 pub static CONTENT_TYPE_OCTET_STREAM: HeaderValue =
     HeaderValue::from_static("application/octet-stream");
@@ -9,7 +9,8 @@ use axum::{
 };
 use bytes::Bytes;
 use cid::Cid;
-use iroh_resolver::resolver::CidOrDomain;
+use iroh_resolver::render::RenderFormat;
+use iroh_resolver::resolver::{CidOrDomain, Path as ContentPath, PathType};
 use iroh_rpc_client::Client as RpcClient;
 use serde::{Deserialize, Serialize};
 use serde_qs;
@@ -42,7 +43,6 @@ pub struct Core {
 pub struct State {
     config: Config,
     client: Client,
-    rpc_client: iroh_rpc_client::Client,
     pub metrics: Metrics,
 }
 
@@ -72,11 +72,12 @@ impl Core {
     pub async fn new(config: Config, metrics: Metrics) -> anyhow::Result<Self> {
         let rpc_client = RpcClient::new(&config.rpc.client_config).await?;
 
+        let client = Client::new(&rpc_client, &config);
+
         Ok(Self {
             state: Arc::new(State {
                 config,
-                client: Client::new(&rpc_client),
-                rpc_client,
+                client,
                 metrics,
             }),
         })
@@ -182,7 +183,8 @@ async fn get_ipfs(
             return Err(error(StatusCode::BAD_REQUEST, "invalid cid", &state));
         }
     };
-    let full_content_path = format!("/ipfs/{}{}", cid, cpath);
+    let full_content_path = full_content_path(PathType::Ipfs, &cid.to_string(), cpath)
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string(), &state))?;
 
     // todo(arqu): actually plug in a resolver
     let resolved_cid = CidOrDomain::Cid(resolve_cid(&cid).await.unwrap());
@@ -220,7 +222,6 @@ async fn get_ipfs(
         &HEADER_X_IPFS_PATH,
         HeaderValue::from_str(&full_content_path).unwrap(),
     );
-    // todo(arqu): add X-Ipfs-Roots
 
     // handle request and fetch data
     let req = Request {
@@ -235,6 +236,12 @@ async fn get_ipfs(
     match req.format {
         ResponseFormat::Raw => serve_raw(&req, state, headers, start_time).await,
         ResponseFormat::Car => serve_car(&req, state, headers, start_time).await,
+        ResponseFormat::DagJson => {
+            serve_ipld(&req, state, headers, start_time, RenderFormat::DagJson).await
+        }
+        ResponseFormat::DagCbor => {
+            serve_ipld(&req, state, headers, start_time, RenderFormat::DagCbor).await
+        }
         ResponseFormat::Fs(_) => serve_fs(&req, state, headers, start_time).await,
     }
 }
@@ -273,7 +280,8 @@ async fn get_ipns(
     }
 
     // TODO: better path validation
-    let full_content_path = format!("/ipns/{}{}", cid, cpath);
+    let full_content_path = full_content_path(PathType::Ipns, &cid, cpath)
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string(), &state))?;
 
     // parse query params
     let format = match get_response_format(&request_headers, query_params.format) {
@@ -299,7 +307,6 @@ async fn get_ipns(
         &HEADER_X_IPFS_PATH,
         HeaderValue::from_str(&full_content_path).unwrap(),
     );
-    // todo(arqu): add X-Ipfs-Roots
 
     // handle request and fetch data
     let req = Request {
@@ -314,10 +321,29 @@ async fn get_ipns(
     match req.format {
         ResponseFormat::Raw => serve_raw(&req, state, headers, start_time).await,
         ResponseFormat::Car => serve_car(&req, state, headers, start_time).await,
+        ResponseFormat::DagJson => {
+            serve_ipld(&req, state, headers, start_time, RenderFormat::DagJson).await
+        }
+        ResponseFormat::DagCbor => {
+            serve_ipld(&req, state, headers, start_time, RenderFormat::DagCbor).await
+        }
         ResponseFormat::Fs(_) => serve_fs(&req, state, headers, start_time).await,
     }
 }
 
+/// Formats the path of the requested content from the route parameters.
+///
+/// The parameters are already percent-decoded, so the segments are taken as is and
+/// escaped again when formatted, instead of being decoded a second time when parsed.
+fn full_content_path(typ: PathType, root: &str, cpath: &str) -> anyhow::Result<String> {
+    let tail = cpath
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(ToString::to_string)
+        .collect();
+    Ok(ContentPath::from_segments(typ, root, tail)?.to_string())
+}
+
 // todo(arqu): flesh out resolving
 #[tracing::instrument()]
 async fn resolve_cid(cid: &Cid) -> Result<Cid, String> {
@@ -332,17 +358,12 @@ async fn serve_raw(
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
     // FIXME: we currently only retrieve full cids
-    let body = state
+    let (body, metadata) = state
         .client
-        .get_file(
-            &req.full_content_path,
-            &state.rpc_client,
-            start_time,
-            Arc::clone(&state),
-        )
+        .get_file(&req.full_content_path, start_time, Arc::clone(&state))
         .await
-        .unwrap();
-    // .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+        .map_err(|(status_code, e)| error(status_code, &e, &state))?;
+    add_ipfs_roots_headers(&mut headers, &metadata);
 
     set_content_disposition_headers(
         &mut headers,
@@ -361,17 +382,12 @@ async fn serve_car(
     mut headers: HeaderMap,
     start_time: std::time::Instant,
 ) -> Result<GatewayResponse, GatewayError> {
-    // FIXME: we currently only retrieve full cids
-    let body = state
+    let (body, metadata) = state
         .client
-        .get_file(
-            &req.full_content_path,
-            &state.rpc_client,
-            start_time,
-            Arc::clone(&state),
-        )
+        .get_car(&req.full_content_path, start_time, Arc::clone(&state))
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|(status_code, e)| error(status_code, &e, &state))?;
+    add_ipfs_roots_headers(&mut headers, &metadata);
 
     set_content_disposition_headers(
         &mut headers,
@@ -386,24 +402,51 @@ async fn serve_car(
     response(StatusCode::OK, body, headers)
 }
 
+/// Serves any IPLD block converted to the requested codec.
 #[tracing::instrument()]
-async fn serve_fs(
+async fn serve_ipld(
     req: &Request,
     state: Arc<State>,
     mut headers: HeaderMap,
     start_time: std::time::Instant,
+    format: RenderFormat,
 ) -> Result<GatewayResponse, GatewayError> {
-    // FIXME: we currently only retrieve full cids
-    let body = state
+    let (body, metadata) = state
         .client
-        .get_file(
+        .get_ipld(
             &req.full_content_path,
-            &state.rpc_client,
+            format,
             start_time,
             Arc::clone(&state),
         )
         .await
-        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e, &state))?;
+        .map_err(|(status_code, e)| error(status_code, &e, &state))?;
+    add_ipfs_roots_headers(&mut headers, &metadata);
+
+    set_content_disposition_headers(
+        &mut headers,
+        format!("{}.{}", req.cid, req.format.get_extenstion()).as_str(),
+        DISPOSITION_INLINE,
+    );
+    set_etag_headers(&mut headers, get_etag(&req.cid, Some(req.format.clone())));
+    add_cache_control_headers(&mut headers, req.full_content_path.to_string());
+    response(StatusCode::OK, Body::from(body), headers)
+}
+
+#[tracing::instrument()]
+async fn serve_fs(
+    req: &Request,
+    state: Arc<State>,
+    mut headers: HeaderMap,
+    start_time: std::time::Instant,
+) -> Result<GatewayResponse, GatewayError> {
+    // FIXME: we currently only retrieve full cids
+    let (body, metadata) = state
+        .client
+        .get_file(&req.full_content_path, start_time, Arc::clone(&state))
+        .await
+        .map_err(|(status_code, e)| error(status_code, &e, &state))?;
+    add_ipfs_roots_headers(&mut headers, &metadata);
 
     let name = add_content_disposition_headers(
         &mut headers,
@@ -413,6 +456,9 @@ async fn serve_fs(
     );
     set_etag_headers(&mut headers, get_etag(&req.cid, Some(req.format.clone())));
     add_cache_control_headers(&mut headers, req.full_content_path.to_string());
+    if let Some(mtime) = metadata.mtime {
+        set_last_modified_headers(&mut headers, mtime);
+    }
     add_content_type_headers(&mut headers, &name);
     response(StatusCode::OK, body, headers)
 }
@@ -469,3 +515,29 @@ async fn middleware_error_handler(
         &state,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_content_path() {
+        let cid = "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy";
+        // a request for `/ipfs/{cid}/dir/100%2525.txt`, as decoded by the router
+        let path = full_content_path(PathType::Ipfs, cid, "/dir/100%25.txt").unwrap();
+        assert_eq!(path, format!("/ipfs/{cid}/dir/100%2525.txt"));
+        let parsed: ContentPath = path.parse().unwrap();
+        assert_eq!(
+            parsed.tail(),
+            &["dir".to_string(), "100%25.txt".to_string()]
+        );
+
+        let path = full_content_path(PathType::Ipfs, cid, "/100%.txt").unwrap();
+        let parsed: ContentPath = path.parse().unwrap();
+        assert_eq!(parsed.tail(), &["100%.txt".to_string()]);
+
+        let path = full_content_path(PathType::Ipns, "ipfs.io", "").unwrap();
+        assert_eq!(path, "/ipns/ipfs.io");
+        assert!(full_content_path(PathType::Ipfs, "ipfs.io", "/a").is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use crate::{constants::*, response::ResponseFormat};
+use ::headers::HeaderMapExt;
 use ::time::OffsetDateTime;
 use axum::http::header::*;
-use iroh_resolver::resolver::CidOrDomain;
+use iroh_resolver::resolver::{CidOrDomain, Metadata};
 use std::time;
 
 #[tracing::instrument()]
@@ -63,6 +64,26 @@ pub fn add_cache_control_headers(headers: &mut HeaderMap, content_path: String)
     }
 }
 
+/// Uses the modification time stored in UnixFS, instead of the time of the request.
+#[tracing::instrument()]
+pub fn set_last_modified_headers(headers: &mut HeaderMap, mtime: time::SystemTime) {
+    headers.typed_insert(::headers::LastModified::from(mtime));
+}
+
+/// Lists the CIDs of all resolved path segments, so clients can verify the resolution.
+#[tracing::instrument()]
+pub fn add_ipfs_roots_headers(headers: &mut HeaderMap, metadata: &Metadata) {
+    let roots: Vec<String> = metadata
+        .resolved_path
+        .iter()
+        .map(|(_, cid)| cid.to_string())
+        .collect();
+    headers.insert(
+        &HEADER_X_IPFS_ROOTS,
+        HeaderValue::from_str(&roots.join(",")).unwrap(),
+    );
+}
+
 #[tracing::instrument()]
 pub fn set_etag_headers(headers: &mut HeaderMap, etag: String) {
     headers.insert(ETAG, HeaderValue::from_str(&etag).unwrap());
@@ -178,6 +199,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_last_modified_headers_test() {
+        let mut headers = HeaderMap::new();
+        let mtime = time::UNIX_EPOCH + time::Duration::from_secs(1_650_000_000);
+        set_last_modified_headers(&mut headers, mtime);
+        assert_eq!(
+            headers.get(&LAST_MODIFIED).unwrap(),
+            &"Fri, 15 Apr 2022 05:20:00 GMT".to_string()
+        );
+    }
+
+    #[test]
+    fn add_ipfs_roots_headers_test() {
+        let root: Cid = "QmfTVUNatSpmZUERu62hwSEuLHEUNuY8FFuzFL5n187yGq"
+            .parse()
+            .unwrap();
+        let bar: Cid = "QmT7qkMZnZNDACJ8CT4PnVkxXKJfcKNVggkygzRcvZE72B"
+            .parse()
+            .unwrap();
+        let metadata = Metadata {
+            path: format!("/ipfs/{}/bar", root).parse().unwrap(),
+            size: None,
+            typ: iroh_resolver::resolver::OutType::Unixfs,
+            unixfs_type: None,
+            resolved_path: vec![(root.to_string(), root), ("bar".to_string(), bar)],
+            mode: None,
+            mtime: None,
+        };
+        let mut headers = HeaderMap::new();
+        add_ipfs_roots_headers(&mut headers, &metadata);
+        assert_eq!(
+            headers.get(&HEADER_X_IPFS_ROOTS).unwrap(),
+            &format!("{},{}", root, bar)
+        );
+    }
+
     #[test]
     fn add_content_type_headers_test() {
         let mut headers = HeaderMap::new();
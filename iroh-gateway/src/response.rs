@@ -12,6 +12,8 @@ pub const ERR_UNSUPPORTED_FORMAT: &str = "unsuported format";
 pub enum ResponseFormat {
     Raw,
     Car,
+    DagJson,
+    DagCbor,
     Fs(String),
 }
 
@@ -22,6 +24,8 @@ impl std::convert::TryFrom<&str> for ResponseFormat {
         match s.to_lowercase().as_str() {
             "application/vnd.ipld.raw" | "raw" => Ok(ResponseFormat::Raw),
             "application/vnd.ipld.car" | "car" => Ok(ResponseFormat::Car),
+            "application/vnd.ipld.dag-json" | "dag-json" => Ok(ResponseFormat::DagJson),
+            "application/vnd.ipld.dag-cbor" | "dag-cbor" => Ok(ResponseFormat::DagCbor),
             "fs" | "" => Ok(ResponseFormat::Fs(String::new())),
             rf => {
                 if rf.starts_with("application/vnd.ipld.") {
@@ -47,6 +51,14 @@ impl ResponseFormat {
                 headers.insert(ACCEPT_RANGES, VALUE_NONE.clone());
                 headers.insert(CACHE_CONTROL, VALUE_NO_CACHE_NO_TRANSFORM.clone());
             }
+            ResponseFormat::DagJson => {
+                headers.insert(CONTENT_TYPE, CONTENT_TYPE_IPLD_DAG_JSON.clone());
+                headers.insert(&HEADER_X_CONTENT_TYPE_OPTIONS, VALUE_XCTO_NOSNIFF.clone());
+            }
+            ResponseFormat::DagCbor => {
+                headers.insert(CONTENT_TYPE, CONTENT_TYPE_IPLD_DAG_CBOR.clone());
+                headers.insert(&HEADER_X_CONTENT_TYPE_OPTIONS, VALUE_XCTO_NOSNIFF.clone());
+            }
             ResponseFormat::Fs(_) => {
                 headers.insert(CONTENT_TYPE, CONTENT_TYPE_OCTET_STREAM.clone());
             }
@@ -57,6 +69,8 @@ impl ResponseFormat {
         match self {
             ResponseFormat::Raw => "bin".to_string(),
             ResponseFormat::Car => "car".to_string(),
+            ResponseFormat::DagJson => "json".to_string(),
+            ResponseFormat::DagCbor => "cbor".to_string(),
             ResponseFormat::Fs(s) => {
                 if s.is_empty() {
                     String::new()
@@ -78,6 +92,8 @@ impl ResponseFormat {
                         // todo(arqu): add support for better media type detection
                         if h_value != "application/vnd.ipld.raw"
                             && h_value != "application/vnd.ipld.car"
+                            && h_value != "application/vnd.ipld.dag-json"
+                            && h_value != "application/vnd.ipld.dag-cbor"
                         {
                             return Err(format!("{}: {}", ERR_UNSUPPORTED_FORMAT, h_value));
                         }
@@ -164,6 +180,11 @@ mod tests {
         let rf = ResponseFormat::try_from("");
         assert_eq!(rf, Ok(ResponseFormat::Fs(String::new())));
 
+        let rf = ResponseFormat::try_from("dag-json");
+        assert_eq!(rf, Ok(ResponseFormat::DagJson));
+        let rf = ResponseFormat::try_from("application/vnd.ipld.dag-cbor");
+        assert_eq!(rf, Ok(ResponseFormat::DagCbor));
+
         let rf = ResponseFormat::try_from("RaW");
         assert_eq!(rf, Ok(ResponseFormat::Raw));
 
@@ -193,6 +214,15 @@ mod tests {
             &VALUE_XCTO_NOSNIFF
         );
 
+        let rf = ResponseFormat::try_from("dag-json").unwrap();
+        let mut headers = HeaderMap::new();
+        rf.write_headers(&mut headers);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(
+            headers.get(&CONTENT_TYPE).unwrap(),
+            &CONTENT_TYPE_IPLD_DAG_JSON
+        );
+
         let rf = ResponseFormat::try_from("fs").unwrap();
         let mut headers = HeaderMap::new();
         rf.write_headers(&mut headers);
@@ -16,7 +16,8 @@ use iroh_bitswap::{Block, QueryError};
 use iroh_rpc_types::p2p::p2p_server;
 use iroh_rpc_types::p2p::{
     BitswapRequest, BitswapResponse, ConnectRequest, ConnectResponse, DisconnectRequest, Empty,
-    GetListeningAddrsResponse, GetPeersResponse, Key as ProviderKey, Multiaddrs, Providers,
+    GetListeningAddrsResponse, GetPeersResponse, GetRecordResponse, Key as ProviderKey, Multiaddrs,
+    Providers,
 };
 
 struct P2p {
@@ -103,6 +104,33 @@ impl p2p_server::P2p for P2p {
         Ok(Response::new(Providers { providers }))
     }
 
+    #[tracing::instrument(skip(self, request))]
+    async fn get_record(
+        &self,
+        request: Request<ProviderKey>,
+    ) -> Result<Response<GetRecordResponse>, tonic::Status> {
+        iroh_metrics::req::set_trace_ctx(&request);
+        let req = request.into_inner();
+        trace!("received GetRecordRequest: {:?}", req.key);
+        let (s, r) = oneshot::channel();
+        let msg = RpcMessage::GetRecordRequest {
+            key: req.key.into(),
+            response_channel: s,
+        };
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| Status::internal("receiver dropped"))?;
+
+        let records = r
+            .await
+            .map_err(|_| Status::internal("sender dropped"))?
+            .map_err(|e| Status::not_found(format!("failed to retrieve record: {:?}", e)))?;
+
+        let records = records.into_iter().map(Into::into).collect();
+        Ok(Response::new(GetRecordResponse { records }))
+    }
+
     async fn get_listening_addrs(
         &self,
         _request: Request<Empty>,
@@ -214,6 +242,10 @@ pub enum RpcMessage {
         key: Key,
         response_channel: oneshot::Sender<Result<HashSet<PeerId>, String>>,
     },
+    GetRecordRequest {
+        key: Key,
+        response_channel: oneshot::Sender<Result<Vec<Vec<u8>>, String>>,
+    },
     NetListeningAddrs(oneshot::Sender<(PeerId, Vec<Multiaddr>)>),
     NetPeers(oneshot::Sender<HashMap<PeerId, Vec<Multiaddr>>>),
     NetConnect(oneshot::Sender<bool>, PeerId, Vec<Multiaddr>),
@@ -16,7 +16,8 @@ pub use libp2p::gossipsub::{IdentTopic, Topic};
 use libp2p::identify::{IdentifyEvent, IdentifyInfo};
 use libp2p::identity::Keypair;
 use libp2p::kad::{
-    self, record::Key, GetProvidersError, GetProvidersOk, KademliaEvent, QueryResult,
+    self, record::Key, GetProvidersError, GetProvidersOk, GetRecordError, GetRecordOk,
+    KademliaEvent, PeerRecord, QueryId, QueryResult, Quorum,
 };
 use libp2p::metrics::{Metrics, Recorder};
 use libp2p::multiaddr::Protocol;
@@ -57,17 +58,21 @@ pub struct Libp2pService {
     net_receiver_in: Receiver<RpcMessage>,
     bitswap_queries: AHashMap<BitswapQueryId, OneShotSender<Result<Block, QueryError>>>,
     kad_queries: AHashMap<QueryKey, QueryChannel>,
+    /// Keys of the running record queries, successful queries without records don't report it.
+    record_queries: AHashMap<QueryId, Key>,
     metrics: Metrics,
     rpc_client: RpcClient,
 }
 
 enum QueryChannel {
     GetProviders(Vec<oneshot::Sender<Result<HashSet<PeerId>, String>>>),
+    GetRecord(Vec<oneshot::Sender<Result<Vec<Vec<u8>>, String>>>),
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
 enum QueryKey {
     ProviderKey(Key),
+    RecordKey(Key),
 }
 
 impl Libp2pService {
@@ -117,6 +122,7 @@ impl Libp2pService {
             net_receiver_in: network_receiver_in,
             bitswap_queries: Default::default(),
             kad_queries: Default::default(),
+            record_queries: Default::default(),
             metrics,
             rpc_client,
         })
@@ -220,7 +226,7 @@ impl Libp2pService {
             }
             Event::Kademlia(e) => {
                 self.metrics.record(&e);
-                if let KademliaEvent::OutboundQueryCompleted { result, .. } = e {
+                if let KademliaEvent::OutboundQueryCompleted { id, result, .. } = e {
                     debug!("kad: {:?}", result);
                     match result {
                         QueryResult::GetProviders(Ok(GetProvidersOk {
@@ -253,6 +259,39 @@ impl Libp2pService {
                                 }
                             }
                         }
+                        QueryResult::GetRecord(Ok(GetRecordOk { records, .. })) => {
+                            let key = self.record_queries.remove(&id);
+                            if records.is_empty() {
+                                if let Some(key) = key {
+                                    self.respond_record_query(
+                                        key,
+                                        Err("record not found".to_string()),
+                                    );
+                                }
+                            } else {
+                                let key = key.unwrap_or_else(|| records[0].record.key.clone());
+                                self.respond_record_query(key, Ok(records_values(records)));
+                            }
+                        }
+                        QueryResult::GetRecord(Err(err)) => {
+                            let (key, res) = match err {
+                                GetRecordError::NotFound { key, .. } => {
+                                    (key, Err("record not found".to_string()))
+                                }
+                                GetRecordError::QuorumFailed { key, records, .. }
+                                | GetRecordError::Timeout { key, records, .. } => {
+                                    // return what we have, if anything
+                                    if records.is_empty() {
+                                        (key, Err("record lookup timed out".to_string()))
+                                    } else {
+                                        (key, Ok(records_values(records)))
+                                    }
+                                }
+                            };
+                            self.record_queries.remove(&id);
+                            debug!("GetRecord failed {:?}", key);
+                            self.respond_record_query(key, res);
+                        }
                         other => {
                             debug!("Libp2p => Unhandled Kademlia query result: {:?}", other)
                         }
@@ -345,6 +384,34 @@ impl Libp2pService {
                     response_channel.send(Ok(Default::default())).ok();
                 }
             }
+            RpcMessage::GetRecordRequest {
+                key,
+                response_channel,
+            } => {
+                if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+                    if let Some(QueryChannel::GetRecord(chans)) =
+                        self.kad_queries.get_mut(&QueryKey::RecordKey(key.clone()))
+                    {
+                        debug!(
+                            "RpcMessage::GetRecordRequest: already fetching record for {:?}",
+                            key
+                        );
+                        chans.push(response_channel);
+                    } else {
+                        debug!("RpcMessage::GetRecordRequest: getting record for {:?}", key);
+                        let id = kad.get_record(key.clone(), Quorum::One);
+                        self.record_queries.insert(id, key.clone());
+                        self.kad_queries.insert(
+                            QueryKey::RecordKey(key),
+                            QueryChannel::GetRecord(vec![response_channel]),
+                        );
+                    }
+                } else {
+                    response_channel
+                        .send(Err("kademlia is disabled".to_string()))
+                        .ok();
+                }
+            }
             RpcMessage::NetListeningAddrs(response_channel) => {
                 let listeners: Vec<_> = Swarm::listeners(&self.swarm).cloned().collect();
                 let peer_id = Swarm::local_peer_id(&self.swarm);
@@ -397,6 +464,23 @@ impl Libp2pService {
 
         Ok(())
     }
+
+    fn respond_record_query(&mut self, key: Key, res: Result<Vec<Vec<u8>>, String>) {
+        if let Some(QueryChannel::GetRecord(chans)) =
+            self.kad_queries.remove(&QueryKey::RecordKey(key.clone()))
+        {
+            for chan in chans.into_iter() {
+                debug!("Sending record for {:?}", key);
+                chan.send(res.clone()).ok();
+            }
+        } else {
+            debug!("No listeners");
+        }
+    }
+}
+
+fn records_values(records: Vec<PeerRecord>) -> Vec<Vec<u8>> {
+    records.into_iter().map(|r| r.record.value).collect()
 }
 
 /// Builds the transport stack that LibP2P will communicate over.
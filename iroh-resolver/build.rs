@@ -1,6 +1,13 @@
 fn main() {
     prost_build::Config::new()
-        .bytes(&[".unixfs_pb.Data", ".merkledag_pb.PBNode.Data"])
-        .compile_protos(&["src/unixfs.proto", "src/merkledag.proto"], &["src"])
+        .bytes(&[
+            ".unixfs_pb.Data",
+            ".merkledag_pb.PBNode.Data",
+            ".ipns_pb.IpnsEntry",
+        ])
+        .compile_protos(
+            &["src/unixfs.proto", "src/merkledag.proto", "src/ipns.proto"],
+            &["src"],
+        )
         .unwrap();
 }
@@ -0,0 +1,110 @@
+use std::io;
+
+use anyhow::Result;
+use bytes::Bytes;
+use cid::Cid;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+
+use crate::unixfs_builder::{encode_leaf, encode_stem, TreeLink};
+
+/// Maximum number of links per node used by go-ipfs by default.
+pub const DEFAULT_DEGREE: usize = 174;
+
+/// Builds a balanced tree out of the given chunks, using raw leaves.
+///
+/// Produces the same layout as the balanced builder in go-ipfs: the tree is filled from the
+/// left and only grows in depth once all existing nodes are full.
+/// Blocks are yielded as soon as they are complete, the root block is always the last one.
+pub fn stream_balanced_tree<S>(
+    chunks: S,
+    degree: usize,
+) -> impl Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>> + Send
+where
+    S: Stream<Item = io::Result<Bytes>> + Send,
+{
+    assert!(degree > 1, "degree must be at least 2");
+
+    let chunks = Box::pin(chunks);
+    stream::try_unfold(
+        (chunks, Some(TreeBuilder::new(degree))),
+        |(mut chunks, tree)| async move {
+            let mut tree = match tree {
+                Some(tree) => tree,
+                None => return Ok(None),
+            };
+
+            match chunks.next().await {
+                Some(chunk) => {
+                    let blocks = tree.push_leaf(chunk?);
+                    Ok(Some((blocks, (chunks, Some(tree)))))
+                }
+                None => {
+                    let blocks = tree.finish();
+                    Ok(Some((blocks, (chunks, None))))
+                }
+            }
+        },
+    )
+    .map_ok(|blocks| stream::iter(blocks.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+#[derive(Debug)]
+struct TreeBuilder {
+    degree: usize,
+    /// Links waiting to be put into a node, per level. Level `0` holds the leaves.
+    levels: Vec<Vec<TreeLink>>,
+}
+
+impl TreeBuilder {
+    fn new(degree: usize) -> Self {
+        TreeBuilder {
+            degree,
+            levels: vec![Vec::with_capacity(degree)],
+        }
+    }
+
+    fn push_leaf(&mut self, chunk: Bytes) -> Vec<(Cid, Bytes, Vec<Cid>)> {
+        let mut blocks = Vec::new();
+        let (link, block) = encode_leaf(chunk);
+        blocks.push(block);
+        self.push(0, link, &mut blocks);
+        blocks
+    }
+
+    /// Adds a link on the given level, turning the level into a node first if it is full.
+    fn push(&mut self, level: usize, link: TreeLink, blocks: &mut Vec<(Cid, Bytes, Vec<Cid>)>) {
+        if self.levels.len() == level {
+            self.levels.push(Vec::with_capacity(self.degree));
+        }
+        if self.levels[level].len() == self.degree {
+            let links = std::mem::replace(&mut self.levels[level], Vec::with_capacity(self.degree));
+            let (node, block) = encode_stem(&links);
+            blocks.push(block);
+            self.push(level + 1, node, blocks);
+        }
+        self.levels[level].push(link);
+    }
+
+    /// Wraps up all pending levels, until a single root is left.
+    fn finish(mut self) -> Vec<(Cid, Bytes, Vec<Cid>)> {
+        let mut blocks = Vec::new();
+        if self.levels[0].is_empty() {
+            // empty content is represented by a single empty leaf
+            let (link, block) = encode_leaf(Bytes::new());
+            blocks.push(block);
+            self.levels[0].push(link);
+        }
+
+        let mut level = 0;
+        while level + 1 < self.levels.len() || self.levels[level].len() > 1 {
+            let links = std::mem::take(&mut self.levels[level]);
+            let (node, block) = encode_stem(&links);
+            blocks.push(block);
+            self.push(level + 1, node, &mut blocks);
+            level += 1;
+        }
+
+        blocks
+    }
+}
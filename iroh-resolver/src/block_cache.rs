@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::Cid;
+use tracing::trace;
+
+use crate::resolver::ContentLoader;
+
+/// Maximum size of the cached blocks by default.
+pub const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum number of failed loads that are remembered.
+const MAX_NEGATIVE_ENTRIES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum total size in bytes of the cached blocks, `0` disables caching.
+    pub max_bytes: usize,
+    /// If set, failed loads are remembered for this duration and fail immediately.
+    pub negative_ttl: Option<Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_bytes: DEFAULT_CACHE_SIZE,
+            negative_ttl: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Passes all loads through, without caching anything. Every load counts as a miss.
+    pub fn disabled() -> Self {
+        CacheConfig {
+            max_bytes: 0,
+            negative_ttl: None,
+        }
+    }
+}
+
+/// Counters of a [`CachingLoader`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Loads that failed immediately, because of a recent failure.
+    pub negative_hits: u64,
+    pub evictions: u64,
+    /// Current size of the cached blocks.
+    pub bytes: u64,
+    /// Current number of cached blocks.
+    pub entries: u64,
+}
+
+/// A [`ContentLoader`] that keeps recently loaded blocks in memory.
+///
+/// Blocks are evicted in least recently used order, once the total size of the cached
+/// blocks exceeds the configured maximum. Blocks larger than the maximum are not cached.
+/// Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct CachingLoader<T: ContentLoader> {
+    loader: T,
+    config: CacheConfig,
+    cache: Arc<Mutex<Lru>>,
+    negative: Arc<Mutex<HashMap<Cid, Instant>>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    negative_hits: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<T: ContentLoader> CachingLoader<T> {
+    pub fn new(loader: T, config: CacheConfig) -> Self {
+        CachingLoader {
+            loader,
+            config,
+            cache: Default::default(),
+            negative: Default::default(),
+            counters: Default::default(),
+        }
+    }
+
+    pub fn loader(&self) -> &T {
+        &self.loader
+    }
+
+    /// Returns the counters accumulated since the last call, and the current size.
+    pub fn take_stats(&self) -> CacheStats {
+        let (bytes, entries) = {
+            let cache = self.cache.lock().unwrap();
+            (cache.bytes as u64, cache.blocks.len() as u64)
+        };
+
+        CacheStats {
+            hits: self.counters.hits.swap(0, Ordering::Relaxed),
+            misses: self.counters.misses.swap(0, Ordering::Relaxed),
+            negative_hits: self.counters.negative_hits.swap(0, Ordering::Relaxed),
+            evictions: self.counters.evictions.swap(0, Ordering::Relaxed),
+            bytes,
+            entries,
+        }
+    }
+
+    /// Removes all cached blocks and failures.
+    pub fn clear(&self) {
+        *self.cache.lock().unwrap() = Lru::default();
+        self.negative.lock().unwrap().clear();
+    }
+
+    fn check_negative(&self, cid: &Cid) -> bool {
+        if self.config.negative_ttl.is_none() {
+            return false;
+        }
+
+        let mut negative = self.negative.lock().unwrap();
+        match negative.get(cid) {
+            Some(expires) if *expires > Instant::now() => true,
+            Some(_) => {
+                negative.remove(cid);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn insert_negative(&self, cid: Cid) {
+        if let Some(ttl) = self.config.negative_ttl {
+            let now = Instant::now();
+            let mut negative = self.negative.lock().unwrap();
+            if negative.len() >= MAX_NEGATIVE_ENTRIES {
+                negative.retain(|_, expires| *expires > now);
+                if negative.len() >= MAX_NEGATIVE_ENTRIES {
+                    negative.clear();
+                }
+            }
+            negative.insert(cid, now + ttl);
+        }
+    }
+}
+
+#[async_trait]
+impl<T: ContentLoader> ContentLoader for CachingLoader<T> {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        if self.config.max_bytes == 0 && self.config.negative_ttl.is_none() {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            return self.loader.load_cid(cid).await;
+        }
+
+        let cached = self.cache.lock().unwrap().get(cid);
+        if let Some(bytes) = cached {
+            trace!("block cache hit");
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(bytes);
+        }
+        if self.check_negative(cid) {
+            self.counters.negative_hits.fetch_add(1, Ordering::Relaxed);
+            bail!("failed to load {}, recently not found", cid);
+        }
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+        match self.loader.load_cid(cid).await {
+            Ok(bytes) => {
+                let evicted =
+                    self.cache
+                        .lock()
+                        .unwrap()
+                        .insert(*cid, bytes.clone(), self.config.max_bytes);
+                self.counters
+                    .evictions
+                    .fetch_add(evicted as u64, Ordering::Relaxed);
+                Ok(bytes)
+            }
+            Err(err) => {
+                self.insert_negative(*cid);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Blocks ordered by their last use.
+#[derive(Debug, Default)]
+struct Lru {
+    /// Block and the tick of its last use.
+    blocks: HashMap<Cid, (Bytes, u64)>,
+    /// Last use -> block, the oldest first.
+    order: BTreeMap<u64, Cid>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl Lru {
+    fn get(&mut self, cid: &Cid) -> Option<Bytes> {
+        let tick = self.next_tick();
+        let (bytes, last_used) = self.blocks.get_mut(cid)?;
+        self.order.remove(last_used);
+        *last_used = tick;
+        self.order.insert(tick, *cid);
+
+        Some(bytes.clone())
+    }
+
+    /// Inserts the block, returning the number of evicted blocks.
+    fn insert(&mut self, cid: Cid, bytes: Bytes, max_bytes: usize) -> usize {
+        if bytes.len() > max_bytes || self.blocks.contains_key(&cid) {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.bytes + bytes.len() > max_bytes {
+            let oldest = match self.order.values().next() {
+                Some(cid) => *cid,
+                None => break,
+            };
+            self.remove(&oldest);
+            evicted += 1;
+        }
+
+        let tick = self.next_tick();
+        self.bytes += bytes.len();
+        self.blocks.insert(cid, (bytes, tick));
+        self.order.insert(tick, cid);
+
+        evicted
+    }
+
+    fn remove(&mut self, cid: &Cid) {
+        if let Some((bytes, last_used)) = self.blocks.remove(cid) {
+            self.order.remove(&last_used);
+            self.bytes -= bytes.len();
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::{Code, MultihashDigest};
+
+    /// Counts the loads that reach the inner loader.
+    #[derive(Debug, Clone, Default)]
+    struct TestLoader {
+        blocks: Arc<HashMap<Cid, Bytes>>,
+        loads: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl ContentLoader for TestLoader {
+        async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            match self.blocks.get(cid) {
+                Some(bytes) => Ok(bytes.clone()),
+                None => bail!("not found: {}", cid),
+            }
+        }
+    }
+
+    fn make_blocks(sizes: &[usize]) -> (TestLoader, Vec<Cid>) {
+        let mut blocks = HashMap::new();
+        let mut cids = Vec::new();
+        for (i, size) in sizes.iter().enumerate() {
+            let bytes = Bytes::from(vec![i as u8; *size]);
+            let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(&bytes));
+            blocks.insert(cid, bytes);
+            cids.push(cid);
+        }
+        let loader = TestLoader {
+            blocks: Arc::new(blocks),
+            loads: Default::default(),
+        };
+        (loader, cids)
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let (inner, cids) = make_blocks(&[40, 40, 40, 200]);
+        let loader = CachingLoader::new(
+            inner.clone(),
+            CacheConfig {
+                max_bytes: 100,
+                negative_ttl: None,
+            },
+        );
+
+        loader.load_cid(&cids[0]).await.unwrap();
+        loader.load_cid(&cids[1]).await.unwrap();
+        // cached
+        loader.load_cid(&cids[0]).await.unwrap();
+        let stats = loader.take_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.bytes, stats.entries), (80, 2));
+
+        // evicts cids[1], the least recently used one
+        loader.load_cid(&cids[2]).await.unwrap();
+        let stats = loader.take_stats();
+        assert_eq!((stats.misses, stats.evictions), (1, 1));
+        assert_eq!(stats.bytes, 80);
+
+        inner.loads.store(0, Ordering::SeqCst);
+        loader.load_cid(&cids[0]).await.unwrap();
+        loader.load_cid(&cids[2]).await.unwrap();
+        assert_eq!(inner.loads.load(Ordering::SeqCst), 0);
+        loader.load_cid(&cids[1]).await.unwrap();
+        assert_eq!(inner.loads.load(Ordering::SeqCst), 1);
+
+        // too large to be cached
+        loader.load_cid(&cids[3]).await.unwrap();
+        loader.load_cid(&cids[3]).await.unwrap();
+        assert_eq!(inner.loads.load(Ordering::SeqCst), 3);
+        assert!(loader.take_stats().bytes <= 100);
+
+        // clones share the cache
+        loader.clear();
+        let clone = loader.clone();
+        clone.load_cid(&cids[0]).await.unwrap();
+        loader.load_cid(&cids[0]).await.unwrap();
+        assert_eq!(loader.take_stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache() {
+        let (inner, _) = make_blocks(&[]);
+        let missing = Cid::new_v1(0x55, Code::Sha2_256.digest(b"missing"));
+
+        // without a negative cache, every load is passed through
+        let loader = CachingLoader::new(inner.clone(), CacheConfig::default());
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert_eq!(inner.loads.swap(0, Ordering::SeqCst), 2);
+
+        let loader = CachingLoader::new(
+            inner.clone(),
+            CacheConfig {
+                negative_ttl: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert_eq!(inner.loads.swap(0, Ordering::SeqCst), 1);
+        assert_eq!(loader.take_stats().negative_hits, 1);
+
+        // expired failures are retried
+        let loader = CachingLoader::new(
+            inner.clone(),
+            CacheConfig {
+                negative_ttl: Some(Duration::ZERO),
+                ..Default::default()
+            },
+        );
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert!(loader.load_cid(&missing).await.is_err());
+        assert_eq!(inner.loads.swap(0, Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let (inner, cids) = make_blocks(&[10]);
+        let loader = CachingLoader::new(inner.clone(), CacheConfig::disabled());
+        loader.load_cid(&cids[0]).await.unwrap();
+        loader.load_cid(&cids[0]).await.unwrap();
+        assert_eq!(inner.loads.load(Ordering::SeqCst), 2);
+        // pass-through loads are still counted, so the miss metrics stay meaningful
+        assert_eq!(
+            loader.take_stats(),
+            CacheStats {
+                misses: 2,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::Cid;
+
+use crate::resolver::ContentLoader;
+
+/// Limits for resolving a path and reading its content.
+///
+/// ```ignore
+/// let budget = ResolveOptions::default()
+///     .timeout(Duration::from_secs(30))
+///     .max_blocks(1024)
+///     .start();
+/// let out = resolver.resolve_with_budget(path, &budget).await?;
+/// let reader = out.pretty(budget.loader(loader));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResolveOptions {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    max_blocks: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+impl ResolveOptions {
+    /// Maximum duration, starting when the budget is started.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fixed point in time, if both a timeout and a deadline are set the earlier one applies.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Maximum number of loaded blocks.
+    pub fn max_blocks(mut self, max_blocks: u64) -> Self {
+        self.max_blocks = Some(max_blocks);
+        self
+    }
+
+    /// Maximum total size of the loaded blocks.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Starts a budget with these limits.
+    pub fn start(&self) -> Budget {
+        let timeout = self.timeout.map(|timeout| Instant::now() + timeout);
+        let deadline = match (timeout, self.deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        Budget {
+            inner: Arc::new(BudgetInner {
+                deadline,
+                max_blocks: self.max_blocks,
+                max_bytes: self.max_bytes,
+                blocks: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+/// The deadline of a [`Budget`] passed, before the content was found.
+///
+/// Gateways should respond with `504 Gateway Timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    /// The block that was being loaded, if any.
+    pub cid: Option<Cid>,
+}
+
+impl Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.cid {
+            Some(cid) => write!(f, "{} not found within deadline", cid),
+            None => write!(f, "not found within deadline"),
+        }
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// More blocks or bytes were loaded than allowed by a [`Budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    Blocks(u64),
+    Bytes(u64),
+}
+
+impl Display for BudgetExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetExceeded::Blocks(max) => write!(f, "more than {} blocks loaded", max),
+            BudgetExceeded::Bytes(max) => write!(f, "more than {} bytes loaded", max),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Running limits, shared by all loaders created from it.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    inner: Arc<BudgetInner>,
+}
+
+#[derive(Debug)]
+struct BudgetInner {
+    deadline: Option<Instant>,
+    max_blocks: Option<u64>,
+    max_bytes: Option<u64>,
+    blocks: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Budget {
+    /// A budget without any limits.
+    pub fn unlimited() -> Self {
+        ResolveOptions::default().start()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Returns the number of blocks and bytes loaded so far.
+    pub fn used(&self) -> (u64, u64) {
+        (
+            self.inner.blocks.load(Ordering::Relaxed),
+            self.inner.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Wraps the loader, so that all loads are limited by this budget.
+    pub fn loader<T: ContentLoader>(&self, loader: T) -> BudgetLoader<T> {
+        BudgetLoader {
+            loader,
+            budget: self.clone(),
+        }
+    }
+
+    /// Runs the future until the deadline, failing with [`DeadlineExceeded`] afterwards.
+    ///
+    /// Errors after the deadline passed are reported as [`DeadlineExceeded`] as well, as they
+    /// are likely caused by it.
+    pub async fn run<F, O>(&self, cid: Option<Cid>, fut: F) -> Result<O>
+    where
+        F: Future<Output = Result<O>>,
+    {
+        let deadline = match self.inner.deadline {
+            Some(deadline) => deadline,
+            None => return fut.await,
+        };
+
+        match tokio::time::timeout_at(deadline.into(), fut).await {
+            Ok(Ok(out)) => Ok(out),
+            Ok(Err(err)) if Instant::now() < deadline || err.is::<DeadlineExceeded>() => Err(err),
+            _ => Err(DeadlineExceeded { cid }.into()),
+        }
+    }
+
+    fn record_load(&self, len: usize) -> Result<()> {
+        let blocks = self.inner.blocks.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes = self.inner.bytes.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        if let Some(max) = self.inner.max_blocks {
+            if blocks > max {
+                return Err(BudgetExceeded::Blocks(max).into());
+            }
+        }
+        if let Some(max) = self.inner.max_bytes {
+            if bytes > max {
+                return Err(BudgetExceeded::Bytes(max).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`ContentLoader`] limited by a [`Budget`], created by [`Budget::loader`].
+///
+/// Loads that are still running at the deadline are dropped.
+#[derive(Debug, Clone)]
+pub struct BudgetLoader<T: ContentLoader> {
+    loader: T,
+    budget: Budget,
+}
+
+impl<T: ContentLoader> BudgetLoader<T> {
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+}
+
+#[async_trait]
+impl<T: ContentLoader> ContentLoader for BudgetLoader<T> {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        let bytes = self
+            .budget
+            .run(Some(*cid), self.loader.load_cid(cid))
+            .await?;
+        self.budget.record_load(bytes.len())?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::resolver::{Path, Resolver};
+    use crate::test_utils::{fixture_file, load_fixture_dag};
+    use tokio::io::AsyncReadExt;
+
+    /// Never finishes loading missing blocks, like a bitswap query without providers.
+    #[derive(Debug, Clone)]
+    struct HangingLoader(Arc<HashMap<Cid, Bytes>>);
+
+    #[async_trait]
+    impl ContentLoader for HangingLoader {
+        async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+            match self.0.get(cid) {
+                Some(bytes) => Ok(bytes.clone()),
+                None => futures::future::pending().await,
+            }
+        }
+    }
+
+    async fn fixture_loader() -> (Cid, HangingLoader) {
+        let root = fixture_file();
+        let blocks = load_fixture_dag(root).await;
+        (root, HangingLoader(Arc::new(blocks)))
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let (root, loader) = fixture_loader().await;
+        let resolver = Resolver::new(loader.clone());
+        let missing = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+            .parse()
+            .unwrap();
+
+        let budget = ResolveOptions::default()
+            .timeout(Duration::from_millis(50))
+            .start();
+        let err = resolver
+            .resolve_with_budget(Path::from_cid(missing), &budget)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DeadlineExceeded>(),
+            Some(&DeadlineExceeded { cid: Some(missing) })
+        );
+
+        // the deadline applies to reading as well
+        let budget = ResolveOptions::default()
+            .deadline(Instant::now() + Duration::from_secs(10))
+            .timeout(Duration::from_millis(50))
+            .start();
+        assert!(budget.deadline().unwrap() < Instant::now() + Duration::from_secs(1));
+        let out = resolver
+            .resolve_with_budget(Path::from_cid(root), &budget)
+            .await
+            .unwrap();
+        let loader = budget.loader(HangingLoader(Default::default()));
+        let err = out
+            .pretty(loader)
+            .read_to_end(&mut Vec::new())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("not found within deadline"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_limits() {
+        let (root, loader) = fixture_loader().await;
+        let resolver = Resolver::new(loader.clone());
+
+        let budget = ResolveOptions::default().max_blocks(32).start();
+        let out = resolver
+            .resolve_with_budget(Path::from_cid(root), &budget)
+            .await
+            .unwrap();
+        let mut content = Vec::new();
+        out.pretty(budget.loader(loader.clone()))
+            .read_to_end(&mut content)
+            .await
+            .unwrap();
+        assert_eq!(content.len(), 1000);
+        // root, 4 stems and 16 leaves
+        assert!(budget.used().0 >= 21);
+        assert!(budget.used().1 > 1000);
+
+        let budget = ResolveOptions::default().max_blocks(10).start();
+        let out = resolver
+            .resolve_with_budget(Path::from_cid(root), &budget)
+            .await
+            .unwrap();
+        let err = out
+            .pretty(budget.loader(loader.clone()))
+            .read_to_end(&mut Vec::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("more than 10 blocks"), "{}", err);
+
+        let budget = ResolveOptions::default().max_bytes(10).start();
+        let err = resolver
+            .resolve_with_budget(Path::from_cid(root), &budget)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<BudgetExceeded>(),
+            Some(&BudgetExceeded::Bytes(10))
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::{io, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+mod fastcdc;
+mod rabin;
+
+pub use self::fastcdc::FastCdc;
+pub use self::rabin::Rabin;
+
+/// Chunk size used by go-ipfs by default.
+pub const DEFAULT_CHUNK_SIZE_LIMIT: usize = 1024 * 256;
+
+/// Strategy used to split content into chunks.
+///
+/// All chunkers are deterministic, the same content and parameters always result in
+/// the same chunks, independent of how the content is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunker {
+    Fixed(Fixed),
+    /// Content defined chunking, using a Rabin fingerprint.
+    Rabin(Rabin),
+    /// Content defined chunking, using FastCDC.
+    FastCdc(FastCdc),
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Chunker::Fixed(Fixed::default())
+    }
+}
+
+impl Chunker {
+    /// Returns a stream of chunks read from `source`. Empty content yields no chunks.
+    pub fn chunks<'a, R: AsyncRead + Unpin + Send + 'a>(
+        self,
+        source: R,
+    ) -> BoxStream<'a, io::Result<Bytes>> {
+        match self {
+            Chunker::Fixed(chunker) => chunker.chunks(source).boxed(),
+            Chunker::Rabin(chunker) => chunker.chunks(source).boxed(),
+            Chunker::FastCdc(chunker) => chunker.chunks(source).boxed(),
+        }
+    }
+}
+
+/// Parses chunker strings in the style of go-ipfs:
+///
+/// - `size-<chunk_size>`
+/// - `rabin`, `rabin-<avg>` or `rabin-<min>-<avg>-<max>`
+/// - `fastcdc`, `fastcdc-<avg>` or `fastcdc-<min>-<avg>-<max>`
+///
+/// The sizes of `rabin` are derived from the average like go-ipfs does, but the Rabin
+/// fingerprint is not the one of go-ipfs, so the chunk boundaries are different.
+/// `fastcdc` is not supported by go-ipfs.
+impl FromStr for Chunker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('-');
+        let name = parts.next().unwrap_or_default();
+        let sizes = parts
+            .map(|p| {
+                p.parse::<usize>()
+                    .with_context(|| format!("invalid size {:?}", p))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match (name, &sizes[..]) {
+            ("size", [chunk_size]) => {
+                ensure!(*chunk_size > 0, "chunk size must be larger than 0");
+                Ok(Chunker::Fixed(Fixed::new(*chunk_size)))
+            }
+            ("rabin", []) => Ok(Chunker::Rabin(Rabin::default())),
+            ("rabin", [avg]) => Ok(Chunker::Rabin(Rabin::with_avg_size(*avg)?)),
+            ("rabin", [min, avg, max]) => Ok(Chunker::Rabin(Rabin::new(*min, *avg, *max)?)),
+            ("fastcdc", []) => Ok(Chunker::FastCdc(FastCdc::default())),
+            ("fastcdc", [avg]) => Ok(Chunker::FastCdc(FastCdc::with_avg_size(*avg)?)),
+            ("fastcdc", [min, avg, max]) => Ok(Chunker::FastCdc(FastCdc::new(*min, *avg, *max)?)),
+            _ => bail!("invalid chunker {:?}", s),
+        }
+    }
+}
+
+/// Splits the content into chunks of a fixed size.
+///
+/// All chunks but the last one are exactly `chunk_size` bytes long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed {
+    pub chunk_size: usize,
+}
+
+impl Default for Fixed {
+    fn default() -> Self {
+        Fixed {
+            chunk_size: DEFAULT_CHUNK_SIZE_LIMIT,
+        }
+    }
+}
+
+impl Fixed {
+    pub fn new(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+
+        Fixed { chunk_size }
+    }
+
+    /// Returns a stream of chunks read from `source`. Empty content yields no chunks.
+    pub fn chunks<'a, R: AsyncRead + Unpin + Send + 'a>(
+        self,
+        source: R,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'a {
+        let chunk_size = self.chunk_size;
+
+        stream::try_unfold(source, move |mut source| async move {
+            let mut buf = vec![0u8; chunk_size];
+            let mut filled = 0;
+            // Fill the chunk completely, short reads must not change the chunk boundaries.
+            while filled < chunk_size {
+                let read = source.read(&mut buf[filled..]).await?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+
+            if filled == 0 {
+                return Ok(None);
+            }
+            buf.truncate(filled);
+
+            Ok(Some((Bytes::from(buf), source)))
+        })
+    }
+}
+
+/// Validates the sizes used by content defined chunkers.
+fn check_sizes(min_size: usize, avg_size: usize, max_size: usize) -> Result<()> {
+    ensure!(
+        min_size > 0 && min_size < avg_size && avg_size < max_size,
+        "invalid sizes: min {}, avg {}, max {}",
+        min_size,
+        avg_size,
+        max_size
+    );
+    Ok(())
+}
+
+/// Drives a content defined chunker.
+///
+/// `cut` is called with the upcoming data, which is always `max_size` bytes long unless
+/// the end of the content was reached, and returns the length of the next chunk.
+fn content_defined_chunks<'a, R, F>(
+    source: R,
+    max_size: usize,
+    cut: F,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'a
+where
+    R: AsyncRead + Unpin + Send + 'a,
+    F: Fn(&[u8]) -> usize + Send + Sync + 'a,
+{
+    stream::try_unfold(
+        (source, BytesMut::new(), false, cut),
+        move |(mut source, mut buf, mut eof, cut)| async move {
+            while !eof && buf.len() < max_size {
+                buf.reserve(max_size - buf.len());
+                if source.read_buf(&mut buf).await? == 0 {
+                    eof = true;
+                }
+            }
+
+            if buf.is_empty() {
+                return Ok(None);
+            }
+
+            let len = cut(&buf[..std::cmp::min(buf.len(), max_size)]);
+            debug_assert!(len > 0 && len <= max_size);
+            let chunk = buf.split_to(len).freeze();
+
+            Ok(Some((chunk, (source, buf, eof, cut))))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use super::*;
+    use futures::TryStreamExt;
+    use tokio::io::ReadBuf;
+
+    /// Returns the content in small reads of varying size.
+    struct ShortReader {
+        content: Bytes,
+        reads: usize,
+    }
+
+    impl AsyncRead for ShortReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.reads += 1;
+            let len = std::cmp::min(self.reads % 13 + 1, self.content.len());
+            let len = std::cmp::min(len, buf.remaining());
+            let data = self.content.split_to(len);
+            buf.put_slice(&data);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Pseudo random content, so content defined chunkers find boundaries.
+    fn random_content(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                // xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    async fn chunk(chunker: Chunker, content: &[u8]) -> Vec<Bytes> {
+        chunker
+            .chunks(std::io::Cursor::new(content.to_vec()))
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fixed_chunker() {
+        let content: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+
+        let chunks: Vec<_> = Fixed::new(300)
+            .chunks(std::io::Cursor::new(content.clone()))
+            .try_collect()
+            .await
+            .unwrap();
+        let sizes: Vec<_> = chunks.iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![300, 300, 300, 100]);
+        assert_eq!(chunks.concat(), content);
+
+        let chunks: Vec<_> = Fixed::new(250)
+            .chunks(std::io::Cursor::new(content.clone()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.len() == 250));
+
+        let chunks: Vec<_> = Fixed::default()
+            .chunks(std::io::Cursor::new(Vec::new()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_content_defined_chunkers() {
+        let content = random_content(512 * 1024, 0xdead_beef);
+        // the same content, with a few bytes inserted at the start
+        let mut modified = b"inserted".to_vec();
+        modified.extend_from_slice(&content);
+
+        for chunker in [
+            Chunker::Rabin(Rabin::new(1024, 4096, 16 * 1024).unwrap()),
+            Chunker::FastCdc(FastCdc::new(1024, 4096, 16 * 1024).unwrap()),
+        ] {
+            let chunks = chunk(chunker, &content).await;
+            assert_eq!(chunks.concat(), content);
+            let (last, rest) = chunks.split_last().unwrap();
+            assert!(!last.is_empty());
+            assert!(
+                rest.iter().all(|c| c.len() >= 1024 && c.len() <= 16 * 1024),
+                "{:?}",
+                chunker
+            );
+            // sizes vary
+            assert!(chunks.iter().any(|c| c.len() != chunks[0].len()));
+
+            // deterministic, independent of the size of the reads
+            assert_eq!(chunk(chunker, &content).await, chunks);
+            let short_reads: Vec<_> = chunker
+                .chunks(ShortReader {
+                    content: Bytes::from(content.clone()),
+                    reads: 0,
+                })
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(short_reads, chunks);
+
+            // boundaries resynchronize after the insertion
+            let modified_chunks = chunk(chunker, &modified).await;
+            let known: HashSet<_> = chunks.iter().collect();
+            let reused = modified_chunks.iter().filter(|c| known.contains(c)).count();
+            assert!(
+                reused * 10 >= chunks.len() * 9,
+                "{:?}: reused only {} of {}",
+                chunker,
+                reused,
+                chunks.len()
+            );
+        }
+
+        // fixed size chunks do not survive the insertion
+        let chunker = Chunker::Fixed(Fixed::new(4096));
+        let chunks = chunk(chunker, &content).await;
+        let known: HashSet<_> = chunks.iter().collect();
+        let modified_chunks = chunk(chunker, &modified).await;
+        assert!(modified_chunks.iter().all(|c| !known.contains(c)));
+    }
+
+    #[test]
+    fn test_parse_chunker() {
+        assert_eq!(
+            "size-1024".parse::<Chunker>().unwrap(),
+            Chunker::Fixed(Fixed::new(1024))
+        );
+        assert_eq!(
+            "rabin".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::default())
+        );
+        assert_eq!(
+            "rabin-4096".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::new(1365, 4096, 6144).unwrap())
+        );
+        assert_eq!(
+            "rabin-1000".parse::<Chunker>().unwrap(),
+            Chunker::Rabin(Rabin::new(333, 1000, 1500).unwrap())
+        );
+        assert_eq!(
+            "fastcdc-512-2048-8192".parse::<Chunker>().unwrap(),
+            Chunker::FastCdc(FastCdc::new(512, 2048, 8192).unwrap())
+        );
+
+        assert!("size-0".parse::<Chunker>().is_err());
+        assert!("size".parse::<Chunker>().is_err());
+        assert!("rabin-2".parse::<Chunker>().is_err());
+        assert!("fastcdc-1000".parse::<Chunker>().is_err());
+        assert!("fastcdc-4096-1024-8192".parse::<Chunker>().is_err());
+        assert!("buzhash".parse::<Chunker>().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Content defined chunking using FastCDC, as described in
+//! "FastCDC: a Fast and Efficient Content-Defined Chunking Approach for Data Deduplication".
+//!
+//! Uses a gear hash and normalized chunking: below the average size a stricter mask is
+//! used to find a boundary, above it a looser one, which keeps the chunk sizes close to
+//! the average.
+
+use std::io;
+
+use anyhow::{ensure, Result};
+use bytes::Bytes;
+use futures::Stream;
+use tokio::io::AsyncRead;
+
+use super::{check_sizes, content_defined_chunks, DEFAULT_CHUNK_SIZE_LIMIT};
+
+/// Random values for each byte, generated from a fixed seed so chunking is stable.
+const GEAR: [u64; 256] = gear_table(0x6972_6f68_6663_6463);
+
+/// Number of bits the normalized masks differ from the average.
+const NORMALIZATION_LEVEL: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastCdc {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl Default for FastCdc {
+    fn default() -> Self {
+        Self::with_avg_size(DEFAULT_CHUNK_SIZE_LIMIT).expect("valid default")
+    }
+}
+
+impl FastCdc {
+    /// `avg_size` must be a power of two and `min_size < avg_size < max_size`.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Result<Self> {
+        ensure!(
+            avg_size.is_power_of_two(),
+            "average size must be a power of two, got {}",
+            avg_size
+        );
+        check_sizes(min_size, avg_size, max_size)?;
+
+        Ok(FastCdc {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+
+    /// Uses a quarter of `avg_size` as the minimum and four times `avg_size` as the maximum.
+    pub fn with_avg_size(avg_size: usize) -> Result<Self> {
+        Self::new(avg_size / 4, avg_size, avg_size.saturating_mul(4))
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns a stream of chunks read from `source`. Empty content yields no chunks.
+    pub fn chunks<'a, R: AsyncRead + Unpin + Send + 'a>(
+        self,
+        source: R,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'a {
+        content_defined_chunks(source, self.max_size, move |data| self.cut(data))
+    }
+
+    /// Finds the length of the next chunk.
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let bits = self.avg_size.trailing_zeros();
+        let mask_small = mask(bits + NORMALIZATION_LEVEL);
+        let mask_large = mask(bits.saturating_sub(NORMALIZATION_LEVEL));
+
+        let end = std::cmp::min(data.len(), self.max_size);
+        let center = std::cmp::min(self.avg_size, end);
+
+        let mut hash = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < center { mask_small } else { mask_large };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+}
+
+/// Mask with `bits` ones in the most significant bits, as those depend on the most bytes.
+fn mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits if bits >= 64 => u64::MAX,
+        bits => u64::MAX << (64 - bits),
+    }
+}
+
+const fn gear_table(seed: u64) -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = seed;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask(0), 0);
+        assert_eq!(mask(1), 0x8000_0000_0000_0000);
+        assert_eq!(mask(12), 0xFFF0_0000_0000_0000);
+        assert_eq!(mask(64), u64::MAX);
+    }
+
+    #[test]
+    fn test_cut_sizes() {
+        let chunker = FastCdc::new(64, 256, 1024).unwrap();
+        assert_eq!(chunker.cut(&[1u8; 10]), 10);
+
+        let data: Vec<u8> = (0..1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let len = chunker.cut(&data);
+        assert!((64..=1024).contains(&len));
+        assert_eq!(chunker.cut(&data), len);
+    }
+}
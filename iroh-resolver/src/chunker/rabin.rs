@@ -0,0 +1,174 @@
+//! Content defined chunking using a rolling Rabin fingerprint, as described in
+//! "A Low-bandwidth Network File System" (LBFS).
+//!
+//! The fingerprint is computed over a sliding window of the last [`WINDOW_SIZE`] bytes,
+//! a chunk ends where the lowest bits of the fingerprint are all zero.
+
+use std::{io, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::Stream;
+use tokio::io::AsyncRead;
+
+use super::{check_sizes, content_defined_chunks, DEFAULT_CHUNK_SIZE_LIMIT};
+
+/// Irreducible polynomial of degree 53, used for the fingerprint.
+const POLYNOMIAL: u64 = 0x3DA3358B4DC173;
+
+/// Number of bytes the fingerprint is calculated over.
+const WINDOW_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rabin {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl Default for Rabin {
+    fn default() -> Self {
+        Self::with_avg_size(DEFAULT_CHUNK_SIZE_LIMIT).expect("valid default")
+    }
+}
+
+impl Rabin {
+    /// Requires `min_size < avg_size < max_size`. Boundaries are found with the largest
+    /// power of two not above `avg_size`, like go-ipfs does.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Result<Self> {
+        check_sizes(min_size, avg_size, max_size)?;
+
+        Ok(Rabin {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+
+    /// Uses a third of `avg_size` as the minimum and one and a half times `avg_size` as the
+    /// maximum, the same as go-ipfs.
+    pub fn with_avg_size(avg_size: usize) -> Result<Self> {
+        Self::new(
+            avg_size / 3,
+            avg_size,
+            avg_size.saturating_add(avg_size / 2),
+        )
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns a stream of chunks read from `source`. Empty content yields no chunks.
+    pub fn chunks<'a, R: AsyncRead + Unpin + Send + 'a>(
+        self,
+        source: R,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'a {
+        let tables = Arc::new(Tables::new());
+        content_defined_chunks(source, self.max_size, move |data| self.cut(&tables, data))
+    }
+
+    /// Finds the length of the next chunk.
+    fn cut(&self, tables: &Tables, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let end = std::cmp::min(data.len(), self.max_size);
+        // largest power of two not above the average
+        let mask = (1u64 << (usize::BITS - 1 - self.avg_size.leading_zeros())) - 1;
+        let shift = degree(POLYNOMIAL) - 8;
+
+        let mut window = [0u8; WINDOW_SIZE];
+        let mut window_pos = 0;
+        let mut digest = 0u64;
+
+        // Only the window before the minimum size is relevant for the first boundary.
+        let start = self.min_size.saturating_sub(WINDOW_SIZE);
+        for (i, byte) in data[start..end].iter().enumerate() {
+            // slide the window
+            let out = window[window_pos];
+            window[window_pos] = *byte;
+            window_pos = (window_pos + 1) % WINDOW_SIZE;
+            digest ^= tables.out[out as usize];
+
+            // append the new byte
+            let index = (digest >> shift) as usize;
+            digest = ((digest << 8) | *byte as u64) ^ tables.reduce[index];
+
+            let len = start + i + 1;
+            if len >= self.min_size && digest & mask == 0 {
+                return len;
+            }
+        }
+
+        end
+    }
+}
+
+/// Precomputed tables to update the fingerprint a byte at a time.
+struct Tables {
+    /// Contribution of a byte leaving the window.
+    out: [u64; 256],
+    /// Reduction of the top byte, shifted out when appending a byte.
+    reduce: [u64; 256],
+}
+
+impl Tables {
+    fn new() -> Self {
+        let deg = degree(POLYNOMIAL);
+        let mut out = [0u64; 256];
+        let mut reduce = [0u64; 256];
+
+        for b in 0..256u64 {
+            let mut hash = append_byte(0, b);
+            for _ in 0..WINDOW_SIZE - 1 {
+                hash = append_byte(hash, 0);
+            }
+            out[b as usize] = hash;
+            reduce[b as usize] = modulo(b << deg, POLYNOMIAL) | (b << deg);
+        }
+
+        Tables { out, reduce }
+    }
+}
+
+fn append_byte(hash: u64, b: u64) -> u64 {
+    modulo((hash << 8) | b, POLYNOMIAL)
+}
+
+/// Degree of the polynomial `x`, `-1` for zero.
+fn degree(x: u64) -> i32 {
+    63 - x.leading_zeros() as i32
+}
+
+/// Remainder of the polynomial division `x / p` over GF(2).
+fn modulo(mut x: u64, p: u64) -> u64 {
+    let deg_p = degree(p);
+    while degree(x) >= deg_p {
+        x ^= p << (degree(x) - deg_p);
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modulo() {
+        assert_eq!(modulo(0b1011, 0b11), 1);
+        assert_eq!(modulo(POLYNOMIAL, POLYNOMIAL), 0);
+        assert_eq!(modulo(POLYNOMIAL ^ 0b101, POLYNOMIAL), 0b101);
+        assert_eq!(degree(POLYNOMIAL), 53);
+        assert_eq!(degree(0), -1);
+    }
+}
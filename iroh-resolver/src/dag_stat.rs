@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+use anyhow::Result;
+use bytes::Bytes;
+use cid::Cid;
+use futures::{StreamExt, TryStreamExt};
+use iroh_rpc_client::Client;
+use tracing::debug;
+
+use crate::resolver::{identity_content, parse_links, ContentLoader};
+use crate::unixfs::UnixfsNode;
+
+/// Number of blocks that are loaded concurrently.
+const CONCURRENCY: usize = 16;
+
+/// Statistics about all blocks reachable from a root, like `ipfs dag stat`.
+///
+/// Blocks using the identity hash are inlined in their CIDs and not counted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DagStat {
+    /// Number of unique blocks that were found.
+    pub blocks: u64,
+    /// Total size of all unique blocks, `None` if only the links were walked.
+    pub total_size: Option<u64>,
+    /// Size of the root block plus the sizes of its links, as recorded in the root.
+    ///
+    /// Only available for dag-pb roots with sizes on all their links. This is what
+    /// `ipfs files stat` reports as `CumulativeSize`, blocks that are linked multiple
+    /// times are counted multiple times.
+    pub cumulative_size: Option<u64>,
+    /// Length of the longest chain of links, the root has depth `0`.
+    pub max_depth: usize,
+    /// Blocks that could not be found, the blocks below them are not part of the stats.
+    pub missing: Vec<Cid>,
+}
+
+impl DagStat {
+    /// Returns `true` if all blocks of the DAG were found.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// A visited block.
+struct Node {
+    links: Vec<Cid>,
+    size: Option<u64>,
+}
+
+/// Loads all blocks reachable from `root` through the loader.
+///
+/// Failing loads are recorded as missing, blocks that can not be decoded fail the walk.
+pub async fn dag_stat<T: ContentLoader>(loader: &T, root: Cid) -> Result<DagStat> {
+    let mut stat = walk(root, |cid| async move {
+        match loader.load_cid(&cid).await {
+            Ok(bytes) => Ok(Some(Node {
+                links: parse_links(&cid, &bytes)?,
+                size: Some(bytes.len() as u64),
+            })),
+            Err(err) => {
+                debug!("missing block {}: {:?}", cid, err);
+                Ok(None)
+            }
+        }
+    })
+    .await?;
+    if stat.missing.first() != Some(&root) {
+        if let Ok(bytes) = loader.load_cid(&root).await {
+            stat.cumulative_size = cumulative_size(&root, bytes);
+        }
+    }
+
+    Ok(stat)
+}
+
+/// Walks the DAG below `root` using only the links recorded in the store, without loading
+/// the blocks, apart from the root.
+///
+/// The total size is not available this way.
+pub async fn dag_stat_local(client: &Client, root: Cid) -> Result<DagStat> {
+    let store = &client.store;
+    let mut stat = walk(root, |cid| async move {
+        let links = match store.get_links(cid).await? {
+            Some(links) => links,
+            // no links are stored for leaves
+            None if store.has(cid).await? => Vec::new(),
+            None => return Ok(None),
+        };
+        Ok(Some(Node { links, size: None }))
+    })
+    .await?;
+    if let Some(bytes) = store.get(root).await? {
+        stat.cumulative_size = cumulative_size(&root, bytes);
+    }
+
+    Ok(stat)
+}
+
+/// Visits the DAG level by level, each block only once.
+async fn walk<F, Fut>(root: Cid, visit: F) -> Result<DagStat>
+where
+    F: Fn(Cid) -> Fut,
+    Fut: Future<Output = Result<Option<Node>>>,
+{
+    let mut stat = DagStat {
+        total_size: Some(0),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    let mut children: HashMap<Cid, Vec<Cid>> = HashMap::new();
+    let mut level = vec![root];
+    seen.insert(root);
+
+    while !level.is_empty() {
+        let nodes: Vec<_> = futures::stream::iter(level.iter().map(|cid| {
+            let fut = visit(*cid);
+            async move { fut.await.map(|node| (*cid, node)) }
+        }))
+        .buffered(CONCURRENCY)
+        .try_collect()
+        .await?;
+
+        let mut next = Vec::new();
+        for (cid, node) in nodes {
+            let node = match node {
+                Some(node) => node,
+                None => {
+                    stat.missing.push(cid);
+                    continue;
+                }
+            };
+            stat.blocks += 1;
+            stat.total_size = match (stat.total_size, node.size) {
+                (Some(total), Some(size)) => Some(total + size),
+                _ => None,
+            };
+            let links: Vec<Cid> = node
+                .links
+                .into_iter()
+                .filter(|link| identity_content(link).is_none())
+                .collect();
+            for link in &links {
+                if seen.insert(*link) {
+                    next.push(*link);
+                }
+            }
+            children.insert(cid, links);
+        }
+        level = next;
+    }
+
+    stat.max_depth = max_depth(root, &children);
+    Ok(stat)
+}
+
+/// Length of the longest chain of links from the root.
+fn max_depth(root: Cid, children: &HashMap<Cid, Vec<Cid>>) -> usize {
+    // heights of the subtrees, computed bottom up, as DAGs can be too deep to recurse
+    let mut heights: HashMap<Cid, usize> = HashMap::new();
+    let mut stack = vec![(root, false)];
+    while let Some((cid, expanded)) = stack.pop() {
+        if heights.contains_key(&cid) {
+            continue;
+        }
+        let links = children.get(&cid).map(|l| &l[..]).unwrap_or_default();
+        if expanded {
+            // missing blocks are not counted
+            let height = links
+                .iter()
+                .filter(|link| children.contains_key(link))
+                .map(|link| heights[link] + 1)
+                .max()
+                .unwrap_or(0);
+            heights.insert(cid, height);
+        } else {
+            stack.push((cid, true));
+            stack.extend(links.iter().map(|link| (*link, false)));
+        }
+    }
+
+    heights.get(&root).copied().unwrap_or(0)
+}
+
+fn cumulative_size(cid: &Cid, bytes: Bytes) -> Option<u64> {
+    let len = bytes.len() as u64;
+    match UnixfsNode::decode(cid, bytes).ok()? {
+        UnixfsNode::Raw { .. } => Some(len),
+        node @ UnixfsNode::Pb { .. } => node
+            .links()
+            .map(|link| link.ok().and_then(|link| link.tsize))
+            .sum::<Option<u64>>()
+            .map(|links| len + links),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Resolver;
+    use crate::test_utils::{fixture_file, load_fixture_dag};
+
+    #[tokio::test]
+    async fn test_dag_stat() {
+        let root = fixture_file();
+        let mut blocks = load_fixture_dag(root).await;
+        let total_size: u64 = blocks.values().map(|b| b.len() as u64).sum();
+
+        let resolver = Resolver::new(blocks.clone());
+        let stat = resolver.dag_stat(root).await.unwrap();
+        assert_eq!(stat.blocks, 21);
+        assert_eq!(stat.total_size, Some(total_size));
+        // every block is linked once, so the cumulative size matches the unique blocks
+        assert_eq!(stat.cumulative_size, Some(total_size));
+        assert_eq!(stat.max_depth, 2);
+        assert!(stat.is_complete());
+
+        // the blocks below a missing stem are not reached
+        let stem = parse_links(&root, &blocks[&root]).unwrap()[1];
+        let stem_len = blocks.remove(&stem).unwrap().len() as u64;
+        let stat = dag_stat(&blocks, root).await.unwrap();
+        assert_eq!(stat.missing, vec![stem]);
+        assert_eq!(stat.blocks, 16);
+        assert!(stat.total_size.unwrap() < total_size - stem_len);
+        assert_eq!(stat.cumulative_size, Some(total_size));
+        assert_eq!(stat.max_depth, 2);
+
+        let stat = dag_stat(&HashMap::<Cid, Bytes>::new(), root).await.unwrap();
+        assert_eq!(stat.missing, vec![root]);
+        assert_eq!(stat.blocks, 0);
+        assert_eq!(stat.cumulative_size, None);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let cids: Vec<Cid> = (0..4)
+            .map(|i| {
+                use cid::multihash::{Code, MultihashDigest};
+                Cid::new_v1(0x55, Code::Sha2_256.digest(&[i]))
+            })
+            .collect();
+        // 0 -> 1 -> 2 -> 3 and a shortcut 0 -> 3
+        let children: HashMap<Cid, Vec<Cid>> = [
+            (cids[0], vec![cids[3], cids[1]]),
+            (cids[1], vec![cids[2]]),
+            (cids[2], vec![cids[3]]),
+            (cids[3], vec![]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(max_depth(cids[0], &children), 3);
+        assert_eq!(max_depth(cids[2], &children), 1);
+        assert_eq!(max_depth(cids[3], &children), 0);
+    }
+}
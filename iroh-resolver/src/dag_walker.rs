@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::Result;
+use bytes::Bytes;
+use cid::Cid;
+use futures::Stream;
+
+use crate::codecs::Codec;
+use crate::resolver::{parse_links, ContentLoader};
+
+/// Order in which the blocks of a DAG are visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Pre-order depth-first traversal, following links in the order they appear in a
+    /// block. This is the canonical block order of CAR files.
+    DepthFirst,
+    /// Visits all blocks of a level, before descending to the next one.
+    BreadthFirst,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::DepthFirst
+    }
+}
+
+/// Walks all blocks reachable from a root.
+///
+/// ```ignore
+/// let blocks = DagWalker::new(root).order(Order::BreadthFirst).max_depth(2).walk(loader);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DagWalker {
+    root: Cid,
+    order: Order,
+    max_depth: Option<usize>,
+    dedupe: bool,
+    skip_raw_leaves: bool,
+}
+
+impl DagWalker {
+    pub fn new(root: Cid) -> Self {
+        DagWalker {
+            root,
+            order: Order::default(),
+            max_depth: None,
+            dedupe: true,
+            skip_raw_leaves: false,
+        }
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only visits blocks up to the given depth, the root has depth `0`.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Whether blocks that are linked multiple times are only visited once. Defaults to `true`.
+    ///
+    /// With a maximum depth, a block that is reached again at a shallower depth is still
+    /// expanded again, so that its links within the depth limit are visited.
+    pub fn dedupe(mut self, dedupe: bool) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// If set, blocks with the `raw` codec are neither loaded nor yielded.
+    ///
+    /// Raw blocks can not contain links, so this walks the structure of a DAG, e.g. the
+    /// stems of a UnixFS file, without loading its content.
+    pub fn skip_raw_leaves(mut self, skip: bool) -> Self {
+        self.skip_raw_leaves = skip;
+        self
+    }
+
+    /// Returns a stream of all visited blocks, starting with the root.
+    pub fn walk<T: ContentLoader + 'static>(
+        self,
+        loader: T,
+    ) -> impl Stream<Item = Result<(Cid, Bytes)>> + Send {
+        async_stream::try_stream! {
+            // the shallowest depth each block was visited at
+            let mut seen = HashMap::new();
+            // blocks to visit, together with their depth
+            let mut queue = VecDeque::new();
+            if !self.skips(&self.root) {
+                queue.push_back((self.root, 0));
+            }
+
+            while let Some((cid, depth)) = queue.pop_front() {
+                let mut first_visit = true;
+                if self.dedupe {
+                    match seen.get(&cid) {
+                        Some(&prev) if self.max_depth.is_none() || prev <= depth => continue,
+                        // the depth limit may have cut its links before, only expand it
+                        Some(_) => first_visit = false,
+                        None => {}
+                    }
+                    seen.insert(cid, depth);
+                }
+
+                let bytes = loader.load_cid(&cid).await?;
+                if self.max_depth.map(|max| depth < max).unwrap_or(true) {
+                    let links = parse_links(&cid, &bytes)?
+                        .into_iter()
+                        .filter(|link| !self.skips(link));
+                    match self.order {
+                        Order::DepthFirst => {
+                            for link in links.collect::<Vec<_>>().into_iter().rev() {
+                                queue.push_front((link, depth + 1));
+                            }
+                        }
+                        Order::BreadthFirst => {
+                            queue.extend(links.map(|link| (link, depth + 1)));
+                        }
+                    }
+                }
+
+                if first_visit {
+                    yield (cid, bytes);
+                }
+            }
+        }
+    }
+
+    fn skips(&self, cid: &Cid) -> bool {
+        self.skip_raw_leaves && cid.codec() == Codec::Raw as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_utils::{fixture_file, load_fixture_dag};
+    use cid::multihash::{Code, MultihashDigest};
+    use futures::TryStreamExt;
+    use libipld::{codec::Encode, Ipld, IpldCodec};
+
+    async fn walk(walker: DagWalker, loader: Arc<HashMap<Cid, Bytes>>) -> Vec<Cid> {
+        walker
+            .walk(loader)
+            .map_ok(|(cid, _)| cid)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_walk_unixfs_file() {
+        let root = fixture_file();
+        let loader = Arc::new(load_fixture_dag(root).await);
+        let stems = parse_links(&root, &loader[&root]).unwrap();
+        assert_eq!(stems.len(), 4);
+        let leaves: Vec<_> = stems
+            .iter()
+            .map(|stem| parse_links(stem, &loader[stem]).unwrap())
+            .collect();
+
+        let mut depth_first = vec![root];
+        for (stem, leaves) in stems.iter().zip(&leaves) {
+            depth_first.push(*stem);
+            depth_first.extend(leaves);
+        }
+        assert_eq!(
+            walk(DagWalker::new(root), loader.clone()).await,
+            depth_first
+        );
+
+        let mut breadth_first = vec![root];
+        breadth_first.extend(&stems);
+        breadth_first.extend(leaves.concat());
+        assert_eq!(
+            walk(
+                DagWalker::new(root).order(Order::BreadthFirst),
+                loader.clone()
+            )
+            .await,
+            breadth_first
+        );
+
+        let mut structure = vec![root];
+        structure.extend(&stems);
+        for walker in [
+            DagWalker::new(root).max_depth(1).order(Order::BreadthFirst),
+            DagWalker::new(root)
+                .skip_raw_leaves(true)
+                .order(Order::BreadthFirst),
+        ] {
+            assert_eq!(walk(walker, loader.clone()).await, structure);
+        }
+        assert_eq!(
+            walk(DagWalker::new(root).max_depth(0), loader.clone()).await,
+            vec![root]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_walk_dedupe() {
+        let mut blocks = HashMap::new();
+        let mut insert = |ipld: Ipld| {
+            let mut bytes = Vec::new();
+            ipld.encode(IpldCodec::DagCbor, &mut bytes).unwrap();
+            let cid = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+            blocks.insert(cid, Bytes::from(bytes));
+            cid
+        };
+        let leaf = insert(Ipld::String("leaf".into()));
+        let a = insert(Ipld::List(vec![Ipld::Link(leaf)]));
+        let root = insert(Ipld::List(vec![
+            Ipld::Link(a),
+            Ipld::Link(a),
+            Ipld::Link(leaf),
+        ]));
+        let d = insert(Ipld::List(vec![Ipld::Link(leaf), Ipld::Null]));
+        let x = insert(Ipld::List(vec![Ipld::Link(d)]));
+        let diamond = insert(Ipld::List(vec![Ipld::Link(x), Ipld::Link(d)]));
+        let loader = Arc::new(blocks);
+
+        assert_eq!(
+            walk(DagWalker::new(root), loader.clone()).await,
+            vec![root, a, leaf]
+        );
+        assert_eq!(
+            walk(DagWalker::new(root).dedupe(false), loader.clone()).await,
+            vec![root, a, leaf, a, leaf, leaf]
+        );
+        assert_eq!(
+            walk(
+                DagWalker::new(root)
+                    .dedupe(false)
+                    .order(Order::BreadthFirst),
+                loader.clone()
+            )
+            .await,
+            vec![root, a, a, leaf, leaf, leaf]
+        );
+
+        // a block first reached at the depth limit is expanded when reached at a shallower
+        // depth later on
+        for order in [Order::DepthFirst, Order::BreadthFirst] {
+            let walker = DagWalker::new(diamond).order(order);
+            assert_eq!(
+                walk(walker.clone().max_depth(2), loader.clone()).await,
+                vec![diamond, x, d, leaf],
+                "{:?}",
+                order
+            );
+            assert_eq!(
+                walk(walker.max_depth(1), loader.clone()).await,
+                vec![diamond, x, d],
+                "{:?}",
+                order
+            );
+        }
+
+        // missing blocks are an error
+        let loader = Arc::new(HashMap::new());
+        assert!(DagWalker::new(root)
+            .walk(loader)
+            .try_collect::<Vec<_>>()
+            .await
+            .is_err());
+    }
+}
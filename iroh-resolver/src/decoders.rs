@@ -0,0 +1,420 @@
+//! Decoders into the IPLD data model, for codecs not supported by `libipld`.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use cid::multihash::Multihash;
+use cid::Cid;
+use libipld::prelude::Codec as _;
+use libipld::{Ipld, IpldCodec};
+use prost::Message;
+
+use crate::codecs::Codec;
+
+/// Decodes a dag-jose block.
+///
+/// JWS blocks get an additional `link` field, decoded from their payload, as defined by
+/// the dag-jose spec. JWE blocks are returned as is, they contain no links.
+pub fn decode_dag_jose(bytes: &[u8]) -> Result<Ipld> {
+    let mut ipld: Ipld = IpldCodec::DagCbor
+        .decode(bytes)
+        .map_err(|e| anyhow!("invalid dag jose: {:?}", e))?;
+    let map = match ipld {
+        Ipld::Map(ref mut map) => map,
+        _ => bail!("invalid dag jose: expected a map"),
+    };
+    if let Some(payload) = map.get("payload") {
+        let payload = match payload {
+            Ipld::Bytes(payload) => payload,
+            _ => bail!("invalid dag jose: payload must be bytes"),
+        };
+        let link = Cid::try_from(&payload[..]).context("invalid dag jose payload")?;
+        map.insert("link".to_string(), Ipld::Link(link));
+    } else {
+        ensure!(
+            map.contains_key("ciphertext"),
+            "invalid dag jose: neither a JWS nor a JWE"
+        );
+    }
+
+    Ok(ipld)
+}
+
+/// Decodes a raw git object, following the layout of `go-ipld-git`.
+///
+/// Blobs are returned as bytes, commits, tags and trees as maps, with links to the hashes
+/// of other git objects.
+pub fn decode_git_raw(bytes: &[u8]) -> Result<Ipld> {
+    let header_end = bytes
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| anyhow!("invalid git object: missing header"))?;
+    let header = std::str::from_utf8(&bytes[..header_end])?;
+    let (typ, size) = header
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid git object header {:?}", header))?;
+    let content = &bytes[header_end + 1..];
+    ensure!(
+        size.parse::<usize>()? == content.len(),
+        "invalid git object: size mismatch"
+    );
+
+    match typ {
+        "blob" => Ok(Ipld::Bytes(content.to_vec())),
+        "tree" => decode_git_tree(content),
+        "commit" => decode_git_commit(content),
+        "tag" => decode_git_tag(content),
+        _ => bail!("unknown git object type {:?}", typ),
+    }
+}
+
+/// Converts a hex encoded sha1 into a git-raw link.
+fn git_link(hex: &str) -> Result<Ipld> {
+    ensure!(
+        hex.len() == 40 && hex.is_ascii(),
+        "invalid git hash {:?}",
+        hex
+    );
+    let mut digest = [0u8; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .with_context(|| format!("invalid git hash {:?}", hex))?;
+    }
+    git_link_from_digest(&digest)
+}
+
+fn git_link_from_digest(digest: &[u8]) -> Result<Ipld> {
+    let hash = Multihash::wrap(Codec::Sha1 as u64, digest)?;
+    Ok(Ipld::Link(Cid::new_v1(Codec::GitRaw as u64, hash)))
+}
+
+fn decode_git_tree(mut content: &[u8]) -> Result<Ipld> {
+    let mut entries = BTreeMap::new();
+    while !content.is_empty() {
+        let name_end = content
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| anyhow!("invalid git tree entry"))?;
+        ensure!(content.len() >= name_end + 21, "truncated git tree entry");
+        let entry = std::str::from_utf8(&content[..name_end])?;
+        let (mode, name) = entry
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("invalid git tree entry {:?}", entry))?;
+        let hash = git_link_from_digest(&content[name_end + 1..name_end + 21])?;
+
+        let mut fields = BTreeMap::new();
+        fields.insert("mode".to_string(), Ipld::String(mode.to_string()));
+        fields.insert("hash".to_string(), hash);
+        entries.insert(name.to_string(), Ipld::Map(fields));
+        content = &content[name_end + 21..];
+    }
+
+    Ok(Ipld::Map(entries))
+}
+
+/// Splits a commit or tag into its headers and message.
+///
+/// Continuation lines of multi line headers, like signatures, are joined into the value.
+fn git_headers(content: &[u8]) -> Result<(Vec<(&str, String)>, &str)> {
+    let content = std::str::from_utf8(content)?;
+    let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+
+    let mut out: Vec<(&str, String)> = Vec::new();
+    for line in headers.lines() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            let (_, value) = out
+                .last_mut()
+                .ok_or_else(|| anyhow!("invalid git header {:?}", line))?;
+            value.push('\n');
+            value.push_str(continuation);
+        } else {
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("invalid git header {:?}", line))?;
+            out.push((key, value.to_string()));
+        }
+    }
+
+    Ok((out, message))
+}
+
+/// Parses `Name <email> 1654000000 +0200`.
+fn git_person(value: &str) -> Result<Ipld> {
+    let (name, rest) = value
+        .split_once(" <")
+        .ok_or_else(|| anyhow!("invalid git person {:?}", value))?;
+    let (email, rest) = rest
+        .split_once("> ")
+        .ok_or_else(|| anyhow!("invalid git person {:?}", value))?;
+    let (date, timezone) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    let mut person = BTreeMap::new();
+    person.insert("name".to_string(), Ipld::String(name.to_string()));
+    person.insert("email".to_string(), Ipld::String(email.to_string()));
+    person.insert("date".to_string(), Ipld::String(date.to_string()));
+    person.insert("timezone".to_string(), Ipld::String(timezone.to_string()));
+    Ok(Ipld::Map(person))
+}
+
+fn decode_git_commit(content: &[u8]) -> Result<Ipld> {
+    let (headers, message) = git_headers(content)?;
+    let mut commit = BTreeMap::new();
+    let mut parents = Vec::new();
+    for (key, value) in headers {
+        let value = match key {
+            "tree" => git_link(&value)?,
+            "parent" => {
+                parents.push(git_link(&value)?);
+                continue;
+            }
+            "author" | "committer" => git_person(&value)?,
+            "gpgsig" => {
+                commit.insert("signature".to_string(), Ipld::String(value));
+                continue;
+            }
+            _ => Ipld::String(value),
+        };
+        commit.insert(key.to_string(), value);
+    }
+    ensure!(
+        commit.contains_key("tree"),
+        "invalid git commit: missing tree"
+    );
+    commit.insert("parents".to_string(), Ipld::List(parents));
+    commit.insert("message".to_string(), Ipld::String(message.to_string()));
+
+    Ok(Ipld::Map(commit))
+}
+
+fn decode_git_tag(content: &[u8]) -> Result<Ipld> {
+    let (headers, message) = git_headers(content)?;
+    let mut tag = BTreeMap::new();
+    for (key, value) in headers {
+        let value = match key {
+            "object" => git_link(&value)?,
+            "tagger" => git_person(&value)?,
+            _ => Ipld::String(value),
+        };
+        tag.insert(key.to_string(), value);
+    }
+    ensure!(
+        tag.contains_key("object"),
+        "invalid git tag: missing object"
+    );
+    tag.insert("message".to_string(), Ipld::String(message.to_string()));
+
+    Ok(Ipld::Map(tag))
+}
+
+/// The protobuf encoding of a libp2p public key.
+#[derive(Clone, PartialEq, Message)]
+struct PublicKey {
+    #[prost(int32, tag = "1")]
+    key_type: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    data: Vec<u8>,
+}
+
+/// Decodes a libp2p public key into a map of its `type` and `data`.
+pub fn decode_libp2p_key(bytes: &[u8]) -> Result<Ipld> {
+    let key = PublicKey::decode(bytes).context("invalid libp2p key")?;
+    let typ = match key.key_type {
+        0 => "RSA",
+        1 => "Ed25519",
+        2 => "Secp256k1",
+        3 => "ECDSA",
+        other => bail!("unknown libp2p key type {}", other),
+    };
+
+    let mut map = BTreeMap::new();
+    map.insert("type".to_string(), Ipld::String(typ.to_string()));
+    map.insert("data".to_string(), Ipld::Bytes(key.data));
+    Ok(Ipld::Map(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::{Code, MultihashDigest};
+
+    fn link(ipld: &Ipld, key: &str) -> Cid {
+        match ipld {
+            Ipld::Map(map) => match map.get(key) {
+                Some(Ipld::Link(cid)) => *cid,
+                other => panic!("expected link at {}, got {:?}", key, other),
+            },
+            other => panic!("expected map, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_dag_jose() {
+        let payload = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(b"data"));
+        let mut signature = BTreeMap::new();
+        signature.insert("protected".to_string(), Ipld::Bytes(b"p".to_vec()));
+        signature.insert("signature".to_string(), Ipld::Bytes(b"s".to_vec()));
+        let mut jws = BTreeMap::new();
+        jws.insert("payload".to_string(), Ipld::Bytes(payload.to_bytes()));
+        jws.insert(
+            "signatures".to_string(),
+            Ipld::List(vec![Ipld::Map(signature)]),
+        );
+        let bytes = IpldCodec::DagCbor.encode(&Ipld::Map(jws)).unwrap();
+
+        let decoded = decode_dag_jose(&bytes).unwrap();
+        assert_eq!(link(&decoded, "link"), payload);
+        let mut links = Vec::new();
+        decoded.references(&mut links);
+        assert_eq!(links, vec![payload]);
+
+        let mut jwe = BTreeMap::new();
+        jwe.insert("ciphertext".to_string(), Ipld::Bytes(b"c".to_vec()));
+        let bytes = IpldCodec::DagCbor.encode(&Ipld::Map(jwe)).unwrap();
+        assert!(decode_dag_jose(&bytes).is_ok());
+
+        let bytes = IpldCodec::DagCbor.encode(&Ipld::Integer(1)).unwrap();
+        assert!(decode_dag_jose(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_git_raw() {
+        let tree_hash = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let parent_hash = "0123456789abcdef0123456789abcdef01234567";
+        let content = format!(
+            "tree {tree_hash}\nparent {parent_hash}\n\
+             author A U Thor <author@example.com> 1654000000 +0200\n\
+             committer C O Mitter <committer@example.com> 1654000001 -0100\n\
+             gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+             \ninitial commit\n"
+        );
+        let commit = format!("commit {}\0{}", content.len(), content);
+        let decoded = decode_git_raw(commit.as_bytes()).unwrap();
+        let tree = link(&decoded, "tree");
+        assert_eq!(tree.codec(), Codec::GitRaw as u64);
+        assert_eq!(tree.hash().code(), Codec::Sha1 as u64);
+        assert_eq!(tree.hash().digest()[..2], [0x4b, 0x82]);
+        let map = match &decoded {
+            Ipld::Map(map) => map,
+            _ => unreachable!(),
+        };
+        assert_eq!(map["message"], Ipld::String("initial commit\n".to_string()));
+        assert_eq!(
+            map["signature"],
+            Ipld::String(
+                "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----".to_string()
+            )
+        );
+        match &map["author"] {
+            Ipld::Map(author) => {
+                assert_eq!(author["name"], Ipld::String("A U Thor".to_string()));
+                assert_eq!(author["timezone"], Ipld::String("+0200".to_string()));
+            }
+            other => panic!("invalid author {:?}", other),
+        }
+        let mut links = Vec::new();
+        decoded.references(&mut links);
+        assert_eq!(links.len(), 2);
+
+        let mut tree = b"100644 hello.txt\0".to_vec();
+        tree.extend_from_slice(&[0xab; 20]);
+        tree.extend_from_slice(b"40000 src\0");
+        tree.extend_from_slice(&[0xcd; 20]);
+        let mut object = format!("tree {}\0", tree.len()).into_bytes();
+        object.extend_from_slice(&tree);
+        let decoded = decode_git_raw(&object).unwrap();
+        match &decoded {
+            Ipld::Map(entries) => {
+                assert_eq!(entries.len(), 2);
+                assert_eq!(link(&entries["src"], "hash").hash().digest(), &[0xcd; 20]);
+            }
+            other => panic!("invalid tree {:?}", other),
+        }
+
+        assert_eq!(
+            decode_git_raw(b"blob 5\0hello").unwrap(),
+            Ipld::Bytes(b"hello".to_vec())
+        );
+        assert!(decode_git_raw(b"blob 6\0hello").is_err());
+        assert!(decode_git_raw(b"blob").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_git() {
+        use crate::resolver::{OutType, Path, Resolver};
+        use bytes::Bytes;
+        use std::collections::HashMap;
+        use tokio::io::AsyncReadExt;
+
+        // the loader does not verify hashes, so fixed digests are enough
+        let cid = |digest: u8| match git_link_from_digest(&[digest; 20]).unwrap() {
+            Ipld::Link(cid) => cid,
+            _ => unreachable!(),
+        };
+        let blob = b"blob 5\0hello".to_vec();
+        let mut tree = b"100644 hello.txt\0".to_vec();
+        tree.extend_from_slice(&[1; 20]);
+        let mut tree_object = format!("tree {}\0", tree.len()).into_bytes();
+        tree_object.extend_from_slice(&tree);
+        let content = format!(
+            "tree {}\nauthor A U Thor <author@example.com> 1654000000 +0200\n\nmessage\n",
+            "02".repeat(20)
+        );
+        let commit = format!("commit {}\0{}", content.len(), content).into_bytes();
+
+        let loader: HashMap<Cid, Bytes> = [
+            (cid(1), Bytes::from(blob)),
+            (cid(2), Bytes::from(tree_object)),
+            (cid(3), Bytes::from(commit.clone())),
+        ]
+        .into_iter()
+        .collect();
+        let resolver = Resolver::new(loader.clone());
+
+        let out = resolver
+            .resolve(
+                format!("/ipfs/{}/tree/hello.txt/hash", cid(3))
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let m = out.metadata();
+        assert_eq!(m.typ, OutType::GitRaw);
+        assert_eq!(m.resolved_path.len(), 3);
+        assert_eq!(out.ipld().unwrap(), Ipld::Bytes(b"hello".to_vec()));
+
+        let out = resolver
+            .resolve(format!("/ipfs/{}/author/name", cid(3)).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(out.ipld().unwrap(), Ipld::String("A U Thor".to_string()));
+
+        let out = resolver.resolve(Path::from_cid(cid(3))).await.unwrap();
+        let mut bytes = Vec::new();
+        out.pretty(loader.clone())
+            .read_to_end(&mut bytes)
+            .await
+            .unwrap();
+        assert_eq!(bytes, commit);
+        assert_eq!(crate::parse_links(&cid(3), &commit).unwrap(), vec![cid(2)]);
+    }
+
+    #[test]
+    fn test_decode_libp2p_key() {
+        let key = PublicKey {
+            key_type: 1,
+            data: vec![7; 32],
+        };
+        let decoded = decode_libp2p_key(&key.encode_to_vec()).unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert("type".to_string(), Ipld::String("Ed25519".to_string()));
+        expected.insert("data".to_string(), Ipld::Bytes(vec![7; 32]));
+        assert_eq!(decoded, Ipld::Map(expected));
+
+        let key = PublicKey {
+            key_type: 9,
+            data: vec![],
+        };
+        assert!(decode_libp2p_key(&key.encode_to_vec()).is_err());
+    }
+}
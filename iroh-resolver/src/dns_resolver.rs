@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tracing::trace;
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::resolver::Path;
+
+/// Maximum number of cached DNSLink records.
+const MAX_CACHE_ENTRIES: usize = 1024;
+
+/// An upstream DNS server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Nameserver {
+    /// Plain DNS, over UDP falling back to TCP.
+    Plain(SocketAddr),
+    /// DNS-over-HTTPS, `tls_name` is the name in the certificate of the server.
+    Https { addr: SocketAddr, tls_name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsConfig {
+    /// Upstream servers, if empty the default servers of `trust-dns` are used.
+    pub nameservers: Vec<Nameserver>,
+    /// Maximum duration records are cached for, regardless of their TTL.
+    pub max_ttl: Duration,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            nameservers: Vec::new(),
+            max_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Uses the DNS-over-HTTPS servers of Cloudflare.
+    pub fn cloudflare_https() -> Self {
+        let nameservers = ["1.1.1.1:443", "1.0.0.1:443"]
+            .iter()
+            .map(|addr| Nameserver::Https {
+                addr: addr.parse().unwrap(),
+                tls_name: "cloudflare-dns.com".to_string(),
+            })
+            .collect();
+
+        DnsConfig {
+            nameservers,
+            ..Default::default()
+        }
+    }
+
+    fn resolver_config(&self) -> ResolverConfig {
+        if self.nameservers.is_empty() {
+            return ResolverConfig::default();
+        }
+
+        let mut group = NameServerConfigGroup::new();
+        for nameserver in &self.nameservers {
+            let servers = match nameserver {
+                Nameserver::Plain(addr) => {
+                    NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true)
+                }
+                Nameserver::Https { addr, tls_name } => NameServerConfigGroup::from_ips_https(
+                    &[addr.ip()],
+                    addr.port(),
+                    tls_name.clone(),
+                    true,
+                ),
+            };
+            group.merge(servers);
+        }
+        ResolverConfig::from_parts(None, Vec::new(), group)
+    }
+
+    fn resolver_opts(&self) -> ResolverOpts {
+        let mut opts = ResolverOpts::default();
+        opts.positive_max_ttl = Some(self.max_ttl);
+        opts.negative_max_ttl = Some(self.max_ttl);
+        opts
+    }
+}
+
+/// Resolves DNSLink records, caching them according to their TTL.
+///
+/// CNAME records, e.g. `_dnslink.example.com CNAME _dnslink.other.com`, are followed.
+/// Records pointing to `/ipns/` paths are returned as is, they are resolved recursively by
+/// the [`Resolver`](crate::resolver::Resolver).
+pub struct DnsResolver {
+    config: DnsConfig,
+    /// Created on first use.
+    resolver: Mutex<Option<TokioAsyncResolver>>,
+    cache: Mutex<HashMap<String, (Vec<Path>, Instant)>>,
+}
+
+impl Debug for DnsResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsResolver")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        DnsResolver::new(DnsConfig::default())
+    }
+}
+
+impl DnsResolver {
+    pub fn new(config: DnsConfig) -> Self {
+        DnsResolver {
+            config,
+            resolver: Default::default(),
+            cache: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &DnsConfig {
+        &self.config
+    }
+
+    /// Removes all cached records.
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    fn resolver(&self) -> Result<TokioAsyncResolver> {
+        let mut resolver = self.resolver.lock().unwrap();
+        if let Some(ref resolver) = *resolver {
+            return Ok(resolver.clone());
+        }
+
+        let new =
+            TokioAsyncResolver::tokio(self.config.resolver_config(), self.config.resolver_opts())?;
+        *resolver = Some(new.clone());
+        Ok(new)
+    }
+
+    /// Looks up the TXT records of the given name, returning them and until when they are valid.
+    pub async fn resolve_txt_record(&self, name: &str) -> Result<(Vec<String>, Instant)> {
+        let lookup = self.resolver()?.txt_lookup(name).await?;
+        let records = lookup.iter().map(|r| r.to_string()).collect();
+        Ok((records, lookup.valid_until()))
+    }
+
+    /// Returns the DNSLink paths of the given domain.
+    ///
+    /// Looks up `_dnslink.{domain}`, falling back to the TXT records of the domain itself.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve_dnslink(&self, domain: &str) -> Result<Vec<Path>> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        if let Some(paths) = self.get_cached(&domain) {
+            trace!("dnslink cache hit");
+            return Ok(paths);
+        }
+
+        let (mut paths, mut valid_until) = self
+            .resolve_dnslink_records(&format!("_dnslink.{}.", domain))
+            .await?;
+        if paths.is_empty() {
+            let (fallback, fallback_valid_until) = self
+                .resolve_dnslink_records(&format!("{}.", domain))
+                .await?;
+            paths = fallback;
+            valid_until = fallback_valid_until;
+        }
+
+        if !paths.is_empty() {
+            let valid_until = valid_until.min(Instant::now() + self.config.max_ttl);
+            self.insert_cached(domain, paths.clone(), valid_until);
+        }
+        Ok(paths)
+    }
+
+    async fn resolve_dnslink_records(&self, name: &str) -> Result<(Vec<Path>, Instant)> {
+        let (records, valid_until) = match self.resolver()?.txt_lookup(name).await {
+            Ok(lookup) => {
+                let records: Vec<_> = lookup.iter().map(|r| r.to_string()).collect();
+                (records, lookup.valid_until())
+            }
+            // NXDOMAIN or no TXT records, failing lookups are not the same as a missing record
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                trace!("no txt records for {}: {:?}", name, err);
+                return Ok((Vec::new(), Instant::now()));
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to look up txt records of {}", name))
+            }
+        };
+        let paths = records
+            .iter()
+            .filter_map(|r| r.strip_prefix("dnslink="))
+            .map(|p| p.trim().parse())
+            .collect::<Result<_>>()?;
+        Ok((paths, valid_until))
+    }
+
+    fn get_cached(&self, domain: &str) -> Option<Vec<Path>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(domain) {
+            Some((paths, valid_until)) if *valid_until > Instant::now() => Some(paths.clone()),
+            Some(_) => {
+                cache.remove(domain);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert_cached(&self, domain: String, paths: Vec<Path>, valid_until: Instant) {
+        let now = Instant::now();
+        if valid_until <= now {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.retain(|_, (_, valid_until)| *valid_until > now);
+            if cache.len() >= MAX_CACHE_ENTRIES {
+                cache.clear();
+            }
+        }
+        cache.insert(domain, (paths, valid_until));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::resolver::{PathType, Resolver};
+    use bytes::Bytes;
+    use cid::Cid;
+    use tokio::net::UdpSocket;
+    use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use trust_dns_resolver::proto::rr::{rdata::TXT, Name, RData, Record};
+
+    const CID: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+
+    fn txt(name: &str, ttl: u32, value: &str) -> Record {
+        Record::from_rdata(
+            Name::from_ascii(name).unwrap(),
+            ttl,
+            RData::TXT(TXT::new(vec![value.to_string()])),
+        )
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        Record::from_rdata(
+            Name::from_ascii(name).unwrap(),
+            60,
+            RData::CNAME(Name::from_ascii(target).unwrap()),
+        )
+    }
+
+    /// Answers queries with the matching records, returning its address and the number of
+    /// received queries.
+    async fn serve_dns(records: Vec<Record>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code())
+                    .set_recursion_desired(request.recursion_desired())
+                    .set_recursion_available(true)
+                    .set_authoritative(true);
+                response.add_queries(request.queries().to_vec());
+                let name = request.queries()[0].name();
+                let answers: Vec<_> = records
+                    .iter()
+                    .filter(|r| r.name() == name)
+                    .cloned()
+                    .collect();
+                if name.to_ascii().starts_with("_dnslink.broken.") {
+                    response.set_response_code(ResponseCode::ServFail);
+                } else if answers.is_empty() {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                response.add_answers(answers);
+                socket
+                    .send_to(&response.to_vec().unwrap(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (addr, queries)
+    }
+
+    #[tokio::test]
+    async fn test_dnslink_local_server() {
+        let (addr, queries) = serve_dns(vec![
+            txt("_dnslink.example.com.", 60, &format!("dnslink=/ipfs/{CID}")),
+            cname("_dnslink.alias.com.", "_dnslink.example.com."),
+            txt("_dnslink.other.com.", 60, "dnslink=/ipns/example.com/sub"),
+            txt("short.com.", 0, &format!("dnslink=/ipfs/{CID}")),
+            txt("_dnslink.invalid.com.", 60, "dnslink=/foo/bar"),
+            txt("_dnslink.chain.com.", 60, "dnslink=/ipns/alias.com"),
+            txt("_dnslink.loop.com.", 60, "dnslink=/ipns/loop.com"),
+        ])
+        .await;
+        let resolver = DnsResolver::new(DnsConfig {
+            nameservers: vec![Nameserver::Plain(addr)],
+            ..Default::default()
+        });
+        let expected: Path = format!("/ipfs/{CID}").parse().unwrap();
+
+        assert_eq!(
+            resolver.resolve_dnslink("example.com").await.unwrap(),
+            vec![expected.clone()]
+        );
+        // served from the cache
+        let count = queries.load(Ordering::SeqCst);
+        assert_eq!(
+            resolver.resolve_dnslink("Example.com.").await.unwrap(),
+            vec![expected.clone()]
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), count);
+
+        // cname chain
+        assert_eq!(
+            resolver.resolve_dnslink("alias.com").await.unwrap(),
+            vec![expected.clone()]
+        );
+
+        // ipns paths are returned as is
+        let paths = resolver.resolve_dnslink("other.com").await.unwrap();
+        assert_eq!(paths, vec!["/ipns/example.com/sub".parse().unwrap()]);
+        assert_eq!(paths[0].typ(), PathType::Ipns);
+
+        // fallback to the domain itself, records with a ttl of 0 are not cached
+        assert_eq!(
+            resolver.resolve_dnslink("short.com").await.unwrap(),
+            vec![expected.clone()]
+        );
+        let count = queries.load(Ordering::SeqCst);
+        resolver.resolve_dnslink("short.com").await.unwrap();
+        assert!(queries.load(Ordering::SeqCst) > count);
+
+        assert!(resolver
+            .resolve_dnslink("missing.com")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(resolver.resolve_dnslink("invalid.com").await.is_err());
+        // server failures are errors, without falling back to the domain itself
+        assert!(resolver.resolve_dnslink("broken.com").await.is_err());
+
+        // ipns paths are resolved recursively
+        let cid: Cid = CID.parse().unwrap();
+        let loader: HashMap<Cid, Bytes> = [(cid, Bytes::from_static(b"hello world"))]
+            .into_iter()
+            .collect();
+        let resolver = Resolver::new(loader).with_dns_resolver(resolver);
+        let out = resolver
+            .resolve("/ipns/chain.com".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(out.metadata().resolved_path, vec![(CID.to_string(), cid)]);
+        assert!(resolver
+            .resolve("/ipns/loop.com".parse().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_txt_record() {
+        let resolver = DnsResolver::default();
+        let (result, _) = resolver
+            .resolve_txt_record("_dnslink.ipfs.io.")
+            .await
+            .unwrap();
+        assert!(!result.is_empty());
+        assert_eq!(result[0], "dnslink=/ipns/website.ipfs.io");
+
+        let (result, _) = resolver
+            .resolve_txt_record("_dnslink.website.ipfs.io.")
+            .await
+            .unwrap();
+        assert!(!result.is_empty());
+        assert!(&result[0].starts_with("dnslink=/ipfs"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dnslink() {
+        let resolver = DnsResolver::default();
+        let result = resolver.resolve_dnslink("ipfs.io").await.unwrap();
+        assert!(!result.is_empty());
+        assert_eq!(result[0], "/ipns/website.ipfs.io".parse().unwrap());
+
+        let result = resolver.resolve_dnslink("website.ipfs.io").await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].typ(), PathType::Ipfs);
+    }
+}
@@ -0,0 +1,521 @@
+use std::io::SeekFrom;
+use std::path::Path as FsPath;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use async_recursion::async_recursion;
+use cid::Cid;
+use filetime::FileTime;
+use futures::TryStreamExt;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncSeekExt;
+use tracing::debug;
+
+use crate::codecs::Codec;
+use crate::resolver::{ContentLoader, Path, Resolver};
+use crate::unixfs::{DataType, UnixfsNode};
+
+/// Which metadata stored in the UnixFS nodes is applied to the exported entries.
+///
+/// Metadata is applied to files and directories, never to symlinks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    mode: bool,
+    mtime: bool,
+}
+
+impl ExportOptions {
+    /// Sets the stored permission bits, only supported on unix.
+    ///
+    /// The setuid, setgid and sticky bits are never set, the content is not trusted.
+    pub fn mode(mut self, apply: bool) -> Self {
+        self.mode = apply;
+        self
+    }
+
+    /// Sets the stored modification times.
+    pub fn mtime(mut self, apply: bool) -> Self {
+        self.mtime = apply;
+        self
+    }
+}
+
+/// Summary of an export.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExportStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// Bytes of file content written by this export, content that was already on disk
+    /// from an earlier, interrupted export is not counted.
+    pub bytes_written: u64,
+}
+
+impl<T: ContentLoader + Unpin + 'static> Resolver<T> {
+    /// Resolves the path and writes the UnixFS file, directory or symlink it points to
+    /// to `target`, recursively.
+    ///
+    /// Exports are resumable: complete files are skipped and shorter ones are continued at
+    /// their current length, so the existing content is trusted to be a prefix of the file.
+    /// Existing entries of a different type fail the export, which also makes sure nothing
+    /// is ever written through a symlink. Entry names that could escape their directory,
+    /// like `..`, are rejected.
+    pub async fn export(
+        &self,
+        path: Path,
+        target: impl AsRef<FsPath>,
+        options: ExportOptions,
+    ) -> Result<ExportStats> {
+        let out = self.resolve(path).await?;
+        let (_, cid) = out
+            .metadata()
+            .resolved_path
+            .last()
+            .cloned()
+            .ok_or_else(|| anyhow!("nothing resolved"))?;
+        ensure!(
+            cid.codec() == Codec::DagPb as u64 || cid.codec() == Codec::Raw as u64,
+            "{} is not a UnixFS node",
+            cid
+        );
+
+        let loader = self.loader();
+        let node = load_node(loader, cid).await?;
+        let mut stats = ExportStats::default();
+        export_node(loader, node, target.as_ref(), options, &mut stats).await?;
+
+        Ok(stats)
+    }
+}
+
+async fn load_node<T: ContentLoader>(loader: &T, cid: Cid) -> Result<UnixfsNode> {
+    let bytes = loader.load_cid(&cid).await?;
+    UnixfsNode::decode(&cid, bytes)
+}
+
+#[async_recursion]
+async fn export_node<T: ContentLoader + Unpin + 'static>(
+    loader: &T,
+    node: UnixfsNode,
+    dest: &FsPath,
+    options: ExportOptions,
+    stats: &mut ExportStats,
+) -> Result<()> {
+    let existing = match fs::symlink_metadata(dest).await {
+        Ok(meta) => Some(meta),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to inspect {}", dest.display()))
+        }
+    };
+    let mode = node.mode();
+    let mtime = node.mtime();
+
+    if node.is_dir() {
+        let restore = match existing {
+            Some(meta) if meta.is_dir() => make_writable(dest, meta).await?,
+            Some(_) => bail!("{} exists and is not a directory", dest.display()),
+            None => {
+                fs::create_dir(dest)
+                    .await
+                    .with_context(|| format!("failed to create {}", dest.display()))?;
+                None
+            }
+        };
+        // load the entries upfront, so no HAMT shards are held open while recursing
+        let links: Vec<_> = node
+            .dir_entries(loader.clone())
+            .expect("checked to be a directory")
+            .try_collect()
+            .await?;
+        for link in links {
+            let name = link.name.as_deref().unwrap_or_default();
+            check_name(name)?;
+            let child = load_node(loader, link.cid).await?;
+            export_node(loader, child, &dest.join(name), options, stats).await?;
+        }
+        if let Some(permissions) = restore {
+            fs::set_permissions(dest, permissions)
+                .await
+                .with_context(|| format!("failed to set mode of {}", dest.display()))?;
+        }
+        stats.directories += 1;
+    } else if let Some(target) = node.symlink()? {
+        export_symlink(target, dest, existing).await?;
+        stats.symlinks += 1;
+        return Ok(());
+    } else {
+        match node.typ() {
+            None | Some(DataType::Raw) | Some(DataType::File) => {}
+            Some(typ) => bail!("can not export {:?} nodes", typ),
+        }
+        stats.bytes_written += export_file(loader, node, dest, existing).await?;
+        stats.files += 1;
+    }
+
+    // directories get their metadata last, their mtime changes while the entries are written
+    apply_metadata(dest, mode, mtime, options).await
+}
+
+/// Allows writing to a directory that an earlier export made read-only, returning the
+/// permissions to restore once the entries are written.
+async fn make_writable(
+    dest: &FsPath,
+    meta: std::fs::Metadata,
+) -> Result<Option<std::fs::Permissions>> {
+    let permissions = meta.permissions();
+    if !permissions.readonly() {
+        return Ok(None);
+    }
+    let mut writable = permissions.clone();
+    writable.set_readonly(false);
+    fs::set_permissions(dest, writable)
+        .await
+        .with_context(|| format!("failed to make {} writable", dest.display()))?;
+    Ok(Some(permissions))
+}
+
+/// Rejects names that would not refer to a direct child of the directory.
+fn check_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name != "."
+            && name != ".."
+            && !name.contains(|c| matches!(c, '/' | '\\' | '\0')),
+        "invalid entry name {:?}",
+        name
+    );
+    Ok(())
+}
+
+/// Writes the file content, continuing after what is already on disk.
+///
+/// Returns the number of bytes written.
+async fn export_file<T: ContentLoader + Unpin + 'static>(
+    loader: &T,
+    node: UnixfsNode,
+    dest: &FsPath,
+    existing: Option<std::fs::Metadata>,
+) -> Result<u64> {
+    let size = node
+        .filesize()
+        .ok_or_else(|| anyhow!("missing size of {}", dest.display()))?;
+    let offset = match existing {
+        Some(meta) if meta.is_file() => meta.len(),
+        Some(_) => bail!("{} exists and is not a file", dest.display()),
+        None => 0,
+    };
+    if offset == size {
+        debug!("{} already exported", dest.display());
+        return Ok(0);
+    }
+    // longer files are not from an earlier export of this content
+    let offset = if offset > size { 0 } else { offset };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(dest)
+        .await
+        .with_context(|| format!("failed to open {}", dest.display()))?;
+    file.set_len(offset).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut reader = node.pretty(loader.clone());
+    reader.seek(SeekFrom::Start(offset)).await?;
+    let written = tokio::io::copy(&mut reader, &mut file).await?;
+    file.sync_all().await?;
+    ensure!(
+        offset + written == size,
+        "{}: expected {} bytes, got {}",
+        dest.display(),
+        size,
+        offset + written
+    );
+
+    Ok(written)
+}
+
+async fn export_symlink(
+    target: &str,
+    dest: &FsPath,
+    existing: Option<std::fs::Metadata>,
+) -> Result<()> {
+    match existing {
+        Some(meta) if meta.file_type().is_symlink() => {
+            if fs::read_link(dest).await? == FsPath::new(target) {
+                return Ok(());
+            }
+            fs::remove_file(dest).await?;
+        }
+        Some(_) => bail!("{} exists and is not a symlink", dest.display()),
+        None => {}
+    }
+    create_symlink(target, dest).await
+}
+
+#[cfg(unix)]
+async fn create_symlink(target: &str, dest: &FsPath) -> Result<()> {
+    fs::symlink(target, dest)
+        .await
+        .with_context(|| format!("failed to create symlink {}", dest.display()))
+}
+
+#[cfg(not(unix))]
+async fn create_symlink(_target: &str, dest: &FsPath) -> Result<()> {
+    bail!("can not create symlink {} on this platform", dest.display())
+}
+
+async fn apply_metadata(
+    dest: &FsPath,
+    mode: Option<u32>,
+    mtime: Option<SystemTime>,
+    options: ExportOptions,
+) -> Result<()> {
+    if let Some(mode) = mode.filter(|_| options.mode) {
+        set_mode(dest, mode).await?;
+    }
+    if let Some(mtime) = mtime.filter(|_| options.mtime) {
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            filetime::set_file_mtime(&dest, FileTime::from_system_time(mtime))
+        })
+        .await??;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn set_mode(dest: &FsPath, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // only the permission bits, no setuid, setgid or sticky bits from untrusted content
+    fs::set_permissions(dest, std::fs::Permissions::from_mode(mode & 0o777))
+        .await
+        .with_context(|| format!("failed to set mode of {}", dest.display()))
+}
+
+#[cfg(not(unix))]
+async fn set_mode(dest: &FsPath, _mode: u32) -> Result<()> {
+    debug!("ignoring mode of {}", dest.display());
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::resolver_for;
+    use crate::unixfs_builder::{DirectoryBuilder, FileBuilder, Symlink};
+
+    #[tokio::test]
+    async fn test_export() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let sub = DirectoryBuilder::new()
+            .name("sub")
+            .add_file(
+                FileBuilder::new()
+                    .name("large.bin")
+                    .content_bytes(content.clone())
+                    .build()
+                    .unwrap(),
+            )
+            .add_symlink(Symlink::new("link", "../hello.txt"))
+            .mode(0o700)
+            .build()
+            .unwrap();
+        let dir = DirectoryBuilder::new()
+            .add_file(
+                FileBuilder::new()
+                    .name("hello.txt")
+                    .content_bytes(&b"hello"[..])
+                    .mode(0o640)
+                    .mtime(mtime)
+                    .build()
+                    .unwrap(),
+            )
+            .add_dir(sub);
+        let (root, resolver) = resolver_for(dir.build().unwrap().encode()).await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("out");
+        let options = ExportOptions::default().mode(true).mtime(true);
+        let stats = resolver
+            .export(Path::from_cid(root), &target, options)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            ExportStats {
+                files: 2,
+                directories: 2,
+                symlinks: 1,
+                bytes_written: content.len() as u64 + 5,
+            }
+        );
+
+        let hello = target.join("hello.txt");
+        assert_eq!(std::fs::read(&hello).unwrap(), b"hello");
+        let meta = std::fs::metadata(&hello).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(meta.modified().unwrap(), mtime);
+        let large = target.join("sub/large.bin");
+        assert_eq!(std::fs::read(&large).unwrap(), content);
+        let sub_meta = std::fs::metadata(target.join("sub")).unwrap();
+        assert_eq!(sub_meta.permissions().mode() & 0o7777, 0o700);
+        let link = target.join("sub/link");
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            FsPath::new("../hello.txt")
+        );
+        assert_eq!(std::fs::read(&link).unwrap(), b"hello");
+
+        // a second export has nothing to do
+        let stats = resolver
+            .export(Path::from_cid(root), &target, options)
+            .await
+            .unwrap();
+        assert_eq!(stats.bytes_written, 0);
+
+        // interrupted exports are continued
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&large)
+            .unwrap()
+            .set_len(1234)
+            .unwrap();
+        std::fs::remove_file(&link).unwrap();
+        let stats = resolver
+            .export(Path::from_cid(root), &target, options)
+            .await
+            .unwrap();
+        assert_eq!(stats.bytes_written, content.len() as u64 - 1234);
+        assert_eq!(std::fs::read(&large).unwrap(), content);
+        assert!(std::fs::symlink_metadata(&link).is_ok());
+
+        // single files and paths into the dag
+        let file = tmp.path().join("single.bin");
+        let path: Path = format!("/ipfs/{root}/sub/large.bin").parse().unwrap();
+        resolver
+            .export(path, &file, ExportOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), content);
+
+        // existing entries of another type are not replaced
+        let path: Path = format!("/ipfs/{root}/sub").parse().unwrap();
+        assert!(resolver
+            .export(path, &file, ExportOptions::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_read_only_dir() {
+        let dir = DirectoryBuilder::new()
+            .add_file(
+                FileBuilder::new()
+                    .name("run.sh")
+                    .content_bytes(&b"#!/bin/sh\n"[..])
+                    .mode(0o4755)
+                    .build()
+                    .unwrap(),
+            )
+            .mode(0o500);
+        let (root, resolver) = resolver_for(dir.build().unwrap().encode()).await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("out");
+        let file = target.join("run.sh");
+        let mode =
+            |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        let chmod = |p: &std::path::Path, mode: u32| {
+            std::fs::set_permissions(p, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        let options = ExportOptions::default().mode(true);
+        resolver
+            .export(Path::from_cid(root), &target, options)
+            .await
+            .unwrap();
+        // no setuid bit from the content
+        assert_eq!(mode(&file), 0o755);
+        assert_eq!(mode(&target), 0o500);
+
+        // resuming writes into the read-only directory
+        for options in [options, ExportOptions::default()] {
+            chmod(&target, 0o700);
+            std::fs::remove_file(&file).unwrap();
+            chmod(&target, 0o500);
+            let stats = resolver
+                .export(Path::from_cid(root), &target, options)
+                .await
+                .unwrap();
+            assert_eq!(stats.files, 1);
+            assert_eq!(std::fs::read(&file).unwrap(), b"#!/bin/sh\n");
+            assert_eq!(mode(&target), 0o500);
+        }
+
+        chmod(&target, 0o700);
+    }
+
+    #[tokio::test]
+    async fn test_export_traversal() {
+        let dir = DirectoryBuilder::new().add_file(
+            FileBuilder::new()
+                .name("..")
+                .content_bytes(&b"evil"[..])
+                .build()
+                .unwrap(),
+        );
+        let (root, resolver) = resolver_for(dir.build().unwrap().encode()).await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("out");
+        let err = resolver
+            .export(Path::from_cid(root), &target, ExportOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid entry name"), "{}", err);
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+
+        for name in ["", ".", "..", "a/b", "a\\b", "a\0b"] {
+            assert!(check_name(name).is_err(), "{:?}", name);
+        }
+        assert!(check_name("..a").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_export_no_write_through_symlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let target = tmp.path().join("out");
+        std::fs::create_dir(&target).unwrap();
+        std::os::unix::fs::symlink(&outside, target.join("sub")).unwrap();
+
+        let dir = DirectoryBuilder::new().add_dir(
+            DirectoryBuilder::new()
+                .name("sub")
+                .add_file(
+                    FileBuilder::new()
+                        .name("file.txt")
+                        .content_bytes(&b"data"[..])
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        let (root, resolver) = resolver_for(dir.build().unwrap().encode()).await;
+        assert!(resolver
+            .export(Path::from_cid(root), &target, ExportOptions::default())
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+    }
+}
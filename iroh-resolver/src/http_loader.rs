@@ -0,0 +1,311 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Result};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use cid::Cid;
+use futures::StreamExt;
+use reqwest::header::ACCEPT;
+use tracing::{debug, trace};
+
+use crate::loader_chain::verify_block_strict;
+use crate::resolver::{identity_content, ContentLoader};
+
+/// Content type of single raw blocks, as defined by the trustless gateway spec.
+pub const CONTENT_TYPE_RAW: &str = "application/vnd.ipld.raw";
+
+/// Blocks larger than this are rejected.
+pub const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Default duration of a single request to a gateway.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Loads raw blocks from HTTP gateways, verifying their hashes.
+///
+/// Blocks using hash functions that can not be verified are rejected.
+/// Requests are distributed round-robin over the gateways, failing over to the next
+/// gateway if a request fails. Clones share the rotation.
+#[derive(Debug, Clone)]
+pub struct HttpLoader {
+    gateways: Arc<Vec<String>>,
+    client: reqwest::Client,
+    timeout: Duration,
+    next: Arc<AtomicUsize>,
+}
+
+impl HttpLoader {
+    /// Creates a loader for the given gateways, e.g. `https://ipfs.io`.
+    pub fn new<I, S>(gateways: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let gateways: Vec<String> = gateways
+            .into_iter()
+            .map(|gateway| gateway.into().trim_end_matches('/').to_string())
+            .collect();
+        ensure!(!gateways.is_empty(), "at least one gateway is required");
+        for gateway in &gateways {
+            ensure!(
+                gateway.starts_with("http://") || gateway.starts_with("https://"),
+                "invalid gateway url {}",
+                gateway
+            );
+        }
+
+        Ok(HttpLoader {
+            gateways: Arc::new(gateways),
+            client: reqwest::Client::new(),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            next: Default::default(),
+        })
+    }
+
+    /// Sets the maximum duration of a single request, before failing over to the next gateway.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn gateways(&self) -> &[String] {
+        &self.gateways
+    }
+
+    async fn fetch(&self, gateway: &str, cid: &Cid) -> Result<Bytes> {
+        let url = format!("{}/ipfs/{}?format=raw", gateway, cid);
+        let response = self
+            .client
+            .get(&url)
+            .header(ACCEPT, CONTENT_TYPE_RAW)
+            .timeout(self.timeout)
+            .send()
+            .await?
+            .error_for_status()?;
+        if let Some(len) = response.content_length() {
+            ensure!(len as usize <= MAX_BLOCK_SIZE, "block too large: {}", len);
+        }
+
+        // the content length is optional, so the size is checked while reading as well
+        let mut body = response.bytes_stream();
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            ensure!(
+                bytes.len() + chunk.len() <= MAX_BLOCK_SIZE,
+                "block too large: more than {} bytes",
+                MAX_BLOCK_SIZE
+            );
+            bytes.extend_from_slice(&chunk);
+        }
+        let bytes = bytes.freeze();
+        verify_block_strict(*cid, bytes.clone()).await?;
+
+        Ok(bytes)
+    }
+}
+
+#[async_trait]
+impl ContentLoader for HttpLoader {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        if let Some(bytes) = identity_content(cid) {
+            return Ok(bytes);
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut errors = Vec::new();
+        for i in 0..self.gateways.len() {
+            let gateway = &self.gateways[(start + i) % self.gateways.len()];
+            match self.fetch(gateway, cid).await {
+                Ok(bytes) => {
+                    trace!("retrieved {} from {}", cid, gateway);
+                    return Ok(bytes);
+                }
+                Err(err) => {
+                    debug!("failed to load {} from {}: {:?}", cid, gateway, err);
+                    errors.push(format!("{}: {}", gateway, err));
+                }
+            }
+        }
+
+        bail!("failed to load {}: {}", cid, errors.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicU64;
+
+    use super::*;
+    use crate::resolver::{Path, Resolver};
+    use crate::test_utils::{fixture_file, load_fixture_dag};
+    use axum::body::StreamBody;
+    use axum::extract::{Path as UrlPath, Query};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use cid::multihash::Multihash;
+    use futures::TryStreamExt;
+    use tokio::io::AsyncReadExt;
+
+    #[derive(Debug, Default)]
+    struct Gateway {
+        blocks: HashMap<Cid, Bytes>,
+        /// Serves invalid data.
+        corrupt: bool,
+        /// Serves endless data, without a content length.
+        endless: bool,
+        requests: AtomicU64,
+    }
+
+    async fn get_block(
+        UrlPath(cid): UrlPath<String>,
+        Query(params): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        Extension(gateway): Extension<Arc<Gateway>>,
+    ) -> Result<Response, StatusCode> {
+        gateway.requests.fetch_add(1, Ordering::SeqCst);
+        let accept = headers.get("accept").and_then(|v| v.to_str().ok());
+        if params.get("format").map(|f| f.as_str()) != Some("raw")
+            || accept != Some(CONTENT_TYPE_RAW)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let cid: Cid = cid.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+        if gateway.endless {
+            let chunk = Bytes::from(vec![0u8; 64 * 1024]);
+            let chunks = futures::stream::repeat(Ok::<_, std::io::Error>(chunk));
+            return Ok(StreamBody::new(chunks).into_response());
+        }
+        match gateway.blocks.get(&cid) {
+            Some(_) if gateway.corrupt => Ok(b"corrupt".to_vec().into_response()),
+            Some(bytes) => Ok(bytes.to_vec().into_response()),
+            None => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Serves the gateway on a local port, returning its url.
+    fn serve(gateway: Arc<Gateway>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/ipfs/:cid", get(get_block))
+            .layer(Extension(gateway));
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        url
+    }
+
+    /// Returns the url of a port nobody listens on.
+    fn unused_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    async fn fixture_blocks() -> (Cid, HashMap<Cid, Bytes>) {
+        let root = fixture_file();
+        (root, load_fixture_dag(root).await)
+    }
+
+    #[tokio::test]
+    async fn test_http_loader_resolve() {
+        let (root, blocks) = fixture_blocks().await;
+        let gateway = Arc::new(Gateway {
+            blocks,
+            ..Default::default()
+        });
+        let loader = HttpLoader::new([serve(gateway)]).unwrap();
+
+        let resolver = Resolver::new(loader.clone());
+        let out = resolver.resolve(Path::from_cid(root)).await.unwrap();
+        let mut content = String::new();
+        out.pretty(loader)
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        let expected: String = ('a'..='z').cycle().take(1000).collect();
+        assert_eq!(content, expected);
+    }
+
+    #[tokio::test]
+    async fn test_http_loader_failover() {
+        let (root, blocks) = fixture_blocks().await;
+        let corrupt = Arc::new(Gateway {
+            blocks: blocks.clone(),
+            corrupt: true,
+            ..Default::default()
+        });
+        let a = Arc::new(Gateway {
+            blocks: blocks.clone(),
+            ..Default::default()
+        });
+        let b = Arc::new(Gateway {
+            blocks: blocks.clone(),
+            ..Default::default()
+        });
+
+        // unreachable and corrupt gateways are skipped
+        let loader = HttpLoader::new([unused_url(), serve(corrupt.clone()), serve(a.clone())])
+            .unwrap()
+            .timeout(Duration::from_secs(5));
+        for _ in 0..3 {
+            assert_eq!(loader.load_cid(&root).await.unwrap(), blocks[&root]);
+        }
+        assert!(corrupt.requests.load(Ordering::SeqCst) > 0);
+
+        // requests are distributed over all gateways
+        let loader = HttpLoader::new([serve(a.clone()), serve(b.clone())]).unwrap();
+        a.requests.store(0, Ordering::SeqCst);
+        let cids: Vec<_> = blocks.keys().copied().collect();
+        futures::stream::iter(cids.iter().map(Ok))
+            .try_for_each(|cid| {
+                let loader = loader.clone();
+                async move { loader.load_cid(cid).await.map(|_| ()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            a.requests.load(Ordering::SeqCst) + b.requests.load(Ordering::SeqCst),
+            cids.len() as u64
+        );
+        assert!(b.requests.load(Ordering::SeqCst) >= cids.len() as u64 / 2);
+
+        // only corrupt gateways
+        let loader = HttpLoader::new([serve(corrupt)]).unwrap();
+        assert!(loader.load_cid(&root).await.is_err());
+
+        assert!(HttpLoader::new(Vec::<String>::new()).is_err());
+        assert!(HttpLoader::new(["ipfs.io"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_loader_untrusted() {
+        // murmur3-x64-64 can not be verified
+        let bytes = Bytes::from_static(b"unverifiable");
+        let hash = Multihash::wrap(0x22, &[0; 8]).unwrap();
+        let cid = Cid::new_v1(0x55, hash);
+        let gateway = Arc::new(Gateway {
+            blocks: [(cid, bytes)].into_iter().collect(),
+            ..Default::default()
+        });
+        let loader = HttpLoader::new([serve(gateway)]).unwrap();
+        let err = loader.load_cid(&cid).await.unwrap_err();
+        assert!(err.to_string().contains("unknown hash function"), "{}", err);
+
+        // bodies without a content length are limited while reading
+        let root = fixture_file();
+        let endless = Arc::new(Gateway {
+            endless: true,
+            ..Default::default()
+        });
+        let loader = HttpLoader::new([serve(endless)]).unwrap();
+        let err = loader.load_cid(&root).await.unwrap_err();
+        assert!(err.to_string().contains("block too large"), "{}", err);
+    }
+}
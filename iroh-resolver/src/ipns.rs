@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::multihash::Multihash;
+use cid::Cid;
+use iroh_rpc_client::Client;
+use iroh_util::IDENTITY_HASH_CODE;
+use libipld::prelude::Codec as _;
+use libipld::{Ipld, IpldCodec};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use prost::Message;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{trace, warn};
+
+use crate::resolver::Path;
+
+mod ipns_pb {
+    include!(concat!(env!("OUT_DIR"), "/ipns_pb.rs"));
+}
+
+/// Prefix of the DHT keys under which IPNS records are published.
+const IPNS_KEY_PREFIX: &[u8] = b"/ipns/";
+
+/// Prefix prepended to the CBOR data before creating a V2 signature.
+const SIGNATURE_V2_PREFIX: &[u8] = b"ipns-signature:";
+
+/// A source of signed IPNS records.
+#[async_trait]
+pub trait IpnsSource: Sync + Send + std::fmt::Debug {
+    /// Fetches all raw records that are available for the given peer.
+    async fn get_records(&self, peer_id: &PeerId) -> Result<Vec<Bytes>>;
+}
+
+#[async_trait]
+impl<T: IpnsSource> IpnsSource for Arc<T> {
+    async fn get_records(&self, peer_id: &PeerId) -> Result<Vec<Bytes>> {
+        self.as_ref().get_records(peer_id).await
+    }
+}
+
+#[async_trait]
+impl IpnsSource for Client {
+    async fn get_records(&self, peer_id: &PeerId) -> Result<Vec<Bytes>> {
+        trace!("fetching ipns records from the dht");
+        self.p2p.get_record(ipns_key(peer_id)).await
+    }
+}
+
+/// In memory record map, mostly useful for testing.
+#[async_trait]
+impl IpnsSource for HashMap<PeerId, Bytes> {
+    async fn get_records(&self, peer_id: &PeerId) -> Result<Vec<Bytes>> {
+        Ok(self.get(peer_id).cloned().into_iter().collect())
+    }
+}
+
+/// Returns the DHT key for the IPNS record of the given peer.
+pub fn ipns_key(peer_id: &PeerId) -> Vec<u8> {
+    let mut key = IPNS_KEY_PREFIX.to_vec();
+    key.extend_from_slice(&peer_id.to_bytes());
+    key
+}
+
+/// Converts the root of an `/ipns/<cid>` path into the peer id it refers to.
+pub fn peer_id_from_cid(cid: &Cid) -> Result<PeerId> {
+    PeerId::from_bytes(&cid.hash().to_bytes()).context("cid is not a valid peer id")
+}
+
+/// A decoded and verified IPNS record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpnsRecord {
+    value: Path,
+    sequence: u64,
+    validity: OffsetDateTime,
+    ttl: Option<Duration>,
+}
+
+impl IpnsRecord {
+    /// Decodes the given record, verifying its signature against the key of `peer_id`
+    /// and checking that it has not expired yet.
+    pub fn decode_and_verify(peer_id: &PeerId, bytes: &[u8]) -> Result<Self> {
+        let entry = ipns_pb::IpnsEntry::decode(bytes)?;
+        let public_key = extract_public_key(peer_id, &entry)?;
+
+        let value = entry
+            .value
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing value"))?;
+        let validity = entry
+            .validity
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing validity"))?;
+        ensure!(
+            entry.validity_type() == ipns_pb::ipns_entry::ValidityType::Eol,
+            "unsupported validity type"
+        );
+
+        if let Some(ref signature) = entry.signature_v2 {
+            let data = entry
+                .data
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing data for v2 signature"))?;
+            let mut msg = SIGNATURE_V2_PREFIX.to_vec();
+            msg.extend_from_slice(data);
+            ensure!(public_key.verify(&msg, signature), "invalid v2 signature");
+            verify_v2_data(&entry, data)?;
+        } else if let Some(ref signature) = entry.signature_v1 {
+            ensure!(
+                public_key.verify(&v1_signature_payload(value, validity), signature),
+                "invalid v1 signature"
+            );
+        } else {
+            bail!("record is not signed");
+        }
+
+        let validity = OffsetDateTime::parse(std::str::from_utf8(validity)?, &Rfc3339)
+            .context("invalid validity")?;
+        ensure!(
+            validity > OffsetDateTime::now_utc(),
+            "record expired at {}",
+            validity
+        );
+
+        Ok(IpnsRecord {
+            value: parse_value(value)?,
+            sequence: entry.sequence.unwrap_or_default(),
+            validity,
+            ttl: entry.ttl.map(Duration::from_nanos),
+        })
+    }
+
+    /// Creates a new record pointing at `value`, signed with both V1 and V2 signatures.
+    pub fn create_signed(
+        keypair: &Keypair,
+        value: &Path,
+        sequence: u64,
+        validity: OffsetDateTime,
+        ttl: Duration,
+    ) -> Result<Bytes> {
+        let value = value.to_string().into_bytes();
+        let validity = validity.format(&Rfc3339)?.into_bytes();
+        let ttl = ttl.as_nanos() as u64;
+
+        let data = Ipld::Map(
+            [
+                ("Value".to_string(), Ipld::Bytes(value.clone())),
+                ("Validity".to_string(), Ipld::Bytes(validity.clone())),
+                (
+                    "ValidityType".to_string(),
+                    Ipld::Integer(ipns_pb::ipns_entry::ValidityType::Eol as i128),
+                ),
+                ("Sequence".to_string(), Ipld::Integer(sequence.into())),
+                ("TTL".to_string(), Ipld::Integer(ttl.into())),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let data = IpldCodec::DagCbor
+            .encode(&data)
+            .map_err(|e| anyhow!("failed to encode record data: {:?}", e))?;
+
+        let mut v2_payload = SIGNATURE_V2_PREFIX.to_vec();
+        v2_payload.extend_from_slice(&data);
+        let signature_v2 = keypair.sign(&v2_payload)?;
+        let signature_v1 = keypair.sign(&v1_signature_payload(&value, &validity))?;
+
+        let entry = ipns_pb::IpnsEntry {
+            value: Some(value.into()),
+            signature_v1: Some(signature_v1.into()),
+            validity_type: Some(ipns_pb::ipns_entry::ValidityType::Eol as i32),
+            validity: Some(validity.into()),
+            sequence: Some(sequence),
+            ttl: Some(ttl),
+            pub_key: None,
+            signature_v2: Some(signature_v2.into()),
+            data: Some(data.into()),
+        };
+
+        Ok(entry.encode_to_vec().into())
+    }
+
+    /// The path this record points to.
+    pub fn value(&self) -> &Path {
+        &self.value
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Point in time until which this record is valid.
+    pub fn validity(&self) -> OffsetDateTime {
+        self.validity
+    }
+
+    /// Duration for which this record may be cached.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Returns how long this record can be cached, bounded by both its TTL and its validity.
+    fn cache_duration(&self) -> Duration {
+        let remaining: Duration = (self.validity - OffsetDateTime::now_utc())
+            .try_into()
+            .unwrap_or_default();
+        match self.ttl {
+            Some(ttl) => std::cmp::min(ttl, remaining),
+            None => remaining,
+        }
+    }
+}
+
+/// Verifies all given records and returns the one with the highest sequence number.
+///
+/// Ties are broken by selecting the record with the longest validity.
+pub fn select_best_record(peer_id: &PeerId, records: &[Bytes]) -> Result<IpnsRecord> {
+    records
+        .iter()
+        .filter_map(
+            |bytes| match IpnsRecord::decode_and_verify(peer_id, bytes) {
+                Ok(record) => Some(record),
+                Err(err) => {
+                    warn!("invalid ipns record for {}: {:?}", peer_id, err);
+                    None
+                }
+            },
+        )
+        .max_by(|a, b| {
+            a.sequence
+                .cmp(&b.sequence)
+                .then_with(|| a.validity.cmp(&b.validity))
+        })
+        .ok_or_else(|| anyhow!("no valid ipns record found for {}", peer_id))
+}
+
+/// Caches resolved records for the duration of their TTL.
+#[derive(Debug, Default)]
+pub(crate) struct RecordCache {
+    records: Mutex<HashMap<PeerId, (Path, Instant)>>,
+}
+
+impl RecordCache {
+    pub(crate) fn get(&self, peer_id: &PeerId) -> Option<Path> {
+        let mut records = self.records.lock().unwrap();
+        match records.get(peer_id) {
+            Some((path, expires)) if *expires > Instant::now() => Some(path.clone()),
+            Some(_) => {
+                records.remove(peer_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, peer_id: PeerId, record: &IpnsRecord) {
+        let duration = record.cache_duration();
+        if duration.is_zero() {
+            return;
+        }
+        self.records
+            .lock()
+            .unwrap()
+            .insert(peer_id, (record.value.clone(), Instant::now() + duration));
+    }
+}
+
+fn v1_signature_payload(value: &[u8], validity: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(value.len() + validity.len() + 3);
+    payload.extend_from_slice(value);
+    payload.extend_from_slice(validity);
+    payload.extend_from_slice(b"EOL");
+    payload
+}
+
+/// Ensures the signed CBOR data matches the unsigned protobuf fields.
+fn verify_v2_data(entry: &ipns_pb::IpnsEntry, data: &[u8]) -> Result<()> {
+    let data: Ipld = IpldCodec::DagCbor
+        .decode(data)
+        .map_err(|e| anyhow!("invalid record data: {:?}", e))?;
+
+    let get_bytes = |key: &str| match data.get(key) {
+        Ok(Ipld::Bytes(b)) => Ok(&b[..]),
+        _ => Err(anyhow!("missing {} in record data", key)),
+    };
+    let get_int = |key: &str| match data.get(key) {
+        Ok(Ipld::Integer(i)) => Ok(*i),
+        _ => Err(anyhow!("missing {} in record data", key)),
+    };
+
+    ensure!(
+        entry.value.as_deref() == Some(get_bytes("Value")?),
+        "value mismatch"
+    );
+    ensure!(
+        entry.validity.as_deref() == Some(get_bytes("Validity")?),
+        "validity mismatch"
+    );
+    ensure!(
+        i128::from(entry.validity_type.unwrap_or_default()) == get_int("ValidityType")?,
+        "validity type mismatch"
+    );
+    ensure!(
+        i128::from(entry.sequence.unwrap_or_default()) == get_int("Sequence")?,
+        "sequence mismatch"
+    );
+    ensure!(
+        i128::from(entry.ttl.unwrap_or_default()) == get_int("TTL")?,
+        "ttl mismatch"
+    );
+
+    Ok(())
+}
+
+fn extract_public_key(peer_id: &PeerId, entry: &ipns_pb::IpnsEntry) -> Result<PublicKey> {
+    let public_key = match entry.pub_key {
+        Some(ref key) => PublicKey::from_protobuf_encoding(key)?,
+        None => {
+            // the public key is inlined in the peer id
+            let mh = Multihash::from_bytes(&peer_id.to_bytes())?;
+            ensure!(
+                mh.code() == IDENTITY_HASH_CODE,
+                "record is missing the public key"
+            );
+            PublicKey::from_protobuf_encoding(mh.digest())?
+        }
+    };
+
+    ensure!(
+        PeerId::from_public_key(&public_key) == *peer_id,
+        "public key does not match {}",
+        peer_id
+    );
+
+    Ok(public_key)
+}
+
+fn parse_value(value: &[u8]) -> Result<Path> {
+    if let Ok(s) = std::str::from_utf8(value) {
+        if let Ok(path) = Path::from_str(s) {
+            return Ok(path);
+        }
+    }
+
+    // legacy records store the binary cid directly
+    let cid = Cid::try_from(value).context("invalid record value")?;
+    Ok(Path::from_cid(cid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_record(keypair: &Keypair, value: &str, sequence: u64, valid_for: Duration) -> Bytes {
+        IpnsRecord::create_signed(
+            keypair,
+            &value.parse().unwrap(),
+            sequence,
+            OffsetDateTime::now_utc() + valid_for,
+            Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let value = "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/foo";
+
+        let bytes = make_record(&keypair, value, 3, Duration::from_secs(3600));
+        let record = IpnsRecord::decode_and_verify(&peer_id, &bytes).unwrap();
+        assert_eq!(record.value().to_string(), value);
+        assert_eq!(record.sequence(), 3);
+        assert_eq!(record.ttl(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_record_invalid() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let value = "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy";
+
+        // signed by a different key
+        let other = Keypair::generate_ed25519();
+        let bytes = make_record(&other, value, 1, Duration::from_secs(3600));
+        assert!(IpnsRecord::decode_and_verify(&peer_id, &bytes).is_err());
+
+        // expired
+        let bytes = IpnsRecord::create_signed(
+            &keypair,
+            &value.parse().unwrap(),
+            1,
+            OffsetDateTime::now_utc() - Duration::from_secs(1),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        assert!(IpnsRecord::decode_and_verify(&peer_id, &bytes).is_err());
+
+        // tampered value
+        let bytes = make_record(&keypair, value, 1, Duration::from_secs(3600));
+        let mut entry = ipns_pb::IpnsEntry::decode(&bytes[..]).unwrap();
+        entry.value = Some(Bytes::from_static(
+            b"/ipfs/bafkreicysg23kiwv34eg2d7qweipxwosdo2py4ldv42nbauguluen5v6am",
+        ));
+        let bytes = entry.encode_to_vec();
+        assert!(IpnsRecord::decode_and_verify(&peer_id, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_select_best_record() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let old = "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy";
+        let new = "/ipfs/bafkreicysg23kiwv34eg2d7qweipxwosdo2py4ldv42nbauguluen5v6am";
+
+        let records = [
+            make_record(&keypair, old, 1, Duration::from_secs(3600)),
+            make_record(&keypair, new, 2, Duration::from_secs(3600)),
+            Bytes::from_static(b"garbage"),
+        ];
+        let best = select_best_record(&peer_id, &records).unwrap();
+        assert_eq!(best.value().to_string(), new);
+        assert_eq!(best.sequence(), 2);
+
+        assert!(select_best_record(&peer_id, &records[2..]).is_err());
+    }
+}
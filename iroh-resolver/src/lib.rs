@@ -1,5 +1,24 @@
+pub mod balanced_tree;
+pub mod block_cache;
+pub mod budget;
+pub mod chunker;
 pub mod codecs;
+pub mod dag_stat;
+pub mod dag_walker;
+pub mod decoders;
+pub mod dns_resolver;
+pub mod export;
+pub mod http_loader;
+pub mod ipns;
+pub mod loader_chain;
+pub mod proof;
+pub mod render;
 pub mod resolver;
+pub mod selector;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod trickle_tree;
 pub mod unixfs;
+pub mod unixfs_builder;
 
-pub use crate::resolver::{parse_links, verify_hash};
+pub use crate::resolver::{identity_content, parse_links, verify_hash};
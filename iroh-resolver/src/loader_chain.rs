@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::Cid;
+use futures::future::BoxFuture;
+use iroh_car::CarReader;
+use iroh_rpc_client::Client;
+use tokio::io::AsyncRead;
+use tracing::{debug, trace, warn};
+
+use crate::resolver::{identity_content, parse_links, verify_hash, ContentLoader};
+
+/// Options of a single layer of a [`LoaderChain`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayerOptions {
+    /// Maximum duration of a load from this layer, before the next layer is tried.
+    pub timeout: Option<Duration>,
+    /// Whether blocks loaded from this layer are written back to the store of the chain.
+    pub write_back: bool,
+}
+
+/// A [`ContentLoader`] that tries a list of sources, the layers, in order.
+///
+/// ```ignore
+/// let loader = LoaderChain::new()
+///     .layer("store", StoreLoader::new(client.clone()), LayerOptions::default())
+///     .layer("car", CarLoader::from_reader(file).await?, LayerOptions::default())
+///     .layer(
+///         "p2p",
+///         P2pLoader::new(client.clone()),
+///         LayerOptions { timeout: Some(Duration::from_secs(60)), write_back: true },
+///     )
+///     .write_back_to(client);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LoaderChain {
+    layers: Arc<Vec<Layer>>,
+    store: Option<Client>,
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    name: String,
+    loader: Arc<dyn DynLoader>,
+    options: LayerOptions,
+}
+
+/// Object safe version of [`ContentLoader`].
+trait DynLoader: Send + Sync + Debug {
+    fn load<'a>(&'a self, cid: &'a Cid) -> BoxFuture<'a, Result<Bytes>>;
+}
+
+impl<T: ContentLoader + 'static> DynLoader for T {
+    fn load<'a>(&'a self, cid: &'a Cid) -> BoxFuture<'a, Result<Bytes>> {
+        self.load_cid(cid)
+    }
+}
+
+impl LoaderChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only loads from the local store.
+    pub fn local(client: Client) -> Self {
+        LoaderChain::new().layer("store", StoreLoader::new(client), LayerOptions::default())
+    }
+
+    /// Loads from the local store, falling back to the p2p network, storing all blocks
+    /// retrieved from the network.
+    pub fn network(client: Client) -> Self {
+        LoaderChain::local(client.clone())
+            .layer(
+                "p2p",
+                P2pLoader::new(client.clone()),
+                LayerOptions {
+                    timeout: None,
+                    write_back: true,
+                },
+            )
+            .write_back_to(client)
+    }
+
+    /// Appends a layer, layers are tried in the order they were added.
+    pub fn layer<T: ContentLoader + 'static>(
+        mut self,
+        name: impl Into<String>,
+        loader: T,
+        options: LayerOptions,
+    ) -> Self {
+        Arc::make_mut(&mut self.layers).push(Layer {
+            name: name.into(),
+            loader: Arc::new(loader),
+            options,
+        });
+        self
+    }
+
+    /// Sets the store that blocks from layers with [`LayerOptions::write_back`] are written to.
+    pub fn write_back_to(mut self, store: Client) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Returns the names of the layers, in the order they are tried.
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().map(|layer| layer.name.as_str())
+    }
+}
+
+#[async_trait]
+impl ContentLoader for LoaderChain {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        if let Some(bytes) = identity_content(cid) {
+            return Ok(bytes);
+        }
+
+        let mut errors = Vec::new();
+        for layer in self.layers.iter() {
+            let res = match layer.options.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, layer.loader.load(cid))
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("timed out after {:?}", timeout))),
+                None => layer.loader.load(cid).await,
+            };
+
+            match res {
+                Ok(bytes) => {
+                    trace!("retrieved {} from {}", cid, layer.name);
+                    if layer.options.write_back {
+                        if let Some(ref store) = self.store {
+                            store_in_background(store.clone(), *cid, bytes.clone());
+                        }
+                    }
+                    return Ok(bytes);
+                }
+                Err(err) => {
+                    debug!("failed to load {} from {}: {:?}", cid, layer.name, err);
+                    errors.push(format!("{}: {}", layer.name, err));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            bail!("failed to load {}: no layers configured", cid);
+        }
+        bail!("failed to load {}: {}", cid, errors.join(", "))
+    }
+}
+
+/// Loads blocks from the local store.
+#[derive(Debug, Clone)]
+pub struct StoreLoader {
+    client: Client,
+}
+
+impl StoreLoader {
+    pub fn new(client: Client) -> Self {
+        StoreLoader { client }
+    }
+}
+
+#[async_trait]
+impl ContentLoader for StoreLoader {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        match self.client.store.get(*cid).await? {
+            Some(bytes) => Ok(bytes),
+            None => bail!("not found in the store"),
+        }
+    }
+}
+
+/// Loads blocks from the providers found in the p2p network, using bitswap.
+#[derive(Debug, Clone)]
+pub struct P2pLoader {
+    client: Client,
+}
+
+impl P2pLoader {
+    pub fn new(client: Client) -> Self {
+        P2pLoader { client }
+    }
+}
+
+#[async_trait]
+impl ContentLoader for P2pLoader {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        load_from_p2p(&self.client, cid).await
+    }
+}
+
+/// Loads blocks from a CAR file, which is read into memory.
+#[derive(Debug, Clone)]
+pub struct CarLoader {
+    roots: Vec<Cid>,
+    blocks: Arc<HashMap<Cid, Bytes>>,
+}
+
+impl CarLoader {
+    /// Reads all blocks of the CAR file, verifying their hashes.
+    ///
+    /// CAR files are not trusted, blocks using unknown hash functions are rejected.
+    pub async fn from_reader<R: AsyncRead + Send + Unpin>(reader: R) -> Result<Self> {
+        let mut reader = CarReader::new(reader).await?;
+        let roots = reader.header().roots().to_vec();
+        let mut blocks = HashMap::new();
+        while let Some((cid, bytes)) = reader.next_block().await? {
+            let bytes = Bytes::from(bytes);
+            verify_block_strict(cid, bytes.clone()).await?;
+            blocks.insert(cid, bytes);
+        }
+
+        Ok(CarLoader {
+            roots,
+            blocks: Arc::new(blocks),
+        })
+    }
+
+    pub fn roots(&self) -> &[Cid] {
+        &self.roots
+    }
+}
+
+#[async_trait]
+impl ContentLoader for CarLoader {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        match self.blocks.get(cid) {
+            Some(bytes) => Ok(bytes.clone()),
+            None => bail!("not found in the car file"),
+        }
+    }
+}
+
+/// Loads a block from the local store, failures are logged and treated as missing blocks.
+pub(crate) async fn load_from_store(client: &Client, cid: &Cid) -> Option<Bytes> {
+    match client.store.get(*cid).await {
+        Ok(Some(data)) => {
+            trace!("retrieved from store");
+            Some(data)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            warn!("failed to fetch data from store {}: {:?}", cid, err);
+            None
+        }
+    }
+}
+
+/// Fetches a block from the providers of the p2p network and verifies it.
+pub(crate) async fn load_from_p2p(client: &Client, cid: &Cid) -> Result<Bytes> {
+    let cid = *cid;
+    let providers = client.p2p.fetch_providers(&cid).await?;
+    let bytes = client.p2p.fetch_bitswap(cid, providers).await?;
+    verify_block(cid, bytes.clone()).await?;
+
+    Ok(bytes)
+}
+
+/// Verifies that the block matches the hash of its cid.
+///
+/// Blocks using unknown hash functions are accepted, with a warning.
+pub(crate) async fn verify_block(cid: Cid, bytes: Bytes) -> Result<()> {
+    match tokio::task::spawn_blocking(move || verify_hash(&cid, &bytes)).await? {
+        Some(true) => Ok(()),
+        Some(false) => bail!("invalid hash {:?}", cid.hash()),
+        None => {
+            warn!(
+                "unable to verify hash, unknown hash function {} for {}",
+                cid.hash().code(),
+                cid
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Verifies that the block matches the hash of its cid, failing for unknown hash functions.
+///
+/// Used for blocks from sources that are not trusted at all, like HTTP gateways, CAR files
+/// and proofs.
+pub(crate) async fn verify_block_strict(cid: Cid, bytes: Bytes) -> Result<()> {
+    match tokio::task::spawn_blocking(move || verify_hash(&cid, &bytes)).await? {
+        Some(true) => Ok(()),
+        Some(false) => bail!("invalid hash {:?}", cid.hash()),
+        None => bail!(
+            "unable to verify hash, unknown hash function {} for {}",
+            cid.hash().code(),
+            cid
+        ),
+    }
+}
+
+/// Stores the block in the background, together with its links.
+pub(crate) fn store_in_background(client: Client, cid: Cid, bytes: Bytes) {
+    if identity_content(&cid).is_some() {
+        return;
+    }
+    tokio::spawn(async move {
+        let clone = bytes.clone();
+        let links =
+            tokio::task::spawn_blocking(move || parse_links(&cid, &clone).unwrap_or_default())
+                .await
+                .unwrap_or_default();
+
+        let len = bytes.len();
+        let links_len = links.len();
+        match client.store.put(cid, bytes, links).await {
+            Ok(_) => debug!("stored {} ({}bytes, {}links)", cid, len, links_len),
+            Err(err) => {
+                warn!("failed to store {}: {:?}", cid, err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::{Code, MultihashDigest};
+    use iroh_car::{CarHeader, CarWriter};
+
+    #[derive(Debug, Clone)]
+    struct SlowLoader(Duration);
+
+    #[async_trait]
+    impl ContentLoader for SlowLoader {
+        async fn load_cid(&self, _cid: &Cid) -> Result<Bytes> {
+            tokio::time::sleep(self.0).await;
+            Ok(Bytes::from_static(b"slow"))
+        }
+    }
+
+    fn raw_block(data: &'static [u8]) -> (Cid, Bytes) {
+        (
+            Cid::new_v1(0x55, Code::Sha2_256.digest(data)),
+            Bytes::from_static(data),
+        )
+    }
+
+    async fn car_loader(blocks: &[(Cid, Bytes)]) -> CarLoader {
+        let header = CarHeader::V1(vec![blocks[0].0].into());
+        let mut writer = CarWriter::new(header, Vec::new());
+        for (cid, bytes) in blocks {
+            writer.write(*cid, bytes).await.unwrap();
+        }
+        let car = writer.finish().await.unwrap();
+        CarLoader::from_reader(&car[..]).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_loader_chain_order() {
+        let (a, a_bytes) = raw_block(b"a");
+        let (b, b_bytes) = raw_block(b"b");
+        let (c, _) = raw_block(b"c");
+
+        let first = car_loader(&[(a, a_bytes.clone())]).await;
+        assert_eq!(first.roots(), &[a]);
+        let second = car_loader(&[(b, b_bytes.clone()), (a, Bytes::from_static(b"a"))]).await;
+
+        let chain = LoaderChain::new()
+            .layer("first", first, LayerOptions::default())
+            .layer("second", second, LayerOptions::default());
+        assert_eq!(chain.layer_names().collect::<Vec<_>>(), ["first", "second"]);
+
+        assert_eq!(chain.load_cid(&a).await.unwrap(), a_bytes);
+        assert_eq!(chain.load_cid(&b).await.unwrap(), b_bytes);
+        let err = chain.load_cid(&c).await.unwrap_err().to_string();
+        assert!(err.contains("first: not found"), "{}", err);
+        assert!(err.contains("second: not found"), "{}", err);
+
+        assert!(LoaderChain::new().load_cid(&a).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_loader_chain_timeout() {
+        let (a, a_bytes) = raw_block(b"a");
+        let fallback = car_loader(&[(a, a_bytes.clone())]).await;
+
+        let chain = LoaderChain::new()
+            .layer(
+                "slow",
+                SlowLoader(Duration::from_secs(10)),
+                LayerOptions {
+                    timeout: Some(Duration::from_millis(10)),
+                    write_back: false,
+                },
+            )
+            .layer("car", fallback, LayerOptions::default());
+        assert_eq!(chain.load_cid(&a).await.unwrap(), a_bytes);
+
+        let chain = LoaderChain::new().layer(
+            "slow",
+            SlowLoader(Duration::from_millis(1)),
+            LayerOptions {
+                timeout: Some(Duration::from_secs(10)),
+                write_back: false,
+            },
+        );
+        assert_eq!(chain.load_cid(&a).await.unwrap(), &b"slow"[..]);
+    }
+
+    #[tokio::test]
+    async fn test_car_loader_verifies_hashes() {
+        let (a, _) = raw_block(b"a");
+        let header = CarHeader::V1(vec![a].into());
+        let mut writer = CarWriter::new(header, Vec::new());
+        writer.write(a, b"not a").await.unwrap();
+        let car = writer.finish().await.unwrap();
+        assert!(CarLoader::from_reader(&car[..]).await.is_err());
+
+        // blocks with hashes that can not be verified are not accepted either
+        let hash = cid::multihash::Multihash::wrap(0x22, &[0; 8]).unwrap();
+        let unverifiable = Cid::new_v1(0x55, hash);
+        let header = CarHeader::V1(vec![unverifiable].into());
+        let mut writer = CarWriter::new(header, Vec::new());
+        writer.write(unverifiable, b"unverifiable").await.unwrap();
+        let car = writer.finish().await.unwrap();
+        assert!(CarLoader::from_reader(&car[..]).await.is_err());
+    }
+}
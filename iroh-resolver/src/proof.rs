@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, ensure, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::Cid;
+use futures::TryStreamExt;
+use iroh_car::{CarHeader, CarReader, CarWriter};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::dag_walker::DagWalker;
+use crate::loader_chain::verify_block_strict;
+use crate::resolver::{
+    identity_content, CidOrDomain, ContentLoader, Out, Path, PathType, Resolver,
+};
+
+/// All blocks needed to resolve a path, in the order they were loaded.
+///
+/// This includes intermediate HAMT shards, symlinks and blocks of other codecs the path
+/// passes through, as well as the block the path resolves to. The content below that block
+/// is not part of the proof. Identity hashed blocks are inlined in their CIDs and never
+/// part of a proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathProof {
+    path: Path,
+    blocks: Vec<(Cid, Bytes)>,
+}
+
+impl PathProof {
+    pub(crate) fn new(path: Path, blocks: Vec<(Cid, Bytes)>) -> Self {
+        PathProof { path, blocks }
+    }
+
+    /// Reads a proof from a CAR file, as written by [`PathProof::write_car`].
+    ///
+    /// The hashes of all blocks are verified, blocks using unknown hash functions are rejected.
+    pub async fn from_car<R: AsyncRead + Send + Unpin>(path: Path, reader: R) -> Result<Self> {
+        ensure!(
+            path.typ() == PathType::Ipfs && matches!(path.root(), CidOrDomain::Cid(_)),
+            "expected an /ipfs path, got {}",
+            path
+        );
+        let mut reader = CarReader::new(reader).await?;
+        let mut blocks = Vec::new();
+        while let Some((cid, bytes)) = reader.next_block().await? {
+            let bytes = Bytes::from(bytes);
+            verify_block_strict(cid, bytes.clone()).await?;
+            blocks.push((cid, bytes));
+        }
+
+        Ok(PathProof { path, blocks })
+    }
+
+    /// The `/ipfs` path that was resolved, after any IPNS or DNSLink lookups.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The root [`Cid`] of the resolved `/ipfs` path.
+    pub fn root(&self) -> Cid {
+        match self.path.root() {
+            CidOrDomain::Cid(cid) => *cid,
+            CidOrDomain::Domain(_) => unreachable!("proofs are only created for /ipfs paths"),
+        }
+    }
+
+    pub fn blocks(&self) -> &[(Cid, Bytes)] {
+        &self.blocks
+    }
+
+    pub fn cids(&self) -> impl Iterator<Item = &Cid> {
+        self.blocks.iter().map(|(cid, _)| cid)
+    }
+
+    /// Writes the blocks as a CAR file, with the root of the path as its root.
+    ///
+    /// Blocks of the content can be appended by writing them to the returned writer.
+    pub async fn write_car<W: AsyncWrite + Send + Unpin>(&self, writer: W) -> Result<CarWriter<W>> {
+        let mut writer = CarWriter::new(CarHeader::V1(vec![self.root()].into()), writer);
+        for (cid, bytes) in &self.blocks {
+            writer.write(*cid, bytes).await?;
+        }
+        Ok(writer)
+    }
+
+    /// Writes the blocks as a CAR file, followed by all blocks below `content`, the block the
+    /// path resolves to, so that both the path and the content can be verified offline.
+    ///
+    /// The content is written in depth-first order, without repeating blocks of the proof.
+    pub async fn write_car_with_content<T, W>(
+        &self,
+        content: Cid,
+        loader: T,
+        writer: W,
+    ) -> Result<W>
+    where
+        T: ContentLoader + 'static,
+        W: AsyncWrite + Send + Unpin,
+    {
+        let mut writer = self.write_car(writer).await?;
+        let proven: HashSet<_> = self.cids().copied().collect();
+        let blocks = DagWalker::new(content).walk(loader);
+        tokio::pin!(blocks);
+        while let Some((cid, bytes)) = blocks.try_next().await? {
+            if !proven.contains(&cid) && identity_content(&cid).is_none() {
+                writer.write(cid, bytes).await?;
+            }
+        }
+        Ok(writer.finish().await?)
+    }
+
+    /// Resolves the path again, only using the blocks of this proof.
+    ///
+    /// Fails if any block is missing or does not match its hash, or if its hash function is
+    /// unknown.
+    pub async fn verify(&self) -> Result<Out> {
+        let mut blocks = HashMap::new();
+        for (cid, bytes) in &self.blocks {
+            verify_block_strict(*cid, bytes.clone()).await?;
+            blocks.insert(*cid, bytes.clone());
+        }
+        let loader = ProofBlocks(Arc::new(blocks));
+        Resolver::new(loader).resolve(self.path.clone()).await
+    }
+}
+
+/// Blocks of a proof, failing on anything else.
+#[derive(Debug, Clone)]
+struct ProofBlocks(Arc<HashMap<Cid, Bytes>>);
+
+#[async_trait]
+impl ContentLoader for ProofBlocks {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        self.0
+            .get(cid)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} is not part of the proof", cid))
+    }
+}
+
+/// Records every block loaded through it, in order and without duplicates.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordingLoader<T: ContentLoader> {
+    loader: T,
+    blocks: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    seen: HashSet<Cid>,
+    blocks: Vec<(Cid, Bytes)>,
+}
+
+impl<T: ContentLoader> RecordingLoader<T> {
+    pub(crate) fn new(loader: T) -> Self {
+        RecordingLoader {
+            loader,
+            blocks: Default::default(),
+        }
+    }
+
+    /// Returns the blocks recorded so far.
+    pub(crate) fn blocks(&self) -> Vec<(Cid, Bytes)> {
+        self.blocks.lock().unwrap().blocks.clone()
+    }
+}
+
+#[async_trait]
+impl<T: ContentLoader> ContentLoader for RecordingLoader<T> {
+    async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        let bytes = self.loader.load_cid(cid).await?;
+        if identity_content(cid).is_none() {
+            let mut recorded = self.blocks.lock().unwrap();
+            if recorded.seen.insert(*cid) {
+                recorded.blocks.push((*cid, bytes.clone()));
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::resolver_for;
+    use crate::unixfs_builder::{DirectoryBuilder, FileBuilder};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_path_proof() {
+        let mut root = DirectoryBuilder::new().sharding_threshold(1);
+        for i in 0..300 {
+            let file = FileBuilder::new()
+                .name(format!("{i}.txt"))
+                .content_bytes(format!("file {i}").into_bytes())
+                .build()
+                .unwrap();
+            root = root.add_file(file);
+        }
+        let sub = DirectoryBuilder::new()
+            .name("sub")
+            .add_file(
+                FileBuilder::new()
+                    .name("hello.txt")
+                    .content_bytes(&b"hello"[..])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let (root, resolver) = resolver_for(root.add_dir(sub).build().unwrap().encode()).await;
+        let loader = resolver.loader().clone();
+
+        let path: Path = format!("/ipfs/{root}/sub/hello.txt").parse().unwrap();
+        let (out, proof) = resolver.resolve_with_proof(path.clone()).await.unwrap();
+        assert_eq!(proof.path(), &path);
+        assert_eq!(proof.root(), root);
+        assert_eq!(proof.blocks()[0].0, root);
+        // every resolved segment is part of the proof, as are the shards in between
+        let resolved_path = out.metadata().resolved_path.clone();
+        assert_eq!(resolved_path.len(), 3);
+        for (_, cid) in &resolved_path {
+            assert!(proof.cids().any(|c| c == cid), "{} missing", cid);
+        }
+        assert!(proof.blocks().len() < loader.len());
+        assert_eq!(proof.cids().last(), Some(&resolved_path[2].1));
+
+        let verified = proof.verify().await.unwrap();
+        assert_eq!(verified.metadata().resolved_path, resolved_path);
+        let mut content = String::new();
+        verified
+            .pretty(loader.clone())
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, "hello");
+
+        // round trip through a car file
+        let car = proof
+            .write_car(Vec::new())
+            .await
+            .unwrap()
+            .finish()
+            .await
+            .unwrap();
+        let read = PathProof::from_car(path.clone(), &car[..]).await.unwrap();
+        assert_eq!(read, proof);
+
+        // incomplete and corrupted proofs
+        let mut blocks = proof.blocks().to_vec();
+        blocks.remove(1);
+        let incomplete = PathProof::new(path.clone(), blocks);
+        assert!(incomplete.verify().await.is_err());
+
+        let mut blocks = proof.blocks().to_vec();
+        blocks[1].1 = Bytes::from_static(b"corrupt");
+        let corrupt = PathProof::new(path.clone(), blocks);
+        assert!(corrupt.verify().await.is_err());
+
+        assert!(PathProof::from_car(Path::from_cid(root), &b""[..])
+            .await
+            .is_err());
+
+        // blocks with hashes that can not be verified are not accepted
+        let hash = cid::multihash::Multihash::wrap(0x22, &[0; 8]).unwrap();
+        let unverifiable = Cid::new_v1(0x55, hash);
+        let bytes = Bytes::from_static(b"unverifiable");
+        let proof = PathProof::new(
+            Path::from_cid(unverifiable),
+            vec![(unverifiable, bytes.clone())],
+        );
+        assert!(proof.verify().await.is_err());
+        let mut writer = CarWriter::new(CarHeader::V1(vec![unverifiable].into()), Vec::new());
+        writer.write(unverifiable, &bytes).await.unwrap();
+        let car = writer.finish().await.unwrap();
+        assert!(PathProof::from_car(Path::from_cid(unverifiable), &car[..])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_path_proof_car_with_content() {
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let dir = DirectoryBuilder::new().add_dir(
+            DirectoryBuilder::new()
+                .name("a")
+                .add_file(
+                    FileBuilder::new()
+                        .name("b")
+                        .chunk_size(1024)
+                        .content_bytes(content.clone())
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        let (root, resolver) = resolver_for(dir.build().unwrap().encode()).await;
+        let loader = resolver.loader().clone();
+
+        let path: Path = format!("/ipfs/{root}/a/b").parse().unwrap();
+        let (out, proof) = resolver.resolve_with_proof(path.clone()).await.unwrap();
+        let file = out.metadata().resolved_path.last().unwrap().1;
+        let car = proof
+            .write_car_with_content(file, loader.clone(), Vec::new())
+            .await
+            .unwrap();
+
+        // the proof comes first, followed by the rest of the file, each block once
+        let mut reader = CarReader::new(&car[..]).await.unwrap();
+        assert_eq!(reader.header().roots(), &[root]);
+        let mut blocks = Vec::new();
+        while let Some((cid, bytes)) = reader.next_block().await.unwrap() {
+            blocks.push((cid, Bytes::from(bytes)));
+        }
+        assert_eq!(&blocks[..proof.blocks().len()], proof.blocks());
+        assert_eq!(blocks.len(), proof.blocks().len() + 10);
+
+        // the content can be read from the car alone
+        let proof = PathProof::from_car(path, &car[..]).await.unwrap();
+        let out = proof.verify().await.unwrap();
+        let car_blocks = ProofBlocks(Arc::new(blocks.into_iter().collect()));
+        let mut read = Vec::new();
+        out.pretty(car_blocks).read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, content);
+    }
+}
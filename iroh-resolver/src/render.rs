@@ -0,0 +1,239 @@
+use std::fmt::Write as _;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use libipld::prelude::Codec as _;
+use libipld::{Ipld, IpldCodec};
+
+/// Target format of [`Out::render`](crate::resolver::Out::render).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderFormat {
+    DagJson,
+    DagCbor,
+    /// Indented dag-json, for humans.
+    PrettyJson,
+    /// An HTML page showing the data, with links to the linked blocks.
+    Html,
+}
+
+impl RenderFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RenderFormat::DagJson => "application/vnd.ipld.dag-json",
+            RenderFormat::DagCbor => "application/vnd.ipld.dag-cbor",
+            RenderFormat::PrettyJson => "application/json",
+            RenderFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// Renders the data in the given format, `title` is only used for HTML.
+pub fn render_ipld(ipld: &Ipld, format: RenderFormat, title: &str) -> Result<Bytes> {
+    let bytes = match format {
+        RenderFormat::DagJson => encode(IpldCodec::DagJson, ipld)?,
+        RenderFormat::DagCbor => encode(IpldCodec::DagCbor, ipld)?,
+        RenderFormat::PrettyJson => indent_json(&encode(IpldCodec::DagJson, ipld)?),
+        RenderFormat::Html => {
+            let mut out = String::new();
+            write!(
+                out,
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n",
+                title = escape_html(title)
+            )?;
+            write_html(&mut out, ipld)?;
+            out.push_str("\n</body>\n</html>\n");
+            out.into_bytes()
+        }
+    };
+    Ok(bytes.into())
+}
+
+fn encode(codec: IpldCodec, ipld: &Ipld) -> Result<Vec<u8>> {
+    codec
+        .encode(ipld)
+        .map_err(|e| anyhow!("failed to encode as {:?}: {:?}", codec, e))
+}
+
+/// Indents compact JSON by two spaces per level, leaving strings untouched.
+fn indent_json(compact: &[u8]) -> Vec<u8> {
+    fn newline(out: &mut Vec<u8>, depth: usize) {
+        out.push(b'\n');
+        out.extend(std::iter::repeat(b' ').take(depth * 2));
+    }
+
+    let mut out = Vec::with_capacity(compact.len() * 2);
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut iter = compact.iter().peekable();
+    while let Some(&b) = iter.next() {
+        if in_string {
+            out.push(b);
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => {
+                in_string = true;
+                out.push(b);
+            }
+            b'{' | b'[' => {
+                out.push(b);
+                // keep empty maps and lists on one line
+                if matches!(iter.peek(), Some(b'}') | Some(b']')) {
+                    out.push(*iter.next().unwrap());
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            b'}' | b']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(b);
+            }
+            b',' => {
+                out.push(b);
+                newline(&mut out, depth);
+            }
+            b':' => out.extend_from_slice(b": "),
+            b' ' | b'\n' | b'\r' | b'\t' => {}
+            b => out.push(b),
+        }
+    }
+    out.push(b'\n');
+    out
+}
+
+fn write_html(out: &mut String, ipld: &Ipld) -> Result<()> {
+    match ipld {
+        Ipld::Null => out.push_str("<code>null</code>"),
+        Ipld::Bool(b) => write!(out, "<code>{}</code>", b)?,
+        Ipld::Integer(i) => write!(out, "<code>{}</code>", i)?,
+        Ipld::Float(f) => write!(out, "<code>{}</code>", f)?,
+        Ipld::String(s) => write!(out, "<code>\"{}\"</code>", escape_html(s))?,
+        Ipld::Bytes(bytes) => {
+            // only show the start of large byte strings
+            const MAX_SHOWN: usize = 32;
+            write!(out, "<code>bytes({}) ", bytes.len())?;
+            for b in bytes.iter().take(MAX_SHOWN) {
+                write!(out, "{:02x}", b)?;
+            }
+            if bytes.len() > MAX_SHOWN {
+                out.push_str("...");
+            }
+            out.push_str("</code>");
+        }
+        Ipld::Link(cid) => write!(out, "<a href=\"/ipfs/{cid}\"><code>{cid}</code></a>")?,
+        Ipld::List(list) => {
+            out.push_str("<ol start=\"0\">\n");
+            for item in list {
+                out.push_str("<li>");
+                write_html(out, item)?;
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ol>");
+        }
+        Ipld::Map(map) => {
+            out.push_str("<dl>\n");
+            for (key, value) in map {
+                write!(out, "<dt>{}</dt>\n<dd>", escape_html(key))?;
+                write_html(out, value)?;
+                out.push_str("</dd>\n");
+            }
+            out.push_str("</dl>");
+        }
+    }
+    Ok(())
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::resolver::{Path, Resolver};
+    use cid::multihash::{Code, MultihashDigest};
+    use cid::Cid;
+
+    #[test]
+    fn test_indent_json() {
+        let compact = br#"{"a":[1,{"/":"b,c"}],"d\"{":{},"e":[]}"#;
+        let expected = r#"{
+  "a": [
+    1,
+    {
+      "/": "b,c"
+    }
+  ],
+  "d\"{": {},
+  "e": []
+}
+"#;
+        assert_eq!(
+            std::str::from_utf8(&indent_json(compact)).unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_formats() {
+        let link = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"hello"));
+        let mut map = BTreeMap::new();
+        map.insert("name".to_string(), Ipld::String("<b>bold</b>".to_string()));
+        map.insert("link".to_string(), Ipld::Link(link));
+        map.insert("data".to_string(), Ipld::Bytes(vec![0xde, 0xad]));
+        let ipld = Ipld::Map(map);
+
+        let bytes = IpldCodec::DagCbor.encode(&ipld).unwrap();
+        let cid = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+        let loader: HashMap<Cid, Bytes> = [(cid, Bytes::from(bytes.clone()))].into_iter().collect();
+        let resolver = Resolver::new(Arc::new(loader));
+        let out = resolver.resolve(Path::from_cid(cid)).await.unwrap();
+
+        // converting between codecs keeps the data
+        let json = out.render(RenderFormat::DagJson).unwrap();
+        let decoded: Ipld = IpldCodec::DagJson.decode(&json).unwrap();
+        assert_eq!(decoded, ipld);
+        assert_eq!(out.render(RenderFormat::DagCbor).unwrap(), bytes);
+
+        let pretty = out.render(RenderFormat::PrettyJson).unwrap();
+        let decoded: Ipld = IpldCodec::DagJson.decode(&pretty).unwrap();
+        assert_eq!(decoded, ipld);
+        assert!(std::str::from_utf8(&pretty)
+            .unwrap()
+            .contains(&format!("  \"link\": {{\n    \"/\": \"{}\"\n  }}", link)));
+
+        let html = out.render(RenderFormat::Html).unwrap();
+        let html = std::str::from_utf8(&html).unwrap();
+        assert!(
+            html.contains(&format!("<a href=\"/ipfs/{link}\">")),
+            "{}",
+            html
+        );
+        assert!(html.contains("&lt;b&gt;bold&lt;/b&gt;"), "{}", html);
+        assert!(html.contains("bytes(2) dead"), "{}", html);
+        assert!(!html.contains("<b>"));
+    }
+}
@@ -1,23 +1,40 @@
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Display, Formatter, Write as _};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::SystemTime;
 
 use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use async_recursion::async_recursion;
 use async_trait::async_trait;
 use bytes::Bytes;
 use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
+use futures::stream::BoxStream;
 use iroh_rpc_client::Client;
 use libipld::codec::{Decode, Encode};
 use libipld::prelude::Codec as _;
 use libipld::{Ipld, IpldCodec};
-use tokio::io::AsyncRead;
-use tracing::{debug, trace, warn};
+use libp2p::PeerId;
+use tokio::io::{AsyncRead, AsyncSeek};
+use tracing::trace;
 
+pub use iroh_util::identity_content;
+
+use crate::budget::Budget;
 use crate::codecs::Codec;
-use crate::unixfs::{poll_read_buf_at_pos, DataType, LinkRef, UnixfsNode, UnixfsReader};
+use crate::dag_stat::{dag_stat, DagStat};
+use crate::decoders::{decode_dag_jose, decode_git_raw, decode_libp2p_key};
+use crate::dns_resolver::DnsResolver;
+use crate::ipns::{self, IpnsSource, RecordCache};
+use crate::loader_chain::{load_from_p2p, load_from_store, store_in_background};
+use crate::proof::{PathProof, RecordingLoader};
+use crate::render::{render_ipld, RenderFormat};
+use crate::unixfs::{
+    poll_read_buf_at_pos, seek_position, DataType, DirEntry, Link, LinkRef, ListOptions,
+    UnixfsNode, UnixfsReader,
+};
 
 /// Represents an ipfs path.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -69,7 +86,8 @@ impl Display for Path {
         write!(f, "/{}/{}", self.typ.as_str(), self.root)?;
 
         for part in &self.tail {
-            write!(f, "/{}", part)?;
+            f.write_char('/')?;
+            write_segment(f, part)?;
         }
 
         Ok(())
@@ -96,36 +114,200 @@ impl PathType {
 impl FromStr for Path {
     type Err = anyhow::Error;
 
+    /// Parses a path in any of the following forms:
+    ///
+    /// - `/ipfs/{cid}/{tail}`, `/ipns/{name}/{tail}` and bare `{cid}/{tail}`
+    /// - `ipfs://{cid}/{tail}`, `ipns://{name}/{tail}` and `dweb:/ipfs/{cid}/{tail}`
+    /// - `{cid}.ipfs.{host}/{tail}` and `{name}.ipns.{host}/{tail}`, optionally as
+    ///   `http(s)://` urls, as well as path gateway urls like `https://{host}/ipfs/{cid}`
+    ///
+    /// Percent-encoded segments are decoded.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(&['/', '\\']).filter(|s| !s.is_empty());
+        let s = s.trim();
+        if let Some(rest) = strip_prefix_ignore_ascii_case(s, "dweb:") {
+            return rest.parse();
+        }
+        for typ in [PathType::Ipfs, PathType::Ipns] {
+            let scheme = format!("{}://", typ.as_str());
+            if let Some(rest) = strip_prefix_ignore_ascii_case(s, &scheme) {
+                let rest = strip_query(rest);
+                let (root, tail) = rest.split_once('/').unwrap_or((rest, ""));
+                return Path::from_parts(typ, root, tail);
+            }
+        }
+        for scheme in ["http://", "https://"] {
+            if let Some(rest) = strip_prefix_ignore_ascii_case(s, scheme) {
+                let rest = strip_query(rest);
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                return match Path::from_subdomain(host, path)? {
+                    Some(path) => Ok(path),
+                    None => path.parse(),
+                };
+            }
+        }
+        if !s.starts_with(&['/', '\\']) {
+            let (host, path) = s.split_once('/').unwrap_or((s, ""));
+            if let Some(path) = Path::from_subdomain(host, path)? {
+                return Ok(path);
+            }
+        }
 
+        let mut parts = s.split(&['/', '\\']).filter(|s| !s.is_empty());
         let first_part = parts.next().ok_or_else(|| anyhow!("path too short"))?;
         let (typ, root) = if first_part.eq_ignore_ascii_case("ipns") {
             let root = parts.next().ok_or_else(|| anyhow!("path too short"))?;
-            let root = if let Ok(c) = Cid::from_str(root) {
-                CidOrDomain::Cid(c)
-            } else {
-                // TODO: url validation?
-                CidOrDomain::Domain(root.to_string())
-            };
-
             (PathType::Ipns, root)
+        } else if first_part.eq_ignore_ascii_case("ipfs") {
+            let root = parts.next().ok_or_else(|| anyhow!("path too short"))?;
+            (PathType::Ipfs, root)
         } else {
-            let root = if first_part.eq_ignore_ascii_case("ipfs") {
-                parts.next().ok_or_else(|| anyhow!("path too short"))?
-            } else {
-                first_part
-            };
+            (PathType::Ipfs, first_part)
+        };
+        let tail = parts.collect::<Vec<_>>().join("/");
 
-            let root = Cid::from_str(root).context("invalid cid")?;
+        Path::from_parts(typ, root, &tail)
+    }
+}
 
-            (PathType::Ipfs, CidOrDomain::Cid(root))
+impl Path {
+    /// Parses the subdomain gateway form, `{cid}.ipfs.{host}` or `{name}.ipns.{host}`.
+    ///
+    /// Returns `None` if the host is not in this form. DNSLink names in subdomains have
+    /// their `.` replaced by `-` and `-` by `--`, which is reverted.
+    pub fn from_subdomain(host: &str, path: &str) -> Result<Option<Self>> {
+        let labels: Vec<_> = host.split('.').collect();
+        if labels.len() < 3 {
+            return Ok(None);
+        }
+        let typ = if labels[1].eq_ignore_ascii_case("ipfs") {
+            PathType::Ipfs
+        } else if labels[1].eq_ignore_ascii_case("ipns") {
+            PathType::Ipns
+        } else {
+            return Ok(None);
         };
 
-        let tail = parts.map(Into::into).collect();
+        let mut root = labels[0].to_string();
+        if typ == PathType::Ipns && Cid::from_str(&root).is_err() && root.contains('-') {
+            root = root
+                .split("--")
+                .map(|part| part.replace('-', "."))
+                .collect::<Vec<_>>()
+                .join("-");
+        }
+        Path::from_parts(typ, &root, strip_query(path)).map(Some)
+    }
+
+    fn from_parts(typ: PathType, root: &str, tail: &str) -> Result<Self> {
+        let tail = tail
+            .split(&['/', '\\'])
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect();
+
+        Path::from_segments(typ, &percent_decode(root), tail)
+    }
+
+    /// Creates a path from a root and tail segments that are already decoded, like the
+    /// parameters of a gateway route. Unlike parsing, no percent-decoding is applied.
+    pub fn from_segments(typ: PathType, root: &str, tail: Vec<String>) -> Result<Self> {
+        let root = match typ {
+            PathType::Ipfs => CidOrDomain::Cid(Cid::from_str(root).context("invalid cid")?),
+            PathType::Ipns => match Cid::from_str(root) {
+                Ok(c) => CidOrDomain::Cid(c),
+                // TODO: url validation?
+                Err(_) => CidOrDomain::Domain(root.to_string()),
+            },
+        };
 
         Ok(Path { typ, root, tail })
     }
+
+    /// Returns the canonical form of this path.
+    ///
+    /// CIDv0 roots are converted to CIDv1, which are displayed in base32, and domains are
+    /// lowercased, so that equal content has a single representation.
+    pub fn to_canonical(&self) -> Path {
+        let root = match self.root {
+            CidOrDomain::Cid(c) => CidOrDomain::Cid(cid_to_v1(&c)),
+            CidOrDomain::Domain(ref domain) => {
+                CidOrDomain::Domain(domain.trim_end_matches('.').to_lowercase())
+            }
+        };
+
+        Path {
+            typ: self.typ,
+            root,
+            tail: self.tail.clone(),
+        }
+    }
+
+    /// Formats this path as `ipfs://` or `ipns://` uri.
+    pub fn to_uri(&self) -> String {
+        let path = self.to_string();
+        format!(
+            "{}:/{}",
+            self.typ.as_str(),
+            &path[self.typ.as_str().len() + 1..]
+        )
+    }
+}
+
+/// Converts CIDv0 to the equivalent CIDv1, other cids are returned as is.
+pub fn cid_to_v1(cid: &Cid) -> Cid {
+    cid.into_v1().unwrap_or(*cid)
+}
+
+fn strip_prefix_ignore_ascii_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+/// Removes the query and fragment of a url.
+fn strip_query(s: &str) -> &str {
+    s.split(&['?', '#']).next().unwrap_or_default()
+}
+
+/// Decodes `%XX` escapes, invalid escapes are kept as is.
+fn percent_decode(s: &str) -> String {
+    if !s.contains('%') {
+        return s.to_string();
+    }
+
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(out).unwrap_or_else(|_| s.to_string())
+}
+
+/// Writes a path segment, escaping the characters that would change how it is parsed.
+fn write_segment(f: &mut Formatter<'_>, segment: &str) -> fmt::Result {
+    for c in segment.chars() {
+        match c {
+            '%' => f.write_str("%25")?,
+            '/' => f.write_str("%2F")?,
+            '\\' => f.write_str("%5C")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -151,12 +333,38 @@ impl Out {
         self.content.typ()
     }
 
+    /// Returns the content in the IPLD data model, UnixFS nodes as their dag-pb form.
+    pub fn ipld(&self) -> Result<Ipld> {
+        match self.content {
+            OutContent::DagPb(ref ipld, _)
+            | OutContent::DagCbor(ref ipld, _)
+            | OutContent::DagJson(ref ipld, _)
+            | OutContent::Raw(ref ipld, _)
+            | OutContent::DagJose(ref ipld, _)
+            | OutContent::GitRaw(ref ipld, _)
+            | OutContent::Libp2pKey(ref ipld, _) => Ok(ipld.clone()),
+            OutContent::Unixfs(UnixfsNode::Raw { ref data }) => Ok(Ipld::Bytes(data.to_vec())),
+            OutContent::Unixfs(ref node) => IpldCodec::DagPb
+                .decode(&node.encode())
+                .map_err(|e| anyhow!("invalid dag pb: {:?}", e)),
+        }
+    }
+
+    /// Renders the content in the given format, converting between codecs as needed.
+    ///
+    /// Unlike [`Out::pretty`], this never returns the content of UnixFS files, only the
+    /// node itself.
+    pub fn render(&self, format: RenderFormat) -> Result<Bytes> {
+        render_ipld(&self.ipld()?, format, &self.metadata.path.to_string())
+    }
+
     /// Returns an iterator over the content of this directory.
-    /// Only if this is of type `unixfs` and a directory.
+    /// Only if this is of type `unixfs` and a flat directory, for sharded directories
+    /// use [`Out::unixfs_dir_entries`].
     pub fn unixfs_read_dir(&self) -> Option<impl Iterator<Item = Result<LinkRef<'_>>>> {
         match self.content {
             OutContent::Unixfs(ref node) => {
-                if node.is_dir() {
+                if node.typ() == Some(DataType::Directory) {
                     Some(node.links())
                 } else {
                     None
@@ -165,6 +373,31 @@ impl Out {
             _ => None,
         }
     }
+
+    /// Returns a stream over the entries of this directory, including sharded directories.
+    /// Only if this is of type `unixfs` and a directory.
+    pub fn unixfs_dir_entries<'a, T: ContentLoader + 'a>(
+        &'a self,
+        loader: T,
+    ) -> Option<BoxStream<'a, Result<Link>>> {
+        match self.content {
+            OutContent::Unixfs(ref node) => node.dir_entries(loader),
+            _ => None,
+        }
+    }
+
+    /// Returns a stream over the entries of this directory, including their type and size.
+    /// Only if this is of type `unixfs` and a directory.
+    pub fn unixfs_list_dir<'a, T: ContentLoader + 'a>(
+        &'a self,
+        loader: T,
+        options: ListOptions,
+    ) -> Option<BoxStream<'a, Result<DirEntry>>> {
+        match self.content {
+            OutContent::Unixfs(ref node) => node.list_dir(loader, options),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -174,6 +407,9 @@ enum OutContent {
     DagCbor(Ipld, Bytes),
     DagJson(Ipld, Bytes),
     Raw(Ipld, Bytes),
+    DagJose(Ipld, Bytes),
+    GitRaw(Ipld, Bytes),
+    Libp2pKey(Ipld, Bytes),
 }
 
 impl OutContent {
@@ -184,6 +420,9 @@ impl OutContent {
             OutContent::DagCbor(_, _) => OutType::DagCbor,
             OutContent::DagJson(_, _) => OutType::DagJson,
             OutContent::Raw(_, _) => OutType::Raw,
+            OutContent::DagJose(_, _) => OutType::DagJose,
+            OutContent::GitRaw(_, _) => OutType::GitRaw,
+            OutContent::Libp2pKey(_, _) => OutType::Libp2pKey,
         }
     }
 }
@@ -200,8 +439,12 @@ pub struct Metadata {
     /// List of mappings "path part" -> Cid.
     ///
     /// Only contains the "top level cids", and only path segments that actually map
-    /// to a block.
+    /// to a block. All loaded blocks are returned by [`Resolver::resolve_with_proof`].
     pub resolved_path: Vec<(String, Cid)>,
+    /// POSIX file mode, only available for UnixFS nodes that store it.
+    pub mode: Option<u32>,
+    /// Modification time, only available for UnixFS nodes that store it.
+    pub mtime: Option<SystemTime>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -211,6 +454,9 @@ pub enum OutType {
     DagCbor,
     DagJson,
     Raw,
+    DagJose,
+    GitRaw,
+    Libp2pKey,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -226,6 +472,9 @@ pub enum OutPrettyReader<T: ContentLoader> {
     DagCbor(usize, Bytes),
     DagJson(usize, Bytes),
     Raw(usize, Bytes),
+    DagJose(usize, Bytes),
+    GitRaw(usize, Bytes),
+    Libp2pKey(usize, Bytes),
 }
 
 impl Out {
@@ -236,6 +485,9 @@ impl Out {
             OutContent::DagCbor(_, bytes) => OutPrettyReader::DagCbor(pos, bytes),
             OutContent::DagJson(_, bytes) => OutPrettyReader::DagJson(pos, bytes),
             OutContent::Raw(_, bytes) => OutPrettyReader::Raw(pos, bytes),
+            OutContent::DagJose(_, bytes) => OutPrettyReader::DagJose(pos, bytes),
+            OutContent::GitRaw(_, bytes) => OutPrettyReader::GitRaw(pos, bytes),
+            OutContent::Libp2pKey(_, bytes) => OutPrettyReader::Libp2pKey(pos, bytes),
             OutContent::Unixfs(node) => OutPrettyReader::Unixfs(node.pretty(loader)),
         }
     }
@@ -251,7 +503,10 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for OutPrettyReader<T> {
             OutPrettyReader::DagPb(pos, content)
             | OutPrettyReader::DagCbor(pos, content)
             | OutPrettyReader::DagJson(pos, content)
-            | OutPrettyReader::Raw(pos, content) => {
+            | OutPrettyReader::Raw(pos, content)
+            | OutPrettyReader::DagJose(pos, content)
+            | OutPrettyReader::GitRaw(pos, content)
+            | OutPrettyReader::Libp2pKey(pos, content) => {
                 let res = poll_read_buf_at_pos(pos, content, buf);
                 Poll::Ready(res)
             }
@@ -260,11 +515,51 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for OutPrettyReader<T> {
     }
 }
 
+impl<T: ContentLoader + Unpin + 'static> AsyncSeek for OutPrettyReader<T> {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        match &mut *self {
+            OutPrettyReader::DagPb(pos, content)
+            | OutPrettyReader::DagCbor(pos, content)
+            | OutPrettyReader::DagJson(pos, content)
+            | OutPrettyReader::Raw(pos, content)
+            | OutPrettyReader::DagJose(pos, content)
+            | OutPrettyReader::GitRaw(pos, content)
+            | OutPrettyReader::Libp2pKey(pos, content) => {
+                *pos = seek_position(*pos, Some(content.len() as u64), position)?;
+                Ok(())
+            }
+            OutPrettyReader::Unixfs(r) => Pin::new(&mut *r).start_seek(position),
+        }
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        match &mut *self {
+            OutPrettyReader::DagPb(pos, _)
+            | OutPrettyReader::DagCbor(pos, _)
+            | OutPrettyReader::DagJson(pos, _)
+            | OutPrettyReader::Raw(pos, _)
+            | OutPrettyReader::DagJose(pos, _)
+            | OutPrettyReader::GitRaw(pos, _)
+            | OutPrettyReader::Libp2pKey(pos, _) => Poll::Ready(Ok(*pos as u64)),
+            OutPrettyReader::Unixfs(r) => Pin::new(&mut *r).poll_complete(cx),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Resolver<T: ContentLoader> {
     loader: T,
+    /// Source of IPNS records, if `None` IPNS names can not be resolved.
+    ipns: Option<Arc<dyn IpnsSource>>,
+    ipns_cache: Arc<RecordCache>,
+    dns_resolver: Arc<DnsResolver>,
+    /// Follow a symlink at the end of the path, instead of returning the symlink itself.
+    follow_final_symlink: bool,
 }
 
+/// Maximum number of symlinks followed while resolving a single path.
+pub const MAX_SYMLINK_HOPS: usize = 32;
+
 #[async_trait]
 pub trait ContentLoader: Sync + Send + std::fmt::Debug + Clone {
     /// Loads the actual content of a given cid.
@@ -289,61 +584,18 @@ impl<'a, T: ContentLoader> ContentLoader for &'a T {
 impl ContentLoader for Client {
     async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
         trace!("loading cid");
+        if let Some(bytes) = identity_content(cid) {
+            return Ok(bytes);
+        }
         // TODO: better strategy
 
-        let cid = *cid;
-        match self.store.get(cid).await {
-            Ok(Some(data)) => {
-                trace!("retrieved from store");
-                return Ok(data);
-            }
-            Ok(None) => {}
-            Err(err) => {
-                warn!("failed to fetch data from store {}: {:?}", cid, err);
-            }
+        if let Some(bytes) = load_from_store(self, cid).await {
+            return Ok(bytes);
         }
 
-        let providers = self.p2p.fetch_providers(&cid).await?;
-        let bytes = self.p2p.fetch_bitswap(cid, providers).await?;
-
+        let bytes = load_from_p2p(self, cid).await?;
         // TODO: is this the right place?
-        // verify cid
-        let bytes_clone = bytes.clone();
-        match tokio::task::spawn_blocking(move || verify_hash(&cid, &bytes_clone)).await? {
-            Some(true) => {
-                // all good
-            }
-            Some(false) => {
-                bail!("invalid hash {:?}", cid.hash());
-            }
-            None => {
-                warn!(
-                    "unable to verify hash, unknown hash function {} for {}",
-                    cid.hash().code(),
-                    cid
-                );
-            }
-        }
-
-        // trigger storage in the background
-        let cloned = bytes.clone();
-        let rpc = self.clone();
-        tokio::spawn(async move {
-            let clone2 = cloned.clone();
-            let links =
-                tokio::task::spawn_blocking(move || parse_links(&cid, &clone2).unwrap_or_default())
-                    .await
-                    .unwrap_or_default();
-
-            let len = cloned.len();
-            let links_len = links.len();
-            match rpc.store.put(cid, cloned, links).await {
-                Ok(_) => debug!("stored {} ({}bytes, {}links)", cid, len, links_len),
-                Err(err) => {
-                    warn!("failed to store {}: {:?}", cid, err);
-                }
-            }
-        });
+        store_in_background(self.clone(), *cid, bytes.clone());
 
         trace!("retrieved from p2p");
 
@@ -353,74 +605,273 @@ impl ContentLoader for Client {
 
 impl<T: ContentLoader> Resolver<T> {
     pub fn new(loader: T) -> Self {
-        Resolver { loader }
+        Resolver {
+            loader,
+            ipns: None,
+            ipns_cache: Default::default(),
+            dns_resolver: Default::default(),
+            follow_final_symlink: false,
+        }
+    }
+
+    /// Creates a resolver that is able to resolve IPNS names, using the given record source.
+    pub fn with_ipns<I: IpnsSource + 'static>(loader: T, ipns: I) -> Self {
+        Resolver {
+            loader,
+            ipns: Some(Arc::new(ipns)),
+            ipns_cache: Default::default(),
+            dns_resolver: Default::default(),
+            follow_final_symlink: false,
+        }
+    }
+
+    /// Uses the given resolver for DNSLink lookups, instead of one with the default config.
+    pub fn with_dns_resolver(mut self, dns_resolver: DnsResolver) -> Self {
+        self.dns_resolver = Arc::new(dns_resolver);
+        self
+    }
+
+    /// Whether a symlink at the end of the path is followed as well.
+    ///
+    /// Symlinks in the middle of a path are always followed. By default the final symlink
+    /// node itself is returned, so its target can be inspected.
+    pub fn follow_final_symlink(mut self, follow: bool) -> Self {
+        self.follow_final_symlink = follow;
+        self
+    }
+
+    pub fn loader(&self) -> &T {
+        &self.loader
     }
 
     /// Resolves through a given path, returning the [`Cid`] and raw bytes of the final leaf.
     #[tracing::instrument(skip(self))]
     pub async fn resolve(&self, path: Path) -> Result<Out> {
         // Resolve the root block.
-        let (root_cid, root_bytes) = self.resolve_root(&path).await?;
+        let (root_cid, root_bytes, tail) = self.resolve_root(&path).await?;
+        let resolved_path = vec![(root_cid.to_string(), root_cid)];
+
+        self.resolve_node(path, &tail, root_cid, root_bytes, resolved_path)
+            .await
+    }
+
+    /// Resolves the path within the limits of the given budget.
+    ///
+    /// Fails with [`DeadlineExceeded`](crate::budget::DeadlineExceeded) if the deadline passes
+    /// before the path is resolved. To read the content within the same limits, use a loader
+    /// created by [`Budget::loader`].
+    #[tracing::instrument(skip(self, budget))]
+    pub async fn resolve_with_budget(&self, path: Path, budget: &Budget) -> Result<Out> {
+        let resolver = self.with_loader(budget.loader(self.loader.clone()));
+        budget.run(None, resolver.resolve(path)).await
+    }
 
-        let codec = Codec::try_from(root_cid.codec()).context("unknown codec")?;
+    /// Resolves the path, additionally returning every block that was needed to resolve it.
+    ///
+    /// The [`PathProof`] can be used to verify the resolution offline, or to be sent along
+    /// with the content as a CAR file.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve_with_proof(&self, path: Path) -> Result<(Out, PathProof)> {
+        let loader = RecordingLoader::new(self.loader.clone());
+        let resolver = self.with_loader(loader.clone());
+        let (root_cid, root_bytes, tail) = resolver.resolve_root(&path).await?;
+        let mut ipfs_path = Path::from_cid(root_cid);
+        ipfs_path.tail = tail.clone();
+        let resolved_path = vec![(root_cid.to_string(), root_cid)];
+
+        let out = resolver
+            .resolve_node(path, &tail, root_cid, root_bytes, resolved_path)
+            .await?;
+        Ok((out, PathProof::new(ipfs_path, loader.blocks())))
+    }
+
+    /// Walks all blocks reachable from `root`, reporting their number, size and depth.
+    pub async fn dag_stat(&self, root: Cid) -> Result<DagStat> {
+        dag_stat(&self.loader, root).await
+    }
+
+    /// Creates a resolver with the same settings and caches, using a different loader.
+    fn with_loader<U: ContentLoader>(&self, loader: U) -> Resolver<U> {
+        Resolver {
+            loader,
+            ipns: self.ipns.clone(),
+            ipns_cache: self.ipns_cache.clone(),
+            dns_resolver: self.dns_resolver.clone(),
+            follow_final_symlink: self.follow_final_symlink,
+        }
+    }
+
+    /// Resolves the remaining path, starting at the given block, depending on its codec.
+    #[async_recursion]
+    async fn resolve_node(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
+        let codec = Codec::try_from(cid.codec()).context("unknown codec")?;
         match codec {
             Codec::DagPb => {
-                self.resolve_dag_pb_or_unixfs(path, root_cid, root_bytes)
+                self.resolve_dag_pb_or_unixfs(root_path, tail, cid, bytes, resolved_path)
+                    .await
+            }
+            Codec::DagCbor => {
+                self.resolve_dag_cbor(root_path, tail, cid, bytes, resolved_path)
+                    .await
+            }
+            Codec::DagJson => {
+                self.resolve_dag_json(root_path, tail, cid, bytes, resolved_path)
+                    .await
+            }
+            Codec::Raw => {
+                self.resolve_raw(root_path, tail, cid, bytes, resolved_path)
+                    .await
+            }
+            Codec::DagJose | Codec::GitRaw | Codec::Libp2pKey => {
+                self.resolve_decoded(root_path, tail, cid, bytes, resolved_path)
                     .await
             }
-            Codec::DagCbor => self.resolve_dag_cbor(path, root_cid, root_bytes).await,
-            Codec::DagJson => self.resolve_dag_json(path, root_cid, root_bytes).await,
-            Codec::Raw => self.resolve_raw(path, root_cid, root_bytes).await,
             _ => bail!("unsupported codec {:?}", codec),
         }
     }
 
-    async fn inner_resolve(
+    /// Continues resolving the path in the linked block, which can be of any codec.
+    async fn resolve_link(
         &self,
-        current: &mut UnixfsNode,
-        resolved_path: &mut Vec<(String, Cid)>,
-        part: &str,
-    ) -> Result<()> {
-        match current.typ() {
-            Some(DataType::Directory) => {
-                let next_link = current
-                    .get_link_by_name(&part)
-                    .await?
-                    .ok_or_else(|| anyhow!("link {} not found", part))?;
-                let next_bytes = self.load_cid(&next_link.cid).await?;
-                let next_node = UnixfsNode::decode(&next_link.cid, next_bytes)?;
-                resolved_path.push((part.to_string(), next_link.cid));
+        root_path: Path,
+        tail: &[String],
+        link: Cid,
+        mut resolved_path: Vec<(String, Cid)>,
+        link_path: String,
+    ) -> Result<Out> {
+        let bytes = self.load_cid(&link).await?;
+        resolved_path.push((link_path, link));
 
-                *current = next_node;
-            }
+        self.resolve_node(root_path, tail, link, bytes, resolved_path)
+            .await
+    }
+
+    /// Finds the link named `part` in the given directory.
+    async fn inner_resolve(&self, current: &UnixfsNode, part: &str) -> Result<Link> {
+        match current.typ() {
+            Some(DataType::Directory) | Some(DataType::HamtShard) => current
+                .get_link_by_name(&self.loader, part)
+                .await?
+                .ok_or_else(|| anyhow!("link {} not found", part)),
             ty => {
                 bail!("unexpected unixfs type {:?}", ty);
             }
         }
-
-        Ok(())
     }
 
     /// Resolves through both DagPb and nested UnixFs DAGs.
+    ///
+    /// Symlinks are followed when more path segments remain. Relative targets are resolved
+    /// against the directory containing the symlink, `/ipfs/` and `/ipns/` targets start a new
+    /// walk from their root and any other absolute target is resolved against the root of the
+    /// current walk.
     #[tracing::instrument(skip(self, bytes))]
     async fn resolve_dag_pb_or_unixfs(
         &self,
         root_path: Path,
+        tail: &[String],
         cid: Cid,
         bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
     ) -> Result<Out> {
-        if let Ok(node) = UnixfsNode::decode(&cid, bytes.clone()) {
-            let tail = &root_path.tail;
-            let mut current = node;
-            let mut resolved_path = vec![(root_path.root.to_string(), cid)];
-
-            for part in tail {
-                self.inner_resolve(&mut current, &mut resolved_path, part)
-                    .await?;
+        let node = match UnixfsNode::decode(&cid, bytes.clone()) {
+            Ok(node) => node,
+            Err(_) => {
+                return self
+                    .resolve_dag_pb(root_path, tail, cid, bytes, resolved_path)
+                    .await;
+            }
+        };
+
+        // the walk restarts here, whenever a symlink is followed
+        let mut root = node;
+        let mut root_resolved_path = resolved_path;
+        let mut tail = tail.to_vec();
+        let mut hops = 0;
+
+        'walk: loop {
+            let mut current = root.clone();
+            let mut resolved_path = root_resolved_path.clone();
+
+            for i in 0..=tail.len() {
+                let is_last = i == tail.len();
+                if current.typ() == Some(DataType::Symlink)
+                    && (!is_last || self.follow_final_symlink)
+                {
+                    hops += 1;
+                    ensure!(
+                        hops <= MAX_SYMLINK_HOPS,
+                        "too many levels of symbolic links in {}",
+                        root_path
+                    );
+                    let target = current
+                        .symlink()?
+                        .ok_or_else(|| anyhow!("missing symlink target"))?
+                        .to_string();
+                    let rest = &tail[i..];
+
+                    if target.starts_with("/ipfs/") || target.starts_with("/ipns/") {
+                        let mut path: Path = target.parse()?;
+                        path.tail.extend_from_slice(rest);
+                        let (cid, bytes, next_tail) = self.resolve_root(&path).await?;
+                        let next_resolved_path = vec![(cid.to_string(), cid)];
+                        if is_unixfs_codec(&cid) {
+                            if let Ok(node) = UnixfsNode::decode(&cid, bytes.clone()) {
+                                root = node;
+                                root_resolved_path = next_resolved_path;
+                                tail = next_tail;
+                                continue 'walk;
+                            }
+                        }
+                        return self
+                            .resolve_node(root_path, &next_tail, cid, bytes, next_resolved_path)
+                            .await;
+                    }
+
+                    let base = if target.starts_with('/') {
+                        &[][..]
+                    } else {
+                        ensure!(i > 0, "relative symlink {} has no parent directory", target);
+                        &tail[..i - 1]
+                    };
+                    let mut next_tail = join_symlink_target(base, &target)?;
+                    next_tail.extend_from_slice(rest);
+                    tail = next_tail;
+                    continue 'walk;
+                }
+                if is_last {
+                    break;
+                }
+
+                let part = &tail[i];
+                let next_link = self.inner_resolve(&current, part).await?;
+                if !is_unixfs_codec(&next_link.cid) {
+                    // leaving unixfs, continue with the codec of the linked block
+                    return self
+                        .resolve_link(
+                            root_path,
+                            &tail[i + 1..],
+                            next_link.cid,
+                            resolved_path,
+                            part.to_string(),
+                        )
+                        .await;
+                }
+
+                let next_bytes = self.load_cid(&next_link.cid).await?;
+                current = UnixfsNode::decode(&next_link.cid, next_bytes)?;
+                resolved_path.push((part.to_string(), next_link.cid));
             }
 
             let unixfs_type = current.typ().and_then(|t| match t {
-                DataType::Directory => Some(UnixfsType::Dir),
+                DataType::Directory | DataType::HamtShard => Some(UnixfsType::Dir),
                 DataType::File | DataType::Raw => Some(UnixfsType::File),
                 DataType::Symlink => Some(UnixfsType::Symlink),
                 _ => None,
@@ -431,28 +882,46 @@ impl<T: ContentLoader> Resolver<T> {
                 typ: OutType::Unixfs,
                 unixfs_type,
                 resolved_path,
+                mode: current.mode(),
+                mtime: current.mtime(),
             };
-            Ok(Out {
+            return Ok(Out {
                 metadata,
                 content: OutContent::Unixfs(current),
-            })
-        } else {
-            self.resolve_dag_pb(root_path, cid, bytes).await
+            });
         }
     }
 
     #[tracing::instrument(skip(self, bytes))]
-    async fn resolve_dag_pb(&self, root_path: Path, cid: Cid, bytes: Bytes) -> Result<Out> {
+    async fn resolve_dag_pb(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
         let ipld: libipld::Ipld = libipld::IpldCodec::DagPb
             .decode(&bytes)
             .map_err(|e| anyhow!("invalid dag cbor: {:?}", e))?;
 
-        let out = self
-            .resolve_ipld(cid, libipld::IpldCodec::DagPb, ipld, &root_path.tail)
-            .await?;
+        let out = match resolve_ipld(&ipld, tail)? {
+            IpldResolved::Value(out) => out,
+            IpldResolved::Link(link, i) => {
+                return self
+                    .resolve_link(
+                        root_path,
+                        &tail[i..],
+                        link,
+                        resolved_path,
+                        tail[..i].join("/"),
+                    )
+                    .await;
+            }
+        };
 
         // reencode if we only return part of the original
-        let bytes = if root_path.tail.is_empty() {
+        let bytes = if tail.is_empty() {
             bytes
         } else {
             let mut bytes = Vec::new();
@@ -465,7 +934,9 @@ impl<T: ContentLoader> Resolver<T> {
             size: Some(bytes.len()),
             typ: OutType::DagPb,
             unixfs_type: None,
-            resolved_path: vec![(cid.to_string(), cid)],
+            resolved_path,
+            mode: None,
+            mtime: None,
         };
         Ok(Out {
             metadata,
@@ -474,17 +945,35 @@ impl<T: ContentLoader> Resolver<T> {
     }
 
     #[tracing::instrument(skip(self, bytes))]
-    async fn resolve_dag_cbor(&self, root_path: Path, cid: Cid, bytes: Bytes) -> Result<Out> {
+    async fn resolve_dag_cbor(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
         let ipld: libipld::Ipld = libipld::IpldCodec::DagCbor
             .decode(&bytes)
             .map_err(|e| anyhow!("invalid dag cbor: {:?}", e))?;
 
-        let out = self
-            .resolve_ipld(cid, libipld::IpldCodec::DagCbor, ipld, &root_path.tail)
-            .await?;
+        let out = match resolve_ipld(&ipld, tail)? {
+            IpldResolved::Value(out) => out,
+            IpldResolved::Link(link, i) => {
+                return self
+                    .resolve_link(
+                        root_path,
+                        &tail[i..],
+                        link,
+                        resolved_path,
+                        tail[..i].join("/"),
+                    )
+                    .await;
+            }
+        };
 
         // reencode if we only return part of the original
-        let bytes = if root_path.tail.is_empty() {
+        let bytes = if tail.is_empty() {
             bytes
         } else {
             let mut bytes = Vec::new();
@@ -497,7 +986,9 @@ impl<T: ContentLoader> Resolver<T> {
             size: Some(bytes.len()),
             typ: OutType::DagCbor,
             unixfs_type: None,
-            resolved_path: vec![(cid.to_string(), cid)],
+            resolved_path,
+            mode: None,
+            mtime: None,
         };
         Ok(Out {
             metadata,
@@ -506,17 +997,35 @@ impl<T: ContentLoader> Resolver<T> {
     }
 
     #[tracing::instrument(skip(self, bytes))]
-    async fn resolve_dag_json(&self, root_path: Path, cid: Cid, bytes: Bytes) -> Result<Out> {
+    async fn resolve_dag_json(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
         let ipld: libipld::Ipld = libipld::IpldCodec::DagJson
             .decode(&bytes)
             .map_err(|e| anyhow!("invalid dag json: {:?}", e))?;
 
-        let out = self
-            .resolve_ipld(cid, libipld::IpldCodec::DagJson, ipld, &root_path.tail)
-            .await?;
+        let out = match resolve_ipld(&ipld, tail)? {
+            IpldResolved::Value(out) => out,
+            IpldResolved::Link(link, i) => {
+                return self
+                    .resolve_link(
+                        root_path,
+                        &tail[i..],
+                        link,
+                        resolved_path,
+                        tail[..i].join("/"),
+                    )
+                    .await;
+            }
+        };
 
         // reencode if we only return part of the original
-        let bytes = if root_path.tail.is_empty() {
+        let bytes = if tail.is_empty() {
             bytes
         } else {
             let mut bytes = Vec::new();
@@ -529,7 +1038,9 @@ impl<T: ContentLoader> Resolver<T> {
             size: Some(bytes.len()),
             typ: OutType::DagJson,
             unixfs_type: None,
-            resolved_path: vec![(cid.to_string(), cid)],
+            resolved_path,
+            mode: None,
+            mtime: None,
         };
         Ok(Out {
             metadata,
@@ -537,22 +1048,90 @@ impl<T: ContentLoader> Resolver<T> {
         })
     }
 
+    /// Resolves through codecs that are decoded by [`decode_ipld`], without a dedicated
+    /// encoder. Parts of a block are returned encoded as dag-cbor.
+    #[tracing::instrument(skip(self, bytes))]
+    async fn resolve_decoded(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
+        let ipld = decode_ipld(&cid, &bytes)?;
+
+        let out = match resolve_ipld(&ipld, tail)? {
+            IpldResolved::Value(out) => out,
+            IpldResolved::Link(link, i) => {
+                return self
+                    .resolve_link(
+                        root_path,
+                        &tail[i..],
+                        link,
+                        resolved_path,
+                        tail[..i].join("/"),
+                    )
+                    .await;
+            }
+        };
+
+        // reencode if we only return part of the original
+        let bytes = if tail.is_empty() {
+            bytes
+        } else {
+            let mut bytes = Vec::new();
+            out.encode(libipld::IpldCodec::DagCbor, &mut bytes)?;
+            bytes.into()
+        };
+
+        let (typ, content) = match Codec::try_from(cid.codec())? {
+            Codec::DagJose => (OutType::DagJose, OutContent::DagJose(out, bytes.clone())),
+            Codec::GitRaw => (OutType::GitRaw, OutContent::GitRaw(out, bytes.clone())),
+            Codec::Libp2pKey => (
+                OutType::Libp2pKey,
+                OutContent::Libp2pKey(out, bytes.clone()),
+            ),
+            codec => bail!("unsupported codec {:?}", codec),
+        };
+        let metadata = Metadata {
+            path: root_path,
+            size: Some(bytes.len()),
+            typ,
+            unixfs_type: None,
+            resolved_path,
+            mode: None,
+            mtime: None,
+        };
+        Ok(Out { metadata, content })
+    }
+
     #[tracing::instrument(skip(self, bytes))]
-    async fn resolve_raw(&self, root_path: Path, cid: Cid, bytes: Bytes) -> Result<Out> {
+    async fn resolve_raw(
+        &self,
+        root_path: Path,
+        tail: &[String],
+        cid: Cid,
+        bytes: Bytes,
+        resolved_path: Vec<(String, Cid)>,
+    ) -> Result<Out> {
         let ipld: libipld::Ipld = libipld::IpldCodec::Raw
             .decode(&bytes)
             .map_err(|e| anyhow!("invalid raw: {:?}", e))?;
 
-        let out = self
-            .resolve_ipld(cid, libipld::IpldCodec::Raw, ipld, &root_path.tail)
-            .await?;
+        let out = match resolve_ipld(&ipld, tail)? {
+            IpldResolved::Value(out) => out,
+            IpldResolved::Link(..) => unreachable!("raw blocks contain no links"),
+        };
 
         let metadata = Metadata {
             path: root_path,
             size: Some(bytes.len()),
             typ: OutType::Raw,
             unixfs_type: None,
-            resolved_path: vec![(cid.to_string(), cid)],
+            resolved_path,
+            mode: None,
+            mtime: None,
         };
         Ok(Out {
             metadata,
@@ -560,77 +1139,47 @@ impl<T: ContentLoader> Resolver<T> {
         })
     }
 
+    /// Resolves the root of the given path to an `/ipfs` block.
+    ///
+    /// Returns the root [`Cid`], its content and the remaining path, which includes any
+    /// path segments that were part of resolved IPNS or DNSLink values.
     #[tracing::instrument(skip(self))]
-    async fn resolve_ipld(
-        &self,
-        _cid: Cid,
-        codec: libipld::IpldCodec,
-        root: Ipld,
-        path: &[String],
-    ) -> Result<Ipld> {
-        let mut root = root;
-        let mut current = &root;
-
-        for part in path {
-            if let libipld::Ipld::Link(c) = current {
-                let c = *c;
-                let new_codec: libipld::IpldCodec = c.codec().try_into()?;
-                ensure!(
-                    new_codec == codec,
-                    "can only resolve the same codec {:?} != {:?}",
-                    new_codec,
-                    codec
-                );
-
-                // resolve link and update if we have encountered a link
-                let bytes = self.load_cid(&c).await?;
-                root = codec
-                    .decode(&bytes)
-                    .map_err(|e| anyhow!("invalid dag json: {:?}", e))?;
-                current = &root;
-            }
-
-            let index: libipld::ipld::IpldIndex = if let Ok(i) = part.parse::<usize>() {
-                i.into()
-            } else {
-                part.clone().into()
-            };
-
-            current = current.get(index)?;
-        }
-
-        // TODO: can we avoid this clone?
-
-        Ok(current.clone())
-    }
-
-    #[tracing::instrument(skip(self))]
-    async fn resolve_root(&self, root: &Path) -> Result<(Cid, Bytes)> {
+    async fn resolve_root(&self, root: &Path) -> Result<(Cid, Bytes, Vec<String>)> {
         let mut current = root.clone();
 
         // maximum cursion of ipns lookups
         const MAX_LOOKUPS: usize = 16;
 
         for _ in 0..MAX_LOOKUPS {
-            match current.typ {
+            let mut next = match current.typ {
                 PathType::Ipfs => match current.root {
-                    CidOrDomain::Cid(ref c) => return Ok((*c, self.load_cid(c).await?)),
+                    CidOrDomain::Cid(c) => {
+                        let bytes = self.load_cid(&c).await?;
+                        return Ok((c, bytes, current.tail));
+                    }
                     CidOrDomain::Domain(_) => bail!("invalid domain encountered"),
                 },
                 PathType::Ipns => match current.root {
                     CidOrDomain::Cid(ref c) => {
-                        let c = self.load_ipns_record(c).await?;
-                        current = Path::from_cid(c);
+                        let peer_id = ipns::peer_id_from_cid(c)?;
+                        self.load_ipns_record(&peer_id).await?
                     }
                     CidOrDomain::Domain(ref domain) => {
-                        let mut records = resolve_dnslink(domain).await?;
-                        if records.is_empty() {
-                            bail!("no valid dnslink records found for {}", domain);
+                        if let Ok(peer_id) = PeerId::from_str(domain) {
+                            self.load_ipns_record(&peer_id).await?
+                        } else {
+                            let mut records = self.dns_resolver.resolve_dnslink(domain).await?;
+                            if records.is_empty() {
+                                bail!("no valid dnslink records found for {}", domain);
+                            }
+                            records.remove(0)
                         }
-                        current = records.remove(0);
                     }
                 },
-            }
+            };
+            // keep the remaining path segments
+            next.tail.extend_from_slice(&current.tail);
+            current = next;
         }
 
         bail!("cannot resolve {}, too many recursive lookups", root);
@@ -638,69 +1187,129 @@ impl<T: ContentLoader> Resolver<T> {
 
     #[tracing::instrument(skip(self))]
     async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+        if let Some(bytes) = identity_content(cid) {
+            return Ok(bytes);
+        }
         self.loader.load_cid(cid).await
     }
 
+    /// Fetches and verifies the IPNS record for the given peer, returning the path it points to.
     #[tracing::instrument(skip(self))]
-    async fn load_ipns_record(&self, cid: &Cid) -> Result<Cid> {
-        todo!()
+    async fn load_ipns_record(&self, peer_id: &PeerId) -> Result<Path> {
+        if let Some(path) = self.ipns_cache.get(peer_id) {
+            trace!("ipns record cache hit");
+            return Ok(path);
+        }
+
+        let source = self
+            .ipns
+            .as_ref()
+            .ok_or_else(|| anyhow!("no ipns source configured, can not resolve {}", peer_id))?;
+        let records = source.get_records(peer_id).await?;
+        let record = ipns::select_best_record(peer_id, &records)?;
+        self.ipns_cache.insert(*peer_id, &record);
+
+        Ok(record.value().clone())
+    }
+}
+
+/// Result of resolving a path inside a single IPLD block.
+#[derive(Debug, Clone, PartialEq)]
+enum IpldResolved {
+    /// The path ends inside the block.
+    Value(Ipld),
+    /// The path continues in the linked block, after the given number of path segments.
+    Link(Cid, usize),
+}
+
+/// Resolves the path inside the given IPLD node, stopping at the first link that needs
+/// to be followed.
+fn resolve_ipld(root: &Ipld, path: &[String]) -> Result<IpldResolved> {
+    let mut current = root;
+
+    for (i, part) in path.iter().enumerate() {
+        if let Ipld::Link(c) = current {
+            return Ok(IpldResolved::Link(*c, i));
+        }
+
+        let index: libipld::ipld::IpldIndex = if let Ok(i) = part.parse::<usize>() {
+            i.into()
+        } else {
+            part.clone().into()
+        };
+
+        current = current.get(index)?;
+    }
+
+    // TODO: can we avoid this clone?
+
+    Ok(IpldResolved::Value(current.clone()))
+}
+
+/// Blocks with these codecs are resolved as part of a UnixFS DAG.
+fn is_unixfs_codec(cid: &Cid) -> bool {
+    cid.codec() == Codec::DagPb as u64 || cid.codec() == Codec::Raw as u64
+}
+
+/// Joins a symlink target onto the segments of the directory containing the symlink.
+///
+/// Fails if the target leaves the root of the walk through `..`.
+fn join_symlink_target(dir: &[String], target: &str) -> Result<Vec<String>> {
+    let mut out = dir.to_vec();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                ensure!(
+                    out.pop().is_some(),
+                    "symlink target {} is out of bounds",
+                    target
+                );
+            }
+            part => out.push(part.to_string()),
+        }
     }
+    Ok(out)
 }
 
 /// Extract links from the given content.
 pub fn parse_links(cid: &Cid, bytes: &[u8]) -> Result<Vec<Cid>> {
+    let decoded = decode_ipld(cid, bytes)?;
+    let mut links = Vec::new();
+    decoded.references(&mut links);
+
+    Ok(links)
+}
+
+/// Decodes the given content into the IPLD data model, using the codec of the [`Cid`].
+pub(crate) fn decode_ipld(cid: &Cid, bytes: &[u8]) -> Result<Ipld> {
     let codec = Codec::try_from(cid.codec()).context("unknown codec")?;
     let codec = match codec {
         Codec::DagPb => IpldCodec::DagPb,
         Codec::DagCbor => IpldCodec::DagCbor,
         Codec::DagJson => IpldCodec::DagJson,
         Codec::Raw => IpldCodec::Raw,
+        Codec::DagJose => return decode_dag_jose(bytes),
+        Codec::GitRaw => return decode_git_raw(bytes),
+        Codec::Libp2pKey => return decode_libp2p_key(bytes),
         _ => bail!("unsupported codec {:?}", codec),
-    };
-
-    let decoded: Ipld = Ipld::decode(codec, &mut std::io::Cursor::new(bytes))?;
-    let mut links = Vec::new();
-    decoded.references(&mut links);
+    };
 
-    Ok(links)
+    let decoded = Ipld::decode(codec, &mut std::io::Cursor::new(bytes))?;
+    Ok(decoded)
 }
 
 /// Verifies that the provided bytes hash to the given multihash.
 pub fn verify_hash(cid: &Cid, bytes: &[u8]) -> Option<bool> {
+    if let Some(content) = identity_content(cid) {
+        return Some(content == bytes);
+    }
     Code::try_from(cid.hash().code()).ok().map(|code| {
         let calculated_hash = code.digest(bytes);
         &calculated_hash == cid.hash()
     })
 }
 
-#[tracing::instrument]
-async fn resolve_dnslink(url: &str) -> Result<Vec<Path>> {
-    let url = format!("_dnslink.{}.", url);
-    let records = resolve_txt_record(&url).await?;
-    let records = records
-        .into_iter()
-        .filter(|r| r.starts_with("dnslink="))
-        .map(|r| {
-            let p = r.trim_start_matches("dnslink=").trim();
-            p.parse()
-        })
-        .collect::<Result<_>>()?;
-    Ok(records)
-}
-
-async fn resolve_txt_record(url: &str) -> Result<Vec<String>> {
-    use trust_dns_resolver::config::*;
-    use trust_dns_resolver::AsyncResolver;
-
-    // Construct a new Resolver with default configuration options
-    let resolver = AsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())?;
-
-    let txt_response = resolver.txt_lookup(url).await?;
-
-    let out = txt_response.into_iter().map(|r| r.to_string()).collect();
-    Ok(out)
-}
-
 #[cfg(test)]
 mod tests {
     use std::{
@@ -709,9 +1318,14 @@ mod tests {
     };
 
     use super::*;
+    use crate::test_utils::{
+        collect_blocks, fixture_file, load_fixture, load_fixture_dag, resolver_for,
+    };
     use cid::multihash::{Code, MultihashDigest};
+    use futures::TryStreamExt;
     use libipld::{codec::Encode, Ipld, IpldCodec};
-    use tokio::io::AsyncReadExt;
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
     #[async_trait]
     impl ContentLoader for HashMap<Cid, Bytes> {
@@ -723,10 +1337,6 @@ mod tests {
         }
     }
 
-    async fn load_fixture(p: &str) -> Bytes {
-        Bytes::from(tokio::fs::read(format!("./fixtures/{p}")).await.unwrap())
-    }
-
     async fn read_to_vec<T: AsyncRead + Unpin>(mut reader: T) -> Vec<u8> {
         let mut out = Vec::new();
         reader.read_to_end(&mut out).await.unwrap();
@@ -753,10 +1363,41 @@ mod tests {
             assert_eq!(p.to_string(), test);
         }
 
-        let valid_tests = [(
-            "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
-            "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
-        )];
+        let valid_tests = [
+            (
+                "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
+            ),
+            (
+                "ipfs://bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/bar?x=1",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/bar",
+            ),
+            ("IPNS://ipfs.io/docs/", "/ipns/ipfs.io/docs"),
+            (
+                "dweb:/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy",
+            ),
+            (
+                "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy.ipfs.localhost:8080/a/b",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/a/b",
+            ),
+            (
+                "https://en-wikipedia--on--ipfs-org.ipns.dweb.link/wiki/#top",
+                "/ipns/en.wikipedia-on-ipfs.org/wiki",
+            ),
+            (
+                "https://ipfs.io/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/c",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/c",
+            ),
+            (
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/hello%20world/100%",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/hello world/100%25",
+            ),
+            (
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/a%2Fb",
+                "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/a%2Fb",
+            ),
+        ];
         for (test_in, test_out) in valid_tests {
             println!("{}", test_in);
             let p: Path = test_in.parse().unwrap();
@@ -768,11 +1409,50 @@ mod tests {
             "bla",
             "/bla/blub",
             "/ipfs/ipfs.io",
+            "ipfs://ipfs.io",
+            "foo.ipfs.localhost",
+            "https://ipfs.io",
         ];
         for test in invalid_tests {
             println!("{}", test);
             assert!(test.parse::<Path>().is_err());
         }
+
+        let p: Path = "/ipfs/bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy/a%2Fb"
+            .parse()
+            .unwrap();
+        assert_eq!(p.tail(), &["a/b".to_string()]);
+        assert_eq!(p.to_string().parse::<Path>().unwrap(), p);
+
+        // decoded segments are kept as is and escaped when formatted
+        let root = "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy";
+        let p = Path::from_segments(PathType::Ipfs, root, vec!["100%25.txt".to_string()]).unwrap();
+        assert_eq!(p.tail(), &["100%25.txt".to_string()]);
+        assert_eq!(p.to_string(), format!("/ipfs/{root}/100%2525.txt"));
+        assert_eq!(p.to_string().parse::<Path>().unwrap(), p);
+        assert!(Path::from_segments(PathType::Ipfs, "ipfs.io", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_canonical_paths() {
+        let v0 = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+        let v1 = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+        let p: Path = format!("/ipfs/{v0}/foo").parse().unwrap();
+        assert_eq!(p.to_string(), format!("/ipfs/{v0}/foo"));
+        let canonical = p.to_canonical();
+        assert_eq!(canonical.to_string(), format!("/ipfs/{v1}/foo"));
+        assert_eq!(canonical.to_uri(), format!("ipfs://{v1}/foo"));
+        assert_eq!(canonical.to_canonical(), canonical);
+
+        // base58 and base36 encoded v1 cids are displayed in base32
+        let base36 = cid_to_v1(&v0.parse().unwrap())
+            .to_string_of_base(cid::multibase::Base::Base36Lower)
+            .unwrap();
+        let p: Path = format!("ipfs://{base36}").parse().unwrap();
+        assert_eq!(p.to_string(), format!("/ipfs/{v1}"));
+
+        let p: Path = "/ipns/Docs.IPFS.io./a".parse().unwrap();
+        assert_eq!(p.to_canonical().to_string(), "/ipns/docs.ipfs.io/a");
     }
 
     fn make_ipld() -> Ipld {
@@ -824,6 +1504,45 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_identity_cids() {
+        let identity = |codec: IpldCodec, bytes: &[u8]| {
+            Cid::new_v1(
+                codec.into(),
+                cid::multihash::Multihash::wrap(iroh_util::IDENTITY_HASH_CODE, bytes).unwrap(),
+            )
+        };
+        let leaf = identity(IpldCodec::Raw, b"hello");
+        assert_eq!(identity_content(&leaf).unwrap(), &b"hello"[..]);
+        assert_eq!(verify_hash(&leaf, b"hello"), Some(true));
+        assert_eq!(verify_hash(&leaf, b"world"), Some(false));
+
+        let mut bytes = Vec::new();
+        Ipld::List(vec![Ipld::Link(leaf)])
+            .encode(IpldCodec::DagCbor, &mut bytes)
+            .unwrap();
+        let inline_root = identity(IpldCodec::DagCbor, &bytes);
+        assert_eq!(parse_links(&inline_root, &bytes).unwrap(), vec![leaf]);
+        let root = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+        assert!(identity_content(&root).is_none());
+
+        // only the non inline root is loaded
+        let loader: HashMap<Cid, Bytes> = [(root, Bytes::from(bytes))].into_iter().collect();
+        let resolver = Resolver::new(loader);
+        for root in [root, inline_root] {
+            let out = resolver
+                .resolve(format!("/ipfs/{root}/0").parse().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(out.metadata().resolved_path.last().unwrap().1, leaf);
+        }
+        let out = resolver.resolve(Path::from_cid(leaf)).await.unwrap();
+        assert_eq!(
+            read_to_vec(out.pretty(resolver.loader.clone())).await,
+            b"hello"
+        );
+    }
+
     #[tokio::test]
     async fn test_resolve_ipld() {
         for codec in [IpldCodec::DagCbor, IpldCodec::DagJson] {
@@ -887,6 +1606,175 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_resolve_ipld_across_codecs() {
+        use crate::unixfs_builder::{DirectoryBuilder, FileBuilder};
+
+        let file = FileBuilder::new()
+            .name("readme.md")
+            .content_bytes(&b"# hello\n"[..])
+            .build()
+            .unwrap();
+        let dir = DirectoryBuilder::new().add_file(file).build().unwrap();
+        let (dir_cid, mut loader) = collect_blocks(dir.encode()).await;
+
+        let mut insert = |codec: IpldCodec, ipld: Ipld| {
+            let mut bytes = Vec::new();
+            ipld.encode(codec, &mut bytes).unwrap();
+            let c = Cid::new_v1(codec.into(), Code::Sha2_256.digest(&bytes));
+            loader.insert(c, bytes.into());
+            c
+        };
+        let meta = insert(
+            IpldCodec::DagJson,
+            Ipld::Map([("key".to_string(), Ipld::String("value".into()))].into()),
+        );
+        let root = insert(
+            IpldCodec::DagCbor,
+            Ipld::Map(
+                [
+                    ("files".to_string(), Ipld::List(vec![Ipld::Link(dir_cid)])),
+                    ("meta".to_string(), Ipld::Link(meta)),
+                ]
+                .into(),
+            ),
+        );
+
+        let loader = Arc::new(loader);
+        let resolver = Resolver::new(loader.clone());
+
+        // dag-cbor -> unixfs
+        let out = resolver
+            .resolve(format!("/ipfs/{root}/files/0/readme.md").parse().unwrap())
+            .await
+            .unwrap();
+        let m = out.metadata().clone();
+        assert_eq!(m.typ, OutType::Unixfs);
+        assert_eq!(m.unixfs_type, Some(UnixfsType::File));
+        assert_eq!(m.resolved_path.len(), 3);
+        assert_eq!(m.resolved_path[0], (root.to_string(), root));
+        assert_eq!(m.resolved_path[1], ("files/0".to_string(), dir_cid));
+        assert_eq!(m.resolved_path[2].0, "readme.md");
+        assert_eq!(
+            read_to_string(out.pretty(loader.clone())).await,
+            "# hello\n"
+        );
+
+        // dag-cbor -> dag-json
+        let out = resolver
+            .resolve(format!("/ipfs/{root}/meta/key").parse().unwrap())
+            .await
+            .unwrap();
+        let m = out.metadata().clone();
+        assert_eq!(m.typ, OutType::DagJson);
+        assert_eq!(
+            m.resolved_path,
+            vec![(root.to_string(), root), ("meta".to_string(), meta)]
+        );
+        let out_bytes = read_to_vec(out.pretty(loader.clone())).await;
+        let out_ipld: Ipld = IpldCodec::DagJson.decode(&out_bytes).unwrap();
+        assert_eq!(out_ipld, Ipld::String("value".to_string()));
+
+        // a trailing link is not followed
+        let out = resolver
+            .resolve(format!("/ipfs/{root}/files/0").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(out.metadata().typ, OutType::DagCbor);
+        let out_bytes = read_to_vec(out.pretty(loader.clone())).await;
+        let out_ipld: Ipld = IpldCodec::DagCbor.decode(&out_bytes).unwrap();
+        assert_eq!(out_ipld, Ipld::Link(dir_cid));
+
+        // missing segments in the linked block
+        assert!(resolver
+            .resolve(format!("/ipfs/{root}/meta/missing").parse().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unixfs_list_dir() {
+        use crate::unixfs_builder::{DirectoryBuilder, FileBuilder, Symlink};
+
+        let content: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+        let dir = DirectoryBuilder::new()
+            .add_file(
+                FileBuilder::new()
+                    .name("small.txt")
+                    .content_bytes(&b"hello world"[..])
+                    .build()
+                    .unwrap(),
+            )
+            .add_file(
+                FileBuilder::new()
+                    .name("large.bin")
+                    .content_bytes(content)
+                    .chunk_size(100)
+                    .build()
+                    .unwrap(),
+            )
+            .add_dir(DirectoryBuilder::new().name("sub").build().unwrap())
+            .add_symlink(Symlink::new("link", "small.txt"))
+            .build()
+            .unwrap();
+        let (root, resolver) = resolver_for(dir.encode()).await;
+        let loader = resolver.loader().clone();
+
+        let out = resolver
+            .resolve(format!("/ipfs/{root}").parse().unwrap())
+            .await
+            .unwrap();
+        for concurrency in [1, 16] {
+            let options = ListOptions {
+                concurrency,
+                ..Default::default()
+            };
+            let ls: Vec<_> = out
+                .unixfs_list_dir(loader.clone(), options)
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let ls: Vec<_> = ls
+                .iter()
+                .map(|e| (e.name.as_str(), e.typ, e.size))
+                .collect();
+            assert_eq!(
+                ls,
+                [
+                    ("large.bin", Some(DataType::File), Some(1000)),
+                    ("link", Some(DataType::Symlink), Some(9)),
+                    ("small.txt", Some(DataType::File), Some(11)),
+                    ("sub", Some(DataType::Directory), None),
+                ]
+            );
+        }
+
+        // without resolving, nothing is loaded
+        let ls: Vec<_> = out
+            .unixfs_list_dir(
+                Arc::new(HashMap::<Cid, Bytes>::new()),
+                ListOptions {
+                    resolve: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ls.len(), 4);
+        assert!(ls.iter().all(|e| e.typ.is_none() && e.tsize.is_some()));
+
+        let file = resolver
+            .resolve(format!("/ipfs/{root}/small.txt").parse().unwrap())
+            .await
+            .unwrap();
+        assert!(file
+            .unixfs_list_dir(loader.clone(), ListOptions::default())
+            .is_none());
+    }
+
     #[tokio::test]
     async fn test_unixfs_basics_cid_v0() {
         // Test content
@@ -1264,6 +2152,136 @@ mod tests {
             }
         }
     }
+    #[derive(Debug, Clone)]
+    struct CountingLoader {
+        blocks: Arc<HashMap<Cid, Bytes>>,
+        loads: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl CountingLoader {
+        fn new(blocks: HashMap<Cid, Bytes>) -> Self {
+            CountingLoader {
+                blocks: Arc::new(blocks),
+                loads: Default::default(),
+            }
+        }
+
+        fn take_loads(&self) -> usize {
+            self.loads.swap(0, std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl ContentLoader for CountingLoader {
+        async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+            self.loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.blocks.load_cid(cid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unixfs_seek() {
+        // Test content
+        // ------------
+        // bafybeicunh5ha3gzmgocluisqanf5pchuo73c6xy42npmqv56z36gxt3hi
+        //   1000 bytes of "abcd..xyzabc..", balanced dag with raw leaves of 64 bytes
+        //   and 4 links per node, two levels deep
+
+        let root = fixture_file();
+        let content: Vec<u8> = (0..1000).map(|i| b'a' + (i % 26) as u8).collect();
+
+        let loader = CountingLoader::new(load_fixture_dag(root).await);
+        let resolver = Resolver::new(loader.clone());
+
+        let path = format!("/ipfs/{root}");
+        let out = resolver.resolve(path.parse().unwrap()).await.unwrap();
+        assert_eq!(read_to_vec(out.pretty(loader.clone())).await, content);
+
+        for offset in [0, 1, 63, 64, 65, 255, 256, 257, 500, 999, 1000, 1500] {
+            let out = resolver.resolve(path.parse().unwrap()).await.unwrap();
+            let mut reader = out.pretty(loader.clone());
+            assert_eq!(reader.seek(SeekFrom::Start(offset)).await.unwrap(), offset);
+            loader.take_loads();
+
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await.unwrap();
+            let offset = std::cmp::min(offset as usize, content.len());
+            assert_eq!(buf, &content[offset..], "offset {}", offset);
+        }
+
+        // seeking only loads the nodes on the path to the target
+        let out = resolver.resolve(path.parse().unwrap()).await.unwrap();
+        let mut reader = out.pretty(loader.clone());
+        assert_eq!(reader.seek(SeekFrom::End(-10)).await.unwrap(), 990);
+        loader.take_loads();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &content[990..]);
+        assert_eq!(loader.take_loads(), 2);
+
+        // seek backwards, after reading
+        assert_eq!(reader.seek(SeekFrom::Current(-500)).await.unwrap(), 500);
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, &content[500..510]);
+
+        assert!(reader.seek(SeekFrom::Current(-1000)).await.is_err());
+    }
+
+    /// Tracks the maximum number of concurrent loads.
+    #[derive(Debug, Clone)]
+    struct ConcurrencyLoader {
+        blocks: Arc<HashMap<Cid, Bytes>>,
+        in_flight: Arc<std::sync::atomic::AtomicUsize>,
+        max_in_flight: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ContentLoader for ConcurrencyLoader {
+        async fn load_cid(&self, cid: &Cid) -> Result<Bytes> {
+            use std::sync::atomic::Ordering;
+
+            let current = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(current, Ordering::SeqCst);
+            // give other loads the chance to start
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.blocks.load_cid(cid).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unixfs_prefetch() {
+        let root = fixture_file();
+        let content: Vec<u8> = (0..1000).map(|i| b'a' + (i % 26) as u8).collect();
+        let blocks = Arc::new(load_fixture_dag(root).await);
+        let path: Path = format!("/ipfs/{root}").parse().unwrap();
+
+        for (window, concurrent) in [(0, false), (1, true), (8, true)] {
+            let loader = ConcurrencyLoader {
+                blocks: blocks.clone(),
+                in_flight: Default::default(),
+                max_in_flight: Default::default(),
+            };
+            let resolver = Resolver::new(loader.clone());
+            let out = resolver.resolve(path.clone()).await.unwrap();
+            let reader = match out.pretty(loader.clone()) {
+                OutPrettyReader::Unixfs(reader) => reader.prefetch_window(window),
+                _ => panic!("expected unixfs"),
+            };
+
+            // the order of the content is preserved
+            assert_eq!(read_to_vec(reader).await, content, "window {}", window);
+            let max = loader
+                .max_in_flight
+                .load(std::sync::atomic::Ordering::SeqCst);
+            assert_eq!(max > 1, concurrent, "window {}: {} loads", window, max);
+            assert!(max <= window + 1, "window {}: {} loads", window, max);
+        }
+    }
+
     #[tokio::test]
     async fn test_unixfs_symlink() {
         // Test content
@@ -1520,26 +2538,315 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_resolve_txt_record() {
-        let result = resolve_txt_record("_dnslink.ipfs.io.").await.unwrap();
-        assert!(!result.is_empty());
-        assert_eq!(result[0], "dnslink=/ipns/website.ipfs.io");
+    async fn test_unixfs_follow_symlinks() {
+        use crate::unixfs_builder::{DirectoryBuilder, FileBuilder, Symlink};
+
+        let v2 = || {
+            DirectoryBuilder::new()
+                .name("v2")
+                .add_file(
+                    FileBuilder::new()
+                        .name("index.html")
+                        .content_bytes(&b"v2"[..])
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap()
+        };
+
+        let (v2_cid, _) = collect_blocks(v2().encode()).await;
+        let dir = DirectoryBuilder::new()
+            .add_dir(v2())
+            .add_symlink(Symlink::new("latest", "v2"))
+            .add_symlink(Symlink::new("current", "./latest/"))
+            .add_symlink(Symlink::new("abs", format!("/ipfs/{v2_cid}")))
+            .add_symlink(Symlink::new("rooted", "/v2/index.html"))
+            .add_symlink(Symlink::new("loop-a", "loop-b"))
+            .add_symlink(Symlink::new("loop-b", "loop-a"))
+            .add_symlink(Symlink::new("up", "../v2"))
+            .build()
+            .unwrap();
+        let (root, resolver) = resolver_for(dir.encode()).await;
+        let loader = resolver.loader().clone();
+        let resolve = |path: String| resolver.resolve(path.parse().unwrap());
+
+        let direct = resolve(format!("/ipfs/{root}/v2/index.html"))
+            .await
+            .unwrap();
+        let index_cid = direct.metadata().resolved_path[2].1;
+
+        // relative, chained and absolute targets in the middle of the path
+        for (link, resolved_path) in [
+            (
+                "latest",
+                vec![
+                    (root.to_string(), root),
+                    ("v2".to_string(), v2_cid),
+                    ("index.html".to_string(), index_cid),
+                ],
+            ),
+            (
+                "current",
+                vec![
+                    (root.to_string(), root),
+                    ("v2".to_string(), v2_cid),
+                    ("index.html".to_string(), index_cid),
+                ],
+            ),
+            (
+                "abs",
+                vec![
+                    (v2_cid.to_string(), v2_cid),
+                    ("index.html".to_string(), index_cid),
+                ],
+            ),
+        ] {
+            let path = format!("/ipfs/{root}/{link}/index.html");
+            let out = resolve(path.clone()).await.unwrap();
+            let m = out.metadata();
+            assert_eq!(m.path.to_string(), path);
+            assert_eq!(m.unixfs_type, Some(UnixfsType::File));
+            assert_eq!(m.resolved_path, resolved_path, "{}", link);
+            assert_eq!(read_to_string(out.pretty(loader.clone())).await, "v2");
+        }
 
-        let result = resolve_txt_record("_dnslink.website.ipfs.io.")
+        // the final symlink is only followed if requested
+        let out = resolve(format!("/ipfs/{root}/rooted")).await.unwrap();
+        assert_eq!(out.metadata().unixfs_type, Some(UnixfsType::Symlink));
+        assert_eq!(
+            read_to_string(out.pretty(loader.clone())).await,
+            "/v2/index.html"
+        );
+        let following = Resolver::new(loader.clone()).follow_final_symlink(true);
+        let out = following
+            .resolve(format!("/ipfs/{root}/rooted").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(out.metadata().unixfs_type, Some(UnixfsType::File));
+        assert_eq!(read_to_string(out.pretty(loader.clone())).await, "v2");
+        let out = following
+            .resolve(format!("/ipfs/{root}/current").parse().unwrap())
             .await
             .unwrap();
-        assert!(!result.is_empty());
-        assert!(&result[0].starts_with("dnslink=/ipfs"));
+        assert_eq!(out.metadata().unixfs_type, Some(UnixfsType::Dir));
+
+        // loops and targets outside of the root
+        let err = resolve(format!("/ipfs/{root}/loop-a/index.html"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too many levels"), "{}", err);
+        let err = following
+            .resolve(format!("/ipfs/{root}/loop-b").parse().unwrap())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too many levels"), "{}", err);
+        let err = resolve(format!("/ipfs/{root}/up/index.html"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("out of bounds"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_unixfs_hamt_dir() {
+        // Test content
+        // ------------
+        // Sharded directory with fanout 256, laid out the same way as go-ipfs does,
+        // containing two nested levels of shards:
+        //
+        // bafybeigt6qre636hfer4rcbug5z2xuyif3kkxypnqijouy6c63rxcn6uui root shard
+        //   world.txt, index.html, README.md, styles.css
+        // bafybeifq7uhtjrmvwpmgn65vuxxq6flds3l5jx7otrhjbqmko3txoqvzd4 shard (prefix df)
+        //   file-297.txt
+        // bafybeihg7pssmrhxgxzzistg2ka7mu2zoav7hcjz6msbqge2rzovonjjvi shard (prefix df19)
+        //   file-229541.txt, file-159140.txt, hello.txt
+        //
+        // each file contains "content of <name>\n"
+
+        let root_cid_str = "bafybeigt6qre636hfer4rcbug5z2xuyif3kkxypnqijouy6c63rxcn6uui";
+        let shards = [
+            "bafybeifq7uhtjrmvwpmgn65vuxxq6flds3l5jx7otrhjbqmko3txoqvzd4",
+            "bafybeihg7pssmrhxgxzzistg2ka7mu2zoav7hcjz6msbqge2rzovonjjvi",
+        ];
+        let files = [
+            (
+                "world.txt",
+                "bafkreidlce6rsquufuacx64a2prw5yytty6mturamuvyxxhc54knga6rs4",
+            ),
+            (
+                "index.html",
+                "bafkreie6p5kroihbxa2vwhh63f4jk5wn4sef3jgrqf45ya3ps2omjftx7i",
+            ),
+            (
+                "README.md",
+                "bafkreidxys4ttjz6u243vnvf72ryjvfh2lf7lrrmr5uwerlvfve7m3dpci",
+            ),
+            (
+                "styles.css",
+                "bafkreie6lfuln4ft7fg7tdhfsstdon5qs7xatszbzf2qjpkqmqnfrzhqv4",
+            ),
+            (
+                "file-229541.txt",
+                "bafkreihim5zuntxdep3n3epjeh2a7mcndl5hff2ii6gtsng6ef36qbhygy",
+            ),
+            (
+                "file-159140.txt",
+                "bafkreifrw4bthxjl24ehela2lkwxr5chul3gk4cqfowkiq37igool7cxty",
+            ),
+            (
+                "hello.txt",
+                "bafkreicjjgexkayjhv4y5yv2thw3prmguu323igp52nb5y3yhvmh6ug3bi",
+            ),
+            (
+                "file-297.txt",
+                "bafkreienp3o4jlgfbydvzgvrbq3xdnp3sx6negvzbyyfc2rcpi5tc6gklu",
+            ),
+        ];
+
+        let mut loader: HashMap<Cid, Bytes> = HashMap::new();
+        for c in shards
+            .iter()
+            .chain(files.iter().map(|(_, c)| c))
+            .chain(Some(&root_cid_str))
+        {
+            loader.insert(c.parse().unwrap(), load_fixture(c).await);
+        }
+        let loader = Arc::new(loader);
+        let resolver = Resolver::new(loader.clone());
+
+        {
+            let path = format!("/ipfs/{root_cid_str}");
+            let ipld_root = resolver.resolve(path.parse().unwrap()).await.unwrap();
+
+            let m = ipld_root.metadata();
+            assert_eq!(m.unixfs_type, Some(UnixfsType::Dir));
+            assert_eq!(m.typ, OutType::Unixfs);
+            assert!(ipld_root.unixfs_read_dir().is_none());
+
+            let ls: Vec<_> = ipld_root
+                .unixfs_dir_entries(loader.clone())
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(ls.len(), files.len());
+            for (link, (name, cid)) in ls.iter().zip(files.iter()) {
+                assert_eq!(link.name.as_deref(), Some(*name));
+                assert_eq!(link.cid, cid.parse().unwrap());
+            }
+
+            let ls: Vec<_> = ipld_root
+                .unixfs_list_dir(loader.clone(), ListOptions::default())
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(ls.len(), files.len());
+            for (entry, (name, cid)) in ls.iter().zip(files.iter()) {
+                assert_eq!(entry.name, *name);
+                assert_eq!(entry.cid, cid.parse().unwrap());
+                assert_eq!(entry.typ, Some(DataType::File));
+                assert_eq!(
+                    entry.size,
+                    Some(format!("content of {name}\n").len() as u64)
+                );
+            }
+
+            let expected: String = files.iter().map(|(name, _)| format!("{name}\n")).collect();
+            assert_eq!(
+                read_to_string(ipld_root.pretty(loader.clone())).await,
+                expected
+            );
+        }
+
+        for (name, cid) in files {
+            let path = format!("/ipfs/{root_cid_str}/{name}");
+            let ipld_file = resolver.resolve(path.parse().unwrap()).await.unwrap();
+
+            let m = ipld_file.metadata();
+            assert_eq!(m.unixfs_type, Some(UnixfsType::File));
+            assert_eq!(m.path.to_string(), path);
+            assert_eq!(
+                m.resolved_path,
+                vec![
+                    (root_cid_str.to_string(), root_cid_str.parse().unwrap()),
+                    (name.to_string(), cid.parse().unwrap()),
+                ]
+            );
+            assert_eq!(
+                read_to_string(ipld_file.pretty(loader.clone())).await,
+                format!("content of {name}\n")
+            );
+        }
+
+        let path = format!("/ipfs/{root_cid_str}/missing.txt");
+        assert!(resolver.resolve(path.parse().unwrap()).await.is_err());
     }
 
     #[tokio::test]
-    async fn test_resolve_dnslink() {
-        let result = resolve_dnslink("ipfs.io").await.unwrap();
-        assert!(!result.is_empty());
-        assert_eq!(result[0], "/ipns/website.ipfs.io".parse().unwrap());
-
-        let result = resolve_dnslink("website.ipfs.io").await.unwrap();
-        assert_eq!(result.len(), 1);
-        assert_eq!(result[0].typ(), PathType::Ipfs);
+    async fn test_resolve_ipns() {
+        use crate::ipns::IpnsRecord;
+        use libp2p::identity::Keypair;
+        use std::time::Duration;
+
+        let bar_txt_cid_str = "bafkreihcldjer7njjrrxknqh67cestxa7s7jf4nhnp62y6k4twcbahvtc4";
+        let bar_cid_str = "bafybeihmgpuwcdrfi47gfxisll7kmurvi6kd7rht5hlq2ed5omxobfip3a";
+        let root_cid_str = "bafybeietod5kx72jgbngoontthoax6nva4edkjnieghwqfzenstg4gil5i";
+
+        let mut loader: HashMap<Cid, Bytes> = HashMap::new();
+        for c in [bar_txt_cid_str, bar_cid_str, root_cid_str] {
+            loader.insert(c.parse().unwrap(), load_fixture(c).await);
+        }
+        let loader = Arc::new(loader);
+
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(keypair.public());
+        let record = IpnsRecord::create_signed(
+            &keypair,
+            &format!("/ipfs/{root_cid_str}/bar").parse().unwrap(),
+            1,
+            time::OffsetDateTime::now_utc() + Duration::from_secs(3600),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        let records: HashMap<PeerId, Bytes> = [(peer_id, record)].into_iter().collect();
+
+        let resolver = Resolver::with_ipns(loader.clone(), records);
+        let name = Cid::new_v1(
+            Codec::Libp2pKey.into(),
+            cid::multihash::Multihash::from_bytes(&peer_id.to_bytes()).unwrap(),
+        );
+
+        let path = format!("/ipns/{name}/bar.txt");
+        let ipld_bar_txt = resolver.resolve(path.parse().unwrap()).await.unwrap();
+        assert!(ipld_bar_txt.is_mutable());
+
+        let m = ipld_bar_txt.metadata();
+        assert_eq!(m.unixfs_type, Some(UnixfsType::File));
+        assert_eq!(m.path.to_string(), path);
+        assert_eq!(
+            m.resolved_path,
+            vec![
+                (root_cid_str.to_string(), root_cid_str.parse().unwrap()),
+                ("bar".to_string(), bar_cid_str.parse().unwrap()),
+                ("bar.txt".to_string(), bar_txt_cid_str.parse().unwrap()),
+            ]
+        );
+
+        if let OutContent::Unixfs(node) = ipld_bar_txt.content {
+            assert_eq!(read_to_string(node.pretty(loader.clone())).await, "world\n");
+        } else {
+            panic!("invalid result: {:?}", ipld_bar_txt);
+        }
+
+        // unknown names fail
+        let other = PeerId::from(Keypair::generate_ed25519().public());
+        let path: Path = format!("/ipns/{other}").parse().unwrap();
+        assert!(resolver.resolve(path).await.is_err());
+
+        // no ipns source configured
+        let resolver = Resolver::new(loader.clone());
+        let path: Path = format!("/ipns/{name}/bar.txt").parse().unwrap();
+        assert!(resolver.resolve(path).await.is_err());
     }
 }
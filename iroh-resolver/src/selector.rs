@@ -0,0 +1,534 @@
+//! IPLD selectors, as specified in https://ipld.io/specs/selectors/.
+//!
+//! Selectors describe which parts of a DAG to traverse, independent of how the DAG is split
+//! into blocks. They are usually exchanged in their dag-json representation, e.g.
+//! `{"R":{"l":{"none":{}},":>":{"a":{">":{"@":{}}}}}}` selects everything below the root.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bytes::Bytes;
+use cid::Cid;
+use futures::Stream;
+use libipld::prelude::Codec as _;
+use libipld::{Ipld, IpldCodec};
+
+use crate::resolver::{decode_ipld, ContentLoader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// Matches the current node, without exploring any further.
+    Matcher,
+    /// Explores all children of the current node.
+    ExploreAll { next: Box<Selector> },
+    /// Explores the children with the given names.
+    ExploreFields { fields: BTreeMap<String, Selector> },
+    /// Explores the child at the given index of a list.
+    ExploreIndex { index: usize, next: Box<Selector> },
+    /// Explores the children at the indices `start..end` of a list.
+    ExploreRange {
+        start: usize,
+        end: usize,
+        next: Box<Selector>,
+    },
+    /// Applies `sequence` repeatedly, every [`Selector::ExploreRecursiveEdge`] in it
+    /// starts the sequence again, until the limit is reached.
+    ExploreRecursive {
+        limit: RecursionLimit,
+        sequence: Box<Selector>,
+        /// The part of the sequence that is currently applied.
+        current: Box<Selector>,
+    },
+    /// Marks where an [`Selector::ExploreRecursive`] starts over.
+    ExploreRecursiveEdge,
+    /// Applies all selectors.
+    ExploreUnion(Vec<Selector>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecursionLimit {
+    None,
+    /// Maximum number of times the sequence is applied.
+    Depth(u64),
+}
+
+/// A step from a node to one of its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl Display for PathSegment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Key(key) => write!(f, "{}", key),
+            PathSegment::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl Selector {
+    /// Parses a selector from its dag-json representation.
+    pub fn from_dag_json(bytes: &[u8]) -> Result<Self> {
+        let ipld: Ipld = IpldCodec::DagJson
+            .decode(bytes)
+            .map_err(|e| anyhow!("invalid dag json: {:?}", e))?;
+        Self::from_ipld(&ipld)
+    }
+
+    /// Parses a selector from its representation in the IPLD data model.
+    pub fn from_ipld(ipld: &Ipld) -> Result<Self> {
+        parse_selector(ipld, false)
+    }
+
+    /// Returns a new selector `start..end`.
+    pub fn explore_range(start: usize, end: usize, next: Selector) -> Self {
+        Selector::ExploreRange {
+            start,
+            end,
+            next: Box::new(next),
+        }
+    }
+
+    /// Returns a new recursive selector, starting with the given sequence.
+    pub fn explore_recursive(limit: RecursionLimit, sequence: Selector) -> Self {
+        Selector::ExploreRecursive {
+            limit,
+            current: Box::new(sequence.clone()),
+            sequence: Box::new(sequence),
+        }
+    }
+
+    /// Returns the selector to apply to the child at `segment`, `None` if the child is not
+    /// selected.
+    pub fn explore(&self, segment: &PathSegment) -> Option<Selector> {
+        match self {
+            Selector::Matcher | Selector::ExploreRecursiveEdge => None,
+            Selector::ExploreAll { next } => Some((**next).clone()),
+            Selector::ExploreFields { fields } => fields.get(&segment.to_string()).cloned(),
+            Selector::ExploreIndex { index, next } => match segment {
+                PathSegment::Index(i) if i == index => Some((**next).clone()),
+                _ => None,
+            },
+            Selector::ExploreRange { start, end, next } => match segment {
+                PathSegment::Index(i) if i >= start && i < end => Some((**next).clone()),
+                _ => None,
+            },
+            Selector::ExploreRecursive {
+                limit,
+                sequence,
+                current,
+            } => {
+                let next = current.explore(segment)?;
+                if !next.has_recursive_edge() {
+                    return Some(Selector::ExploreRecursive {
+                        limit: *limit,
+                        sequence: sequence.clone(),
+                        current: Box::new(next),
+                    });
+                }
+
+                let limit = match limit {
+                    RecursionLimit::Depth(depth) if *depth < 2 => {
+                        // the limit is reached, stop at the edge
+                        return next.replace_recursive_edge(None);
+                    }
+                    RecursionLimit::Depth(depth) => RecursionLimit::Depth(depth - 1),
+                    RecursionLimit::None => RecursionLimit::None,
+                };
+                let current = next.replace_recursive_edge(Some(&**sequence))?;
+                Some(Selector::ExploreRecursive {
+                    limit,
+                    sequence: sequence.clone(),
+                    current: Box::new(current),
+                })
+            }
+            Selector::ExploreUnion(selectors) => {
+                let mut next: Vec<_> = selectors
+                    .iter()
+                    .filter_map(|s| s.explore(segment))
+                    .collect();
+                match next.len() {
+                    0 => None,
+                    1 => next.pop(),
+                    _ => Some(Selector::ExploreUnion(next)),
+                }
+            }
+        }
+    }
+
+    /// Whether an edge is contained anywhere in the selector, not only at the current level.
+    fn contains_recursive_edge(&self) -> bool {
+        match self {
+            Selector::ExploreRecursiveEdge => true,
+            Selector::Matcher | Selector::ExploreRecursive { .. } => false,
+            Selector::ExploreAll { next }
+            | Selector::ExploreIndex { next, .. }
+            | Selector::ExploreRange { next, .. } => next.contains_recursive_edge(),
+            Selector::ExploreFields { fields } => {
+                fields.values().any(|s| s.contains_recursive_edge())
+            }
+            Selector::ExploreUnion(selectors) => {
+                selectors.iter().any(|s| s.contains_recursive_edge())
+            }
+        }
+    }
+
+    /// Whether the selector is an edge, or a union containing one.
+    fn has_recursive_edge(&self) -> bool {
+        match self {
+            Selector::ExploreRecursiveEdge => true,
+            Selector::ExploreUnion(selectors) => selectors.iter().any(|s| s.has_recursive_edge()),
+            _ => false,
+        }
+    }
+
+    fn replace_recursive_edge(self, replacement: Option<&Selector>) -> Option<Selector> {
+        match self {
+            Selector::ExploreRecursiveEdge => replacement.cloned(),
+            Selector::ExploreUnion(selectors) => {
+                let mut selectors: Vec<_> = selectors
+                    .into_iter()
+                    .filter_map(|s| s.replace_recursive_edge(replacement))
+                    .collect();
+                match selectors.len() {
+                    0 => None,
+                    1 => selectors.pop(),
+                    _ => Some(Selector::ExploreUnion(selectors)),
+                }
+            }
+            other => Some(other),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_dag_json(s.as_bytes())
+    }
+}
+
+fn parse_selector(ipld: &Ipld, in_recursive: bool) -> Result<Selector> {
+    let (kind, body) = single_entry(ipld).context("selector must be a map with a single key")?;
+
+    let selector = match kind {
+        "." => Selector::Matcher,
+        "a" => Selector::ExploreAll {
+            next: Box::new(parse_selector(field(body, ">")?, in_recursive)?),
+        },
+        "f" => {
+            let fields = match field(body, "f>")? {
+                Ipld::Map(fields) => fields
+                    .iter()
+                    .map(|(name, s)| Ok((name.clone(), parse_selector(s, in_recursive)?)))
+                    .collect::<Result<_>>()?,
+                _ => bail!("fields of explore fields must be a map"),
+            };
+            Selector::ExploreFields { fields }
+        }
+        "i" => Selector::ExploreIndex {
+            index: parse_usize(field(body, "i")?)?,
+            next: Box::new(parse_selector(field(body, ">")?, in_recursive)?),
+        },
+        "r" => {
+            let start = parse_usize(field(body, "^")?)?;
+            let end = parse_usize(field(body, "$")?)?;
+            ensure!(start <= end, "invalid range {}..{}", start, end);
+            Selector::explore_range(start, end, parse_selector(field(body, ">")?, in_recursive)?)
+        }
+        "R" => {
+            ensure!(
+                field(body, "!").is_err(),
+                "stop conditions are not supported"
+            );
+            let limit = match single_entry(field(body, "l")?) {
+                Some(("none", _)) => RecursionLimit::None,
+                Some(("depth", depth)) => RecursionLimit::Depth(parse_usize(depth)? as u64),
+                _ => bail!("invalid recursion limit"),
+            };
+            let sequence = parse_selector(field(body, ":>")?, true)?;
+            ensure!(
+                sequence.contains_recursive_edge(),
+                "recursive selector without a recursive edge"
+            );
+            Selector::explore_recursive(limit, sequence)
+        }
+        "@" => {
+            ensure!(
+                in_recursive,
+                "recursive edge outside of a recursive selector"
+            );
+            Selector::ExploreRecursiveEdge
+        }
+        "|" => match body {
+            Ipld::List(selectors) => Selector::ExploreUnion(
+                selectors
+                    .iter()
+                    .map(|s| parse_selector(s, in_recursive))
+                    .collect::<Result<_>>()?,
+            ),
+            _ => bail!("union must be a list of selectors"),
+        },
+        "&" => bail!("conditional selectors are not supported"),
+        _ => bail!("unknown selector {:?}", kind),
+    };
+
+    Ok(selector)
+}
+
+fn single_entry(ipld: &Ipld) -> Option<(&str, &Ipld)> {
+    match ipld {
+        Ipld::Map(map) if map.len() == 1 => map.iter().next().map(|(k, v)| (k.as_str(), v)),
+        _ => None,
+    }
+}
+
+fn field<'a>(ipld: &'a Ipld, name: &str) -> Result<&'a Ipld> {
+    match ipld {
+        Ipld::Map(map) => map
+            .get(name)
+            .ok_or_else(|| anyhow!("missing field {:?}", name)),
+        _ => bail!("expected a map"),
+    }
+}
+
+fn parse_usize(ipld: &Ipld) -> Result<usize> {
+    match ipld {
+        Ipld::Integer(i) => usize::try_from(*i).map_err(|_| anyhow!("invalid integer {}", i)),
+        _ => bail!("expected an integer"),
+    }
+}
+
+/// Traverses the DAG below `root`, following the selector.
+///
+/// Links are followed transparently, every block that is loaded during the traversal is
+/// yielded once, in depth-first order.
+pub fn select<T: ContentLoader + 'static>(
+    loader: T,
+    root: Cid,
+    selector: Selector,
+) -> impl Stream<Item = Result<(Cid, Bytes)>> + Send {
+    async_stream::try_stream! {
+        let mut yielded = HashSet::new();
+        let mut stack = vec![(Ipld::Link(root), selector)];
+
+        while let Some((node, selector)) = stack.pop() {
+            match node {
+                Ipld::Link(cid) => {
+                    let bytes = loader.load_cid(&cid).await?;
+                    let node = decode_ipld(&cid, &bytes)?;
+                    stack.push((node, selector));
+                    if yielded.insert(cid) {
+                        yield (cid, bytes);
+                    }
+                }
+                Ipld::List(list) => {
+                    for (i, child) in list.into_iter().enumerate().rev() {
+                        if let Some(next) = selector.explore(&PathSegment::Index(i)) {
+                            stack.push((child, next));
+                        }
+                    }
+                }
+                Ipld::Map(map) => {
+                    for (key, child) in map.into_iter().rev() {
+                        if let Some(next) = selector.explore(&PathSegment::Key(key)) {
+                            stack.push((child, next));
+                        }
+                    }
+                }
+                _ => {
+                    // scalars have no children
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use cid::multihash::{Code, MultihashDigest};
+    use futures::TryStreamExt;
+    use libipld::codec::Encode;
+
+    #[test]
+    fn test_parse_selector() {
+        let all: Selector = r#"{"R":{"l":{"none":{}},":>":{"a":{">":{"@":{}}}}}}"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            all,
+            Selector::explore_recursive(
+                RecursionLimit::None,
+                Selector::ExploreAll {
+                    next: Box::new(Selector::ExploreRecursiveEdge)
+                }
+            )
+        );
+
+        let s: Selector = r#"{"f":{"f>":{"Links":{"r":{"^":1,"$":3,">":{".":{}}}}}}}"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            s,
+            Selector::ExploreFields {
+                fields: [(
+                    "Links".to_string(),
+                    Selector::explore_range(1, 3, Selector::Matcher)
+                )]
+                .into_iter()
+                .collect()
+            }
+        );
+
+        let s: Selector = r#"{"|":[{"i":{"i":0,">":{".":{}}}},{"."  :{}}]}"#.parse().unwrap();
+        assert_eq!(
+            s,
+            Selector::ExploreUnion(vec![
+                Selector::ExploreIndex {
+                    index: 0,
+                    next: Box::new(Selector::Matcher)
+                },
+                Selector::Matcher
+            ])
+        );
+
+        for invalid in [
+            r#"{}"#,
+            r#"{".":{},"a":{">":{".":{}}}}"#,
+            r#"{"@":{}}"#,
+            r#"{"R":{"l":{"depth":1},":>":{".":{}}}}"#,
+            r#"{"R":{"l":{"depth":-1},":>":{"@":{}}}}"#,
+            r#"{"r":{"^":3,"$":1,">":{".":{}}}}"#,
+            r#"{"x":{}}"#,
+            r#"[]"#,
+        ] {
+            assert!(invalid.parse::<Selector>().is_err(), "{}", invalid);
+        }
+    }
+
+    /// Builds `root -> [a -> [c], b]` out of dag-cbor blocks.
+    fn make_dag() -> (HashMap<Cid, Bytes>, [Cid; 4]) {
+        let mut blocks = HashMap::new();
+        let mut insert = |ipld: Ipld| {
+            let mut bytes = Vec::new();
+            ipld.encode(IpldCodec::DagCbor, &mut bytes).unwrap();
+            let cid = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&bytes));
+            blocks.insert(cid, Bytes::from(bytes));
+            cid
+        };
+        let c = insert(Ipld::String("c".into()));
+        let b = insert(Ipld::String("b".into()));
+        let a = insert(Ipld::List(vec![Ipld::Link(c)]));
+        let root = insert(Ipld::List(vec![Ipld::Link(a), Ipld::Link(b)]));
+        (blocks, [root, a, b, c])
+    }
+
+    async fn select_cids(loader: Arc<HashMap<Cid, Bytes>>, root: Cid, selector: &str) -> Vec<Cid> {
+        select(loader, root, selector.parse().unwrap())
+            .map_ok(|(cid, _)| cid)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_select() {
+        let (blocks, [root, a, b, c]) = make_dag();
+        let loader = Arc::new(blocks);
+
+        let all = r#"{"R":{"l":{"none":{}},":>":{"a":{">":{"@":{}}}}}}"#;
+        assert_eq!(
+            select_cids(loader.clone(), root, all).await,
+            vec![root, a, c, b]
+        );
+        let depth = |d| {
+            format!(
+                r#"{{"R":{{"l":{{"depth":{}}},":>":{{"a":{{">":{{"@":{{}}}}}}}}}}}}"#,
+                d
+            )
+        };
+        assert_eq!(
+            select_cids(loader.clone(), root, &depth(1)).await,
+            vec![root]
+        );
+        assert_eq!(
+            select_cids(loader.clone(), root, &depth(2)).await,
+            vec![root, a, b]
+        );
+        assert_eq!(
+            select_cids(loader.clone(), root, &depth(3)).await,
+            vec![root, a, c, b]
+        );
+
+        assert_eq!(
+            select_cids(loader.clone(), root, r#"{".":{}}"#).await,
+            vec![root]
+        );
+        assert_eq!(
+            select_cids(loader.clone(), root, r#"{"i":{"i":1,">":{".":{}}}}"#).await,
+            vec![root, b]
+        );
+        assert_eq!(
+            select_cids(
+                loader.clone(),
+                root,
+                r#"{"r":{"^":0,"$":1,">":{"a":{">":{".":{}}}}}}"#
+            )
+            .await,
+            vec![root, a, c]
+        );
+        assert_eq!(
+            select_cids(
+                loader.clone(),
+                root,
+                r#"{"|":[{"i":{"i":1,">":{".":{}}}},{"f":{"f>":{"0":{".":{}}}}}]}"#
+            )
+            .await,
+            vec![root, a, b]
+        );
+
+        // missing blocks are an error
+        assert!(select(Arc::new(HashMap::new()), root, Selector::Matcher)
+            .try_collect::<Vec<_>>()
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_select_dag_pb() {
+        use crate::dag_walker::DagWalker;
+        use crate::resolver::parse_links;
+        use crate::test_utils::{fixture_file, load_fixture_dag};
+
+        let root = fixture_file();
+        let loader = Arc::new(load_fixture_dag(root).await);
+
+        // everything, in the same order as a depth-first walk
+        let all = r#"{"R":{"l":{"none":{}},":>":{"a":{">":{"@":{}}}}}}"#;
+        let walked: Vec<_> = DagWalker::new(root)
+            .walk(loader.clone())
+            .map_ok(|(cid, _)| cid)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(walked.len(), 21);
+        assert_eq!(select_cids(loader.clone(), root, all).await, walked);
+
+        // only the second child of the root
+        let stems = parse_links(&root, &loader[&root]).unwrap();
+        let second = r#"{"f":{"f>":{"Links":{"i":{"i":1,">":{"f":{"f>":{"Hash":{".":{}}}}}}}}}}"#;
+        assert_eq!(
+            select_cids(loader.clone(), root, second).await,
+            vec![root, stems[1]]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Helpers shared by the tests of this crate.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use cid::Cid;
+use futures::{Stream, TryStreamExt};
+
+use crate::resolver::{parse_links, Resolver};
+
+/// Root of the fixture with 1000 bytes of "abcd..xyzabc..", a balanced dag with raw leaves
+/// of 64 bytes and 4 links per node, two levels deep.
+pub(crate) fn fixture_file() -> Cid {
+    "bafybeicunh5ha3gzmgocluisqanf5pchuo73c6xy42npmqv56z36gxt3hi"
+        .parse()
+        .unwrap()
+}
+
+pub(crate) async fn load_fixture(p: &str) -> Bytes {
+    Bytes::from(tokio::fs::read(format!("./fixtures/{p}")).await.unwrap())
+}
+
+/// Loads all blocks of the dag below `root` from the fixtures.
+pub(crate) async fn load_fixture_dag(root: Cid) -> HashMap<Cid, Bytes> {
+    let mut blocks = HashMap::new();
+    let mut todo = vec![root];
+    while let Some(cid) = todo.pop() {
+        if blocks.contains_key(&cid) {
+            continue;
+        }
+        let bytes = load_fixture(&cid.to_string()).await;
+        todo.extend(parse_links(&cid, &bytes).unwrap());
+        blocks.insert(cid, bytes);
+    }
+    blocks
+}
+
+/// Turns the blocks produced by the unixfs builder into a loader, returning the root,
+/// which comes last.
+pub(crate) fn into_loader(blocks: Vec<(Cid, Bytes, Vec<Cid>)>) -> (Cid, HashMap<Cid, Bytes>) {
+    let root = blocks.last().expect("no blocks").0;
+    let blocks = blocks
+        .into_iter()
+        .map(|(cid, bytes, _)| (cid, bytes))
+        .collect();
+    (root, blocks)
+}
+
+/// Like [`into_loader`], returning a resolver that loads from the blocks.
+pub(crate) fn into_resolver(
+    blocks: Vec<(Cid, Bytes, Vec<Cid>)>,
+) -> (Cid, Resolver<Arc<HashMap<Cid, Bytes>>>) {
+    let (root, blocks) = into_loader(blocks);
+    (root, Resolver::new(Arc::new(blocks)))
+}
+
+/// Encodes all blocks of the stream, see [`into_loader`].
+pub(crate) async fn collect_blocks<S>(blocks: S) -> (Cid, HashMap<Cid, Bytes>)
+where
+    S: Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>>,
+{
+    into_loader(blocks.try_collect().await.unwrap())
+}
+
+/// Encodes all blocks of the stream, see [`into_resolver`].
+pub(crate) async fn resolver_for<S>(blocks: S) -> (Cid, Resolver<Arc<HashMap<Cid, Bytes>>>)
+where
+    S: Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>>,
+{
+    into_resolver(blocks.try_collect().await.unwrap())
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use anyhow::Result;
+use bytes::Bytes;
+use cid::Cid;
+use futures::{Stream, StreamExt};
+
+use crate::unixfs_builder::{encode_leaf, encode_stem, TreeLink};
+
+/// Number of subtrees of the same depth, before the depth is increased.
+pub const DEFAULT_DEPTH_REPEAT: usize = 4;
+
+/// Builds a trickle tree out of the given chunks, using raw leaves.
+///
+/// Produces the same layout as the trickle builder in go-ipfs: every node first holds up to
+/// `degree` leaves, followed by [`DEFAULT_DEPTH_REPEAT`] subtrees of depth 1, then of depth 2
+/// and so on. This favours reading from the start and appending to the file, as the
+/// beginning of the content is close to the root.
+/// Blocks are yielded as soon as they are complete, the root block is always the last one.
+pub fn stream_trickle_tree<S>(
+    chunks: S,
+    degree: usize,
+) -> impl Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>> + Send
+where
+    S: Stream<Item = io::Result<Bytes>> + Send,
+{
+    assert!(degree > 1, "degree must be at least 2");
+
+    async_stream::try_stream! {
+        let mut chunks = Box::pin(chunks.peekable());
+        // The root has no maximum depth, it grows until all chunks are consumed.
+        let mut stack = vec![Frame::new(None)];
+
+        while let Some(frame) = stack.last_mut() {
+            if !frame.filled {
+                // fill the node with leaves
+                while frame.links.len() < degree {
+                    let chunk = match chunks.next().await {
+                        Some(chunk) => chunk?,
+                        None => break,
+                    };
+                    let (link, block) = encode_leaf(chunk);
+                    frame.links.push(link);
+                    yield block;
+                }
+                frame.filled = true;
+                continue;
+            }
+
+            let done = chunks.as_mut().peek().await.is_none();
+            let depth_reached = frame.max_depth.map(|max| frame.depth >= max).unwrap_or_default();
+            if done || depth_reached {
+                let frame = stack.pop().expect("checked");
+                let (link, block) = encode_stem(&frame.links);
+                yield block;
+                if let Some(parent) = stack.last_mut() {
+                    parent.links.push(link);
+                }
+                continue;
+            }
+
+            if frame.repeat < DEFAULT_DEPTH_REPEAT {
+                frame.repeat += 1;
+                let max_depth = frame.depth;
+                stack.push(Frame::new(Some(max_depth)));
+            } else {
+                frame.depth += 1;
+                frame.repeat = 0;
+            }
+        }
+    }
+}
+
+/// A node of the tree that is currently being filled.
+#[derive(Debug)]
+struct Frame {
+    links: Vec<TreeLink>,
+    /// Whether the leaves of this node have been added.
+    filled: bool,
+    /// Maximum depth of subtrees, `None` for the root.
+    max_depth: Option<usize>,
+    /// Depth of the subtrees currently being added.
+    depth: usize,
+    /// Number of subtrees of the current depth added so far.
+    repeat: usize,
+}
+
+impl Frame {
+    fn new(max_depth: Option<usize>) -> Self {
+        Frame {
+            links: Vec::new(),
+            filled: false,
+            max_depth,
+            depth: 1,
+            repeat: 0,
+        }
+    }
+}
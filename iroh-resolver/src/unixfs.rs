@@ -1,25 +1,39 @@
 use std::{
     collections::VecDeque,
     fmt::Debug,
+    future::Future,
     io::Cursor,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 
-use anyhow::{anyhow, Result};
-use bytes::{Buf, Bytes};
+use anyhow::{anyhow, ensure, Result};
+use bytes::{Buf, Bytes, BytesMut};
 use cid::Cid;
-use futures::{future::BoxFuture, FutureExt};
+use futures::{
+    future::BoxFuture,
+    stream::{self, BoxStream},
+    FutureExt, StreamExt, TryStreamExt,
+};
 use prost::Message;
-use tokio::io::AsyncRead;
+use tokio::{
+    io::{AsyncRead, AsyncSeek},
+    task::JoinHandle,
+};
 
 use crate::{codecs::Codec, resolver::ContentLoader};
 
-mod unixfs_pb {
+pub(crate) mod hamt;
+
+/// Number of blocks of a chunked file that are loaded ahead of the reader by default.
+pub const DEFAULT_PREFETCH_WINDOW: usize = 8;
+
+pub(crate) mod unixfs_pb {
     include!(concat!(env!("OUT_DIR"), "/unixfs_pb.rs"));
 }
 
-mod dag_pb {
+pub(crate) mod dag_pb {
     include!(concat!(env!("OUT_DIR"), "/merkledag_pb.rs"));
 }
 
@@ -64,6 +78,37 @@ pub struct Link {
     pub tsize: Option<u64>,
 }
 
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub cid: Cid,
+    /// Cumulative size of the entry's DAG, as stored in the link.
+    pub tsize: Option<u64>,
+    /// Type of the entry, `None` if the entry was not resolved.
+    pub typ: Option<DataType>,
+    /// Size of the file content, only for files and symlinks that were resolved.
+    pub size: Option<u64>,
+}
+
+/// Options for [`UnixfsNode::list_dir`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Whether to load the entries, to determine their type and size.
+    pub resolve: bool,
+    /// Maximum number of entries that are loaded concurrently.
+    pub concurrency: usize,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions {
+            resolve: true,
+            concurrency: 16,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LinkRef<'a> {
     pub cid: Cid,
@@ -71,6 +116,16 @@ pub struct LinkRef<'a> {
     pub tsize: Option<u64>,
 }
 
+impl LinkRef<'_> {
+    pub fn to_link(&self) -> Link {
+        Link {
+            cid: self.cid,
+            name: self.name.map(ToString::to_string),
+            tsize: self.tsize,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum UnixfsNode {
     Raw {
@@ -102,6 +157,26 @@ impl UnixfsNode {
         }
     }
 
+    /// Encodes the node into its block representation.
+    ///
+    /// For dag-pb the links are written before the data, as required by the spec,
+    /// which results in the same bytes go-ipfs produces.
+    pub fn encode(&self) -> Bytes {
+        match self {
+            UnixfsNode::Raw { data } => data.clone(),
+            UnixfsNode::Pb { outer, .. } => {
+                let mut buf = BytesMut::with_capacity(outer.encoded_len());
+                for link in &outer.links {
+                    prost::encoding::message::encode(2, link, &mut buf);
+                }
+                if let Some(ref data) = outer.data {
+                    prost::encoding::bytes::encode(1, data, &mut buf);
+                }
+                buf.freeze()
+            }
+        }
+    }
+
     pub fn typ(&self) -> Option<DataType> {
         match self {
             UnixfsNode::Raw { .. } => None,
@@ -126,6 +201,42 @@ impl UnixfsNode {
         }
     }
 
+    /// Returns the total size in bytes of the file content, including all linked blocks.
+    /// Available only for `Raw`, `File` and `Symlink` nodes.
+    pub fn filesize(&self) -> Option<u64> {
+        match self {
+            UnixfsNode::Raw { data } => Some(data.len() as u64),
+            UnixfsNode::Pb { inner, .. } => match self.typ() {
+                Some(DataType::File) | Some(DataType::Raw) => inner.filesize.or_else(|| {
+                    let data_len = inner.data.as_ref().map(|d| d.len()).unwrap_or_default();
+                    Some(data_len as u64 + inner.blocksizes.iter().sum::<u64>())
+                }),
+                Some(DataType::Symlink) => {
+                    Some(inner.data.as_ref().map(|d| d.len()).unwrap_or_default() as u64)
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the POSIX file mode, if stored in the node (UnixFS 1.5).
+    ///
+    /// All 32 bits are returned, including the ones that are reserved by the spec.
+    pub fn mode(&self) -> Option<u32> {
+        match self {
+            UnixfsNode::Raw { .. } => None,
+            UnixfsNode::Pb { inner, .. } => inner.mode,
+        }
+    }
+
+    /// Returns the modification time, if stored in the node (UnixFS 1.5).
+    pub fn mtime(&self) -> Option<SystemTime> {
+        match self {
+            UnixfsNode::Raw { .. } => None,
+            UnixfsNode::Pb { inner, .. } => inner.mtime.as_ref().and_then(system_time_from_pb),
+        }
+    }
+
     pub fn links(&self) -> Links {
         match self {
             UnixfsNode::Raw { .. } => Links::Raw,
@@ -133,21 +244,56 @@ impl UnixfsNode {
         }
     }
 
+    /// Returns `true` for both flat and HAMT sharded directories.
     pub fn is_dir(&self) -> bool {
-        matches!(self.typ(), Some(DataType::Directory))
+        matches!(
+            self.typ(),
+            Some(DataType::Directory) | Some(DataType::HamtShard)
+        )
     }
 
-    pub async fn get_link_by_name<S: AsRef<str>>(
+    /// Finds the link with the given name, traversing HAMT shards if this is a sharded directory.
+    pub async fn get_link_by_name<T: ContentLoader, S: AsRef<str>>(
         &self,
+        loader: &T,
         link_name: S,
-    ) -> Result<Option<LinkRef<'_>>> {
+    ) -> Result<Option<Link>> {
         let link_name = link_name.as_ref();
-        self.links()
-            .find(|l| match l {
-                Ok(l) => l.name == Some(link_name),
-                _ => false,
-            })
-            .transpose()
+        match self {
+            UnixfsNode::Pb { outer, inner } if self.typ() == Some(DataType::HamtShard) => {
+                hamt::get_link(outer, inner, loader, link_name).await
+            }
+            _ => self
+                .links()
+                .find(|l| match l {
+                    Ok(l) => l.name == Some(link_name),
+                    _ => false,
+                })
+                .transpose()
+                .map(|l| l.map(|l| l.to_link())),
+        }
+    }
+
+    /// Returns a stream over all entries of this directory.
+    ///
+    /// For HAMT sharded directories this loads the nested shards, yielding the actual
+    /// entry names. Returns `None` if this is not a directory.
+    pub fn dir_entries<'a, T: ContentLoader + 'a>(
+        &'a self,
+        loader: T,
+    ) -> Option<BoxStream<'a, Result<Link>>> {
+        match self {
+            UnixfsNode::Pb { outer, inner } if self.typ() == Some(DataType::HamtShard) => {
+                match hamt::entries(outer, inner, loader) {
+                    Ok(entries) => Some(entries.boxed()),
+                    Err(err) => Some(stream::once(async move { Err(err) }).boxed()),
+                }
+            }
+            _ if self.is_dir() => {
+                Some(stream::iter(self.links().map(|l| l.map(|l| l.to_link()))).boxed())
+            }
+            _ => None,
+        }
     }
 
     fn cid_links(&self) -> VecDeque<Cid> {
@@ -166,6 +312,63 @@ impl UnixfsNode {
         }
     }
 
+    /// Returns a stream over the entries of this directory, including sharded directories,
+    /// with their type and size. Entries are yielded in the order of [`Self::dir_entries`].
+    /// Only if this is a directory.
+    pub fn list_dir<'a, T: ContentLoader + 'a>(
+        &'a self,
+        loader: T,
+        options: ListOptions,
+    ) -> Option<BoxStream<'a, Result<DirEntry>>> {
+        let entries = self.dir_entries(loader.clone())?;
+        if !options.resolve {
+            return Some(
+                entries
+                    .map_ok(|link| DirEntry {
+                        name: link.name.unwrap_or_default(),
+                        cid: link.cid,
+                        tsize: link.tsize,
+                        typ: None,
+                        size: None,
+                    })
+                    .boxed(),
+            );
+        }
+
+        let entries = entries
+            .map_ok(move |link| {
+                let loader = loader.clone();
+                async move {
+                    let (typ, size) = if link.cid.codec() == Codec::Raw as u64
+                        && link.tsize.is_some()
+                    {
+                        // raw blocks are files, no need to load them
+                        (DataType::File, link.tsize)
+                    } else {
+                        let bytes = loader.load_cid(&link.cid).await?;
+                        let node = UnixfsNode::decode(&link.cid, bytes)?;
+                        let typ = node.typ().unwrap_or(DataType::File);
+                        let size = match typ {
+                            DataType::File | DataType::Raw | DataType::Symlink => node.filesize(),
+                            _ => None,
+                        };
+                        (typ, size)
+                    };
+
+                    Ok(DirEntry {
+                        name: link.name.unwrap_or_default(),
+                        cid: link.cid,
+                        tsize: link.tsize,
+                        typ: Some(typ),
+                        size,
+                    })
+                }
+            })
+            .try_buffered(std::cmp::max(options.concurrency, 1));
+
+        Some(entries.boxed())
+    }
+
     pub fn symlink(&self) -> Result<Option<&str>> {
         if self.typ() == Some(DataType::Symlink) {
             match self {
@@ -188,6 +391,8 @@ impl UnixfsNode {
             pos: 0,
             current_node: CurrentNodeState::Outer,
             current_links,
+            prefetch: Prefetch::new(DEFAULT_PREFETCH_WINDOW),
+            dir_listing: DirListing::None,
             loader,
         }
     }
@@ -202,9 +407,22 @@ pub struct UnixfsReader<T: ContentLoader> {
     current_node: CurrentNodeState,
     /// Stack of links left to traverse.
     current_links: Vec<VecDeque<Cid>>,
+    /// Loads of upcoming nodes, running in the background.
+    prefetch: Prefetch,
+    /// Rendered listing, only used for sharded directories.
+    dir_listing: DirListing,
     loader: T,
 }
 
+impl<T: ContentLoader> UnixfsReader<T> {
+    /// Sets the number of blocks that are loaded concurrently, ahead of the current
+    /// position, when reading chunked files. `0` disables prefetching.
+    pub fn prefetch_window(mut self, window: usize) -> Self {
+        self.prefetch = Prefetch::new(window);
+        self
+    }
+}
+
 impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsReader<T> {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -216,7 +434,9 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsReader<T> {
             root_node,
             current_node,
             current_links,
+            prefetch,
             pos,
+            dir_listing,
             loader,
         } = &mut *self;
         match root_node {
@@ -234,6 +454,7 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsReader<T> {
                     buf,
                     current_links,
                     current_node,
+                    prefetch,
                 ),
                 DataType::Symlink => {
                     let data = inner.data.as_deref().unwrap_or_default();
@@ -253,6 +474,9 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsReader<T> {
 
                     Poll::Ready(res)
                 }
+                DataType::HamtShard => {
+                    poll_read_hamt_listing(cx, outer, inner, loader.clone(), pos, buf, dir_listing)
+                }
                 _ => Poll::Ready(Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     format!("unsupported Unixfs type: {:?} ", typ),
@@ -262,6 +486,194 @@ impl<T: ContentLoader + Unpin + 'static> AsyncRead for UnixfsReader<T> {
     }
 }
 
+impl<T: ContentLoader + Unpin + 'static> AsyncSeek for UnixfsReader<T> {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        let Self {
+            root_node,
+            current_node,
+            current_links,
+            pos,
+            loader,
+            ..
+        } = &mut *self;
+
+        let new_pos = seek_position(*pos, root_node.filesize(), position)?;
+        *pos = new_pos;
+
+        if let UnixfsNode::Pb { outer, inner } = &*root_node {
+            if root_node.typ() == Some(DataType::File) {
+                let data_len = inner.data.as_ref().map(|d| d.len()).unwrap_or_default();
+                if new_pos < data_len || outer.links.is_empty() {
+                    // inside the root node, no need to load anything
+                    *current_node = CurrentNodeState::Outer;
+                    *current_links = vec![root_node.cid_links()];
+                } else {
+                    let fut = seek_in_children(
+                        loader.clone(),
+                        root_node.cid_links(),
+                        inner.blocksizes.clone(),
+                        (new_pos - data_len) as u64,
+                    )
+                    .boxed();
+                    *current_node = CurrentNodeState::Seeking(fut);
+                    current_links.clear();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        // Loading of the nodes at the new position happens lazily, on the next read.
+        Poll::Ready(Ok(self.pos as u64))
+    }
+}
+
+/// Calculates the new absolute position for a seek.
+pub fn seek_position(
+    pos: usize,
+    size: Option<u64>,
+    position: std::io::SeekFrom,
+) -> std::io::Result<usize> {
+    let (base, offset) = match position {
+        std::io::SeekFrom::Start(offset) => (offset, 0),
+        std::io::SeekFrom::Current(offset) => (pos as u64, offset),
+        std::io::SeekFrom::End(offset) => {
+            let size = size.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "unknown size, can not seek from the end",
+                )
+            })?;
+            (size, offset)
+        }
+    };
+
+    let new_pos = if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    };
+
+    new_pos
+        .and_then(|p| usize::try_from(p).ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })
+}
+
+/// Result of seeking: the stack of links left to traverse and the node containing the
+/// target position, together with the offset into it.
+type SeekResult = (Vec<VecDeque<Cid>>, Option<(usize, UnixfsNode)>);
+
+/// Descends into the children of a chunked file, to find the node containing `offset`.
+///
+/// Uses the `blocksizes` of each node to skip whole subtrees, so only the nodes on
+/// the path to the target are loaded.
+async fn seek_in_children<T: ContentLoader>(
+    loader: T,
+    mut links: VecDeque<Cid>,
+    mut blocksizes: Vec<u64>,
+    mut offset: u64,
+) -> Result<SeekResult> {
+    let mut stack = Vec::new();
+
+    loop {
+        ensure!(
+            links.len() == blocksizes.len(),
+            "missing blocksizes, can not seek"
+        );
+
+        let mut index = None;
+        for (i, size) in blocksizes.iter().enumerate() {
+            if offset < *size {
+                index = Some(i);
+                break;
+            }
+            offset -= size;
+        }
+        let index = match index {
+            Some(index) => index,
+            None => {
+                // seeked past the end
+                return Ok((Vec::new(), None));
+            }
+        };
+
+        let rest = links.split_off(index + 1);
+        let cid = links[index];
+        stack.push(rest);
+
+        let bytes = loader.load_cid(&cid).await?;
+        let node = UnixfsNode::decode(&cid, bytes)?;
+        match node {
+            UnixfsNode::Raw { .. } => {
+                stack.push(VecDeque::new());
+                return Ok((stack, Some((offset as usize, node))));
+            }
+            UnixfsNode::Pb {
+                ref outer,
+                ref inner,
+            } => {
+                let data_len = inner.data.as_ref().map(|d| d.len()).unwrap_or_default() as u64;
+                if offset < data_len || outer.links.is_empty() {
+                    stack.push(node.cid_links());
+                    return Ok((stack, Some((offset as usize, node))));
+                }
+
+                offset -= data_len;
+                links = node.cid_links();
+                blocksizes = inner.blocksizes.clone();
+            }
+        }
+    }
+}
+
+/// Converts a UnixFS timestamp, ignoring invalid fractional nanoseconds as required by
+/// the spec.
+fn system_time_from_pb(time: &unixfs_pb::UnixTime) -> Option<SystemTime> {
+    let seconds = time.seconds?;
+    let nanos = time
+        .fractional_nanoseconds
+        .filter(|n| *n > 0 && *n < 1_000_000_000)
+        .unwrap_or_default();
+
+    if seconds >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::new(seconds as u64, nanos))
+    } else {
+        SystemTime::UNIX_EPOCH
+            .checked_sub(Duration::from_secs(seconds.unsigned_abs()))?
+            .checked_add(Duration::from_nanos(nanos as u64))
+    }
+}
+
+/// Converts a timestamp into its UnixFS representation.
+pub(crate) fn system_time_to_pb(time: SystemTime) -> unixfs_pb::UnixTime {
+    let (seconds, nanos) = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            // before the epoch, the fractional part counts forward from the full second
+            let before = e.duration();
+            let mut seconds = -(before.as_secs() as i64);
+            let mut nanos = before.subsec_nanos();
+            if nanos > 0 {
+                seconds -= 1;
+                nanos = 1_000_000_000 - nanos;
+            }
+            (seconds, nanos)
+        }
+    };
+
+    unixfs_pb::UnixTime {
+        seconds: Some(seconds),
+        fractional_nanoseconds: if nanos > 0 { Some(nanos) } else { None },
+    }
+}
+
 pub fn poll_read_buf_at_pos(
     pos: &mut usize,
     data: &[u8],
@@ -283,6 +695,7 @@ enum CurrentNodeState {
     None,
     Loaded(usize, UnixfsNode),
     Loading(BoxFuture<'static, Result<UnixfsNode>>),
+    Seeking(BoxFuture<'static, Result<SeekResult>>),
 }
 
 impl Debug for CurrentNodeState {
@@ -294,6 +707,79 @@ impl Debug for CurrentNodeState {
                 write!(f, "CurrentNodeState::Loaded({:?}, {:?})", pos, n)
             }
             CurrentNodeState::Loading(_) => write!(f, "CurrentNodeState::Loading(Fut)"),
+            CurrentNodeState::Seeking(_) => write!(f, "CurrentNodeState::Seeking(Fut)"),
+        }
+    }
+}
+
+enum DirListing {
+    None,
+    Loading(BoxFuture<'static, Result<Vec<u8>>>),
+    Loaded(Vec<u8>),
+}
+
+impl Debug for DirListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirListing::None => write!(f, "DirListing::None"),
+            DirListing::Loading(_) => write!(f, "DirListing::Loading(Fut)"),
+            DirListing::Loaded(l) => write!(f, "DirListing::Loaded({} bytes)", l.len()),
+        }
+    }
+}
+
+/// Renders the names of all entries in a sharded directory, once all shards are loaded.
+fn poll_read_hamt_listing<T: ContentLoader + 'static>(
+    cx: &mut Context<'_>,
+    outer: &dag_pb::PbNode,
+    inner: &unixfs_pb::Data,
+    loader: T,
+    pos: &mut usize,
+    buf: &mut tokio::io::ReadBuf<'_>,
+    dir_listing: &mut DirListing,
+) -> Poll<std::io::Result<()>> {
+    loop {
+        match dir_listing {
+            DirListing::None => {
+                let entries = match hamt::entries(outer, inner, loader.clone()) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        return Poll::Ready(Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            e.to_string(),
+                        )));
+                    }
+                };
+                let fut = entries
+                    .try_fold(Vec::new(), |mut res, link| async move {
+                        if let Some(ref name) = link.name {
+                            res.extend_from_slice(name.as_bytes());
+                        }
+                        res.extend_from_slice(b"\n");
+                        Ok(res)
+                    })
+                    .boxed();
+                *dir_listing = DirListing::Loading(fut);
+            }
+            DirListing::Loading(fut) => match fut.poll_unpin(cx) {
+                Poll::Pending => {
+                    return Poll::Pending;
+                }
+                Poll::Ready(Ok(res)) => {
+                    *dir_listing = DirListing::Loaded(res);
+                }
+                Poll::Ready(Err(e)) => {
+                    *dir_listing = DirListing::None;
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        e.to_string(),
+                    )));
+                }
+            },
+            DirListing::Loaded(res) => {
+                let res = poll_read_buf_at_pos(pos, res, buf);
+                return Poll::Ready(res);
+            }
         }
     }
 }
@@ -301,33 +787,109 @@ impl Debug for CurrentNodeState {
 fn load_next_node<T: ContentLoader + 'static>(
     current_node: &mut CurrentNodeState,
     current_links: &mut Vec<VecDeque<Cid>>,
+    prefetch: &mut Prefetch,
     loader: T,
 ) -> bool {
     // Load next node
-    if current_links.is_empty() {
-        // no links left we are done
-        return true;
-    }
-    if current_links.last().unwrap().is_empty() {
-        // remove emtpy
+    while current_links
+        .last()
+        .map(|l| l.is_empty())
+        .unwrap_or_default()
+    {
+        // remove empty, as all nodes on this level are read
         current_links.pop();
     }
 
-    let links = current_links.last_mut().unwrap();
-    if links.is_empty() {
-        return true;
+    let link = match current_links.last_mut().and_then(|l| l.pop_front()) {
+        Some(link) => link,
+        None => {
+            // no links left we are done
+            return true;
+        }
+    };
+
+    let fut = match prefetch.take(&link) {
+        Some(load) => load.boxed(),
+        None => load_node(loader.clone(), link).boxed(),
+    };
+    prefetch.fill(current_links, &loader);
+    *current_node = CurrentNodeState::Loading(fut);
+    false
+}
+
+async fn load_node<T: ContentLoader>(loader: T, cid: Cid) -> Result<UnixfsNode> {
+    let bytes = loader.load_cid(&cid).await?;
+    let node = UnixfsNode::decode(&cid, bytes)?;
+    Ok(node)
+}
+
+/// Concurrently loads the next nodes of a chunked file, before they are read.
+///
+/// At most `window` loads are outstanding at a time. Nodes are requested in the order
+/// they are read, and loads that are not needed anymore, e.g. after seeking, are aborted.
+#[derive(Debug)]
+struct Prefetch {
+    window: usize,
+    pending: VecDeque<(Cid, AbortOnDrop<Result<UnixfsNode>>)>,
+}
+
+impl Prefetch {
+    fn new(window: usize) -> Self {
+        Prefetch {
+            window,
+            pending: VecDeque::new(),
+        }
     }
 
-    let link = links.pop_front().unwrap();
+    /// Takes the load of the given node, if it was started already.
+    fn take(&mut self, cid: &Cid) -> Option<AbortOnDrop<Result<UnixfsNode>>> {
+        let i = self.pending.iter().position(|(c, _)| c == cid)?;
+        self.pending.remove(i).map(|(_, load)| load)
+    }
+
+    /// Starts loading the upcoming nodes, until the window is full.
+    fn fill<T: ContentLoader + 'static>(&mut self, current_links: &[VecDeque<Cid>], loader: &T) {
+        if self.window == 0 {
+            return;
+        }
 
-    let fut = async move {
-        let bytes = loader.load_cid(&link).await?;
-        let node = UnixfsNode::decode(&link, bytes)?;
-        Ok(node)
+        // The deepest level is read first.
+        let upcoming = || current_links.iter().rev().flat_map(|l| l.iter());
+        self.pending.retain(|(cid, _)| upcoming().any(|c| c == cid));
+
+        for cid in upcoming() {
+            if self.pending.len() >= self.window {
+                break;
+            }
+            if self.pending.iter().any(|(c, _)| c == cid) {
+                continue;
+            }
+            let load = tokio::spawn(load_node(loader.clone(), *cid));
+            self.pending.push_back((*cid, AbortOnDrop(load)));
+        }
+    }
+}
+
+/// Aborts the spawned task when dropped, so dropping a reader cancels its loads.
+#[derive(Debug)]
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<T> Future for AbortOnDrop<Result<T>> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.0.poll_unpin(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(anyhow!("failed to load node: {}", e))),
+        }
     }
-    .boxed();
-    *current_node = CurrentNodeState::Loading(fut);
-    false
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -340,6 +902,7 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
     buf: &mut tokio::io::ReadBuf<'_>,
     current_links: &mut Vec<VecDeque<Cid>>,
     current_node: &mut CurrentNodeState,
+    prefetch: &mut Prefetch,
 ) -> Poll<std::io::Result<()>> {
     loop {
         match current_node {
@@ -360,12 +923,12 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                     }
                 }
                 *current_node = CurrentNodeState::None;
-                if load_next_node(current_node, current_links, loader.clone()) {
+                if load_next_node(current_node, current_links, prefetch, loader.clone()) {
                     return Poll::Ready(Ok(()));
                 }
             }
             CurrentNodeState::None => {
-                if load_next_node(current_node, current_links, loader.clone()) {
+                if load_next_node(current_node, current_links, prefetch, loader.clone()) {
                     return Poll::Ready(Ok(()));
                 }
             }
@@ -377,6 +940,7 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                     }
                     Poll::Ready(Ok(node)) => {
                         current_links.push(node.cid_links());
+                        prefetch.fill(current_links, &loader);
                         *current_node = CurrentNodeState::Loaded(0, node);
 
                         // TODO: do one read
@@ -390,6 +954,27 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                     }
                 }
             }
+            CurrentNodeState::Seeking(fut) => match fut.poll_unpin(cx) {
+                Poll::Pending => {
+                    return Poll::Pending;
+                }
+                Poll::Ready(Ok((links, node))) => {
+                    *current_links = links;
+                    prefetch.fill(current_links, &loader);
+                    *current_node = match node {
+                        Some((node_pos, node)) => CurrentNodeState::Loaded(node_pos, node),
+                        None => CurrentNodeState::None,
+                    };
+                }
+                Poll::Ready(Err(e)) => {
+                    *current_node = CurrentNodeState::None;
+                    current_links.clear();
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        e.to_string(),
+                    )));
+                }
+            },
             CurrentNodeState::Loaded(ref mut node_pos, ref mut current_node_inner) => {
                 // already loaded
                 let ty = current_node_inner.typ();
@@ -404,7 +989,8 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                             return Poll::Ready(res);
                         } else if *node_pos == data.len() {
                             // finished reading this node
-                            if load_next_node(current_node, current_links, loader.clone()) {
+                            if load_next_node(current_node, current_links, prefetch, loader.clone())
+                            {
                                 return Poll::Ready(Ok(()));
                             }
                         }
@@ -423,7 +1009,8 @@ fn poll_read_file_at<T: ContentLoader + 'static>(
                             }
 
                             // follow links
-                            if load_next_node(current_node, current_links, loader.clone()) {
+                            if load_next_node(current_node, current_links, prefetch, loader.clone())
+                            {
                                 return Poll::Ready(Ok(()));
                             }
                         } else {
@@ -0,0 +1,388 @@
+//! Support for HAMT sharded directories, as produced by go-ipfs.
+//!
+//! Entries are distributed by hashing their name with murmur3-x64-64 and consuming
+//! `log2(fanout)` bits of the hash per level. Each shard stores a bitfield of occupied
+//! slots and one link per occupied slot, either pointing at the entry itself (link name
+//! is the hex slot index followed by the entry name) or at a nested shard (link name is
+//! just the hex slot index).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::Bytes;
+use cid::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
+use futures::Stream;
+use prost::Message;
+
+use super::{dag_pb, unixfs_pb, DataType, Link, UnixfsNode};
+use crate::{codecs::Codec, resolver::ContentLoader};
+
+/// Multicodec of the murmur3-x64-64 hash function.
+const HASH_MURMUR3_X64_64: u64 = 0x22;
+
+/// Maximum supported fanout of a single shard.
+const MAX_FANOUT: u64 = 1024;
+
+/// Fanout used by go-ipfs.
+pub(crate) const DEFAULT_FANOUT: u64 = 256;
+
+/// Result of looking up a name in a single shard.
+enum Lookup {
+    NotFound,
+    Found(Link),
+    Shard(Cid),
+}
+
+/// Looks up the link named `name` in the shard, loading nested shards as needed.
+pub(crate) async fn get_link<T: ContentLoader>(
+    outer: &dag_pb::PbNode,
+    inner: &unixfs_pb::Data,
+    loader: &T,
+    name: &str,
+) -> Result<Option<Link>> {
+    let mut hash = HashBits::new(name.as_bytes());
+    let mut lookup = lookup(outer, inner, &mut hash, name)?;
+
+    loop {
+        match lookup {
+            Lookup::NotFound => return Ok(None),
+            Lookup::Found(link) => return Ok(Some(link)),
+            Lookup::Shard(cid) => {
+                let bytes = loader.load_cid(&cid).await?;
+                match UnixfsNode::decode(&cid, bytes)? {
+                    UnixfsNode::Pb { outer, inner }
+                        if inner.r#type == DataType::HamtShard as i32 =>
+                    {
+                        lookup = self::lookup(&outer, &inner, &mut hash, name)?;
+                    }
+                    _ => bail!("invalid hamt shard {}: not a shard node", cid),
+                }
+            }
+        }
+    }
+}
+
+/// Returns a stream over all entries of the shard, in link order, descending into nested shards.
+pub(crate) fn entries<T: ContentLoader>(
+    outer: &dag_pb::PbNode,
+    inner: &unixfs_pb::Data,
+    loader: T,
+) -> Result<impl Stream<Item = Result<Link>>> {
+    let root = (pad_length(fanout(inner)?), links(outer)?);
+
+    Ok(futures::stream::try_unfold(
+        (vec![root], loader),
+        |(mut stack, loader)| async move {
+            loop {
+                let (pad_len, link) = match stack.last_mut() {
+                    Some((pad_len, links)) => (*pad_len, links.pop_front()),
+                    None => return Ok(None),
+                };
+                let link = match link {
+                    Some(link) => link,
+                    None => {
+                        // done with this shard
+                        stack.pop();
+                        continue;
+                    }
+                };
+
+                let link_name = link.name.as_deref().unwrap_or_default();
+                match link_name.get(pad_len..) {
+                    Some("") => {
+                        // nested shard
+                        let bytes = loader.load_cid(&link.cid).await?;
+                        match UnixfsNode::decode(&link.cid, bytes)? {
+                            UnixfsNode::Pb { outer, inner }
+                                if inner.r#type == DataType::HamtShard as i32 =>
+                            {
+                                stack.push((pad_length(fanout(&inner)?), self::links(&outer)?));
+                            }
+                            _ => bail!("invalid hamt shard {}: not a shard node", link.cid),
+                        }
+                    }
+                    Some(name) => {
+                        let entry = Link {
+                            cid: link.cid,
+                            name: Some(name.to_string()),
+                            tsize: link.tsize,
+                        };
+                        return Ok(Some((entry, (stack, loader))));
+                    }
+                    None => bail!("invalid hamt link name {:?}", link_name),
+                }
+            }
+        },
+    ))
+}
+
+/// Builds a sharded directory containing the given entries.
+///
+/// Returns the blocks of all shards, the root shard last.
+pub(crate) fn encode(entries: Vec<Link>, fanout: u64) -> Result<Vec<(Cid, Bytes, Vec<Cid>)>> {
+    ensure!(
+        fanout.is_power_of_two() && fanout > 1 && fanout <= MAX_FANOUT,
+        "invalid hamt fanout {}",
+        fanout
+    );
+
+    let entries = entries
+        .into_iter()
+        .map(|link| {
+            let name = link
+                .name
+                .as_deref()
+                .ok_or_else(|| anyhow!("missing entry name"))?;
+            Ok((HashBits::new(name.as_bytes()), link))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut blocks = Vec::new();
+    encode_shard(entries, fanout, &mut blocks)?;
+    Ok(blocks)
+}
+
+/// Encodes a single shard, recursing into nested shards for colliding slots.
+/// Returns the cumulative size of the shard, its block is the last one in `blocks`.
+fn encode_shard(
+    entries: Vec<(HashBits, Link)>,
+    fanout: u64,
+    blocks: &mut Vec<(Cid, Bytes, Vec<Cid>)>,
+) -> Result<u64> {
+    let pad_len = pad_length(fanout);
+    let mut slots: BTreeMap<u32, Vec<(HashBits, Link)>> = BTreeMap::new();
+    for (mut hash, link) in entries {
+        let index = hash.next(fanout.trailing_zeros())?;
+        slots.entry(index).or_default().push((hash, link));
+    }
+
+    let mut bitfield = vec![0u8; (fanout as usize + 7) / 8];
+    let mut links = Vec::with_capacity(slots.len());
+    for (index, mut entries) in slots {
+        let len = bitfield.len();
+        bitfield[len - 1 - index as usize / 8] |= 1 << (index % 8);
+
+        if entries.len() == 1 {
+            let (_, link) = entries.pop().expect("checked");
+            let name = format!(
+                "{:0pad_len$X}{}",
+                index,
+                link.name.unwrap_or_default(),
+                pad_len = pad_len
+            );
+            links.push((link.cid, name, link.tsize.unwrap_or_default()));
+        } else {
+            let tsize = encode_shard(entries, fanout, blocks)?;
+            let (cid, _, _) = blocks.last().expect("just encoded");
+            let name = format!("{:0pad_len$X}", index, pad_len = pad_len);
+            links.push((*cid, name, tsize));
+        }
+    }
+
+    // the bitfield is encoded like a big endian integer, without leading zeros
+    let start = bitfield
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bitfield.len());
+    let inner = unixfs_pb::Data {
+        r#type: DataType::HamtShard as i32,
+        data: Some(Bytes::copy_from_slice(&bitfield[start..])),
+        hash_type: Some(HASH_MURMUR3_X64_64),
+        fanout: Some(fanout),
+        ..Default::default()
+    };
+    let outer = dag_pb::PbNode {
+        links: links
+            .iter()
+            .map(|(cid, name, tsize)| dag_pb::PbLink {
+                hash: Some(cid.to_bytes()),
+                name: Some(name.clone()),
+                tsize: Some(*tsize),
+            })
+            .collect(),
+        data: Some(inner.encode_to_vec().into()),
+    };
+
+    let bytes = UnixfsNode::Pb { outer, inner }.encode();
+    let cid = Cid::new_v1(Codec::DagPb as u64, Code::Sha2_256.digest(&bytes));
+    let tsize = bytes.len() as u64 + links.iter().map(|(_, _, tsize)| tsize).sum::<u64>();
+    blocks.push((
+        cid,
+        bytes,
+        links.into_iter().map(|(cid, _, _)| cid).collect(),
+    ));
+
+    Ok(tsize)
+}
+
+fn lookup(
+    outer: &dag_pb::PbNode,
+    inner: &unixfs_pb::Data,
+    hash: &mut HashBits,
+    name: &str,
+) -> Result<Lookup> {
+    let fanout = fanout(inner)?;
+    let bitfield = inner.data.as_deref().unwrap_or_default();
+
+    let index = hash.next(fanout.trailing_zeros())?;
+    if !bit_set(bitfield, index) {
+        return Ok(Lookup::NotFound);
+    }
+
+    let pos = ones_before(bitfield, index);
+    let link = outer
+        .links
+        .get(pos)
+        .ok_or_else(|| anyhow!("invalid hamt shard: missing link {}", pos))?;
+    let cid = link
+        .hash
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing link"))
+        .and_then(|c| Ok(Cid::read_bytes(Cursor::new(c))?))?;
+
+    let link_name = link.name.as_deref().unwrap_or_default();
+    match link_name.get(pad_length(fanout)..) {
+        Some("") => Ok(Lookup::Shard(cid)),
+        Some(link_name) if link_name == name => Ok(Lookup::Found(Link {
+            cid,
+            name: Some(name.to_string()),
+            tsize: link.tsize,
+        })),
+        Some(_) => Ok(Lookup::NotFound),
+        None => bail!("invalid hamt link name {:?}", link_name),
+    }
+}
+
+fn fanout(inner: &unixfs_pb::Data) -> Result<u64> {
+    ensure!(
+        inner.hash_type == Some(HASH_MURMUR3_X64_64),
+        "unsupported hamt hash type {:?}",
+        inner.hash_type
+    );
+    let fanout = inner
+        .fanout
+        .ok_or_else(|| anyhow!("invalid hamt shard: missing fanout"))?;
+    ensure!(
+        fanout.is_power_of_two() && fanout > 1 && fanout <= MAX_FANOUT,
+        "invalid hamt fanout {}",
+        fanout
+    );
+    Ok(fanout)
+}
+
+fn links(outer: &dag_pb::PbNode) -> Result<VecDeque<Link>> {
+    outer
+        .links
+        .iter()
+        .map(|l| {
+            let cid = l
+                .hash
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing link"))
+                .and_then(|c| Ok(Cid::read_bytes(Cursor::new(c))?))?;
+            Ok(Link {
+                cid,
+                name: l.name.clone(),
+                tsize: l.tsize,
+            })
+        })
+        .collect()
+}
+
+/// Length of the hex encoded slot index prefix in link names.
+fn pad_length(fanout: u64) -> usize {
+    format!("{:X}", fanout - 1).len()
+}
+
+/// Checks if bit `i` is set, interpreting the bitfield as a big endian integer.
+fn bit_set(bitfield: &[u8], i: u32) -> bool {
+    let byte = i as usize / 8;
+    if byte >= bitfield.len() {
+        return false;
+    }
+    (bitfield[bitfield.len() - 1 - byte] >> (i % 8)) & 1 == 1
+}
+
+/// Counts the set bits below `i`.
+fn ones_before(bitfield: &[u8], i: u32) -> usize {
+    (0..i).filter(|j| bit_set(bitfield, *j)).count()
+}
+
+/// Consumes the bits of a hashed name, most significant bit first.
+#[derive(Debug)]
+struct HashBits {
+    hash: [u8; 8],
+    consumed: usize,
+}
+
+impl HashBits {
+    fn new(name: &[u8]) -> Self {
+        let hash = murmur3::murmur3_x64_128(&mut Cursor::new(name), 0)
+            .expect("reading from memory can not fail");
+        // murmur3-x64-64 is the first half of murmur3-x64-128
+        HashBits {
+            hash: (hash as u64).to_be_bytes(),
+            consumed: 0,
+        }
+    }
+
+    fn next(&mut self, bits: u32) -> Result<u32> {
+        let bits = bits as usize;
+        ensure!(
+            self.consumed + bits <= self.hash.len() * 8,
+            "sharded directory too deep"
+        );
+
+        let mut out = 0;
+        for _ in 0..bits {
+            let bit = (self.hash[self.consumed / 8] >> (7 - self.consumed % 8)) & 1;
+            out = (out << 1) | bit as u32;
+            self.consumed += 1;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_bits() {
+        // murmur3-x64-64("hello") = cbd8a7b341bd9b02
+        let mut bits = HashBits::new(b"hello");
+        assert_eq!(bits.next(8).unwrap(), 0xcb);
+        assert_eq!(bits.next(4).unwrap(), 0xd);
+        assert_eq!(bits.next(8).unwrap(), 0x8a);
+        assert_eq!(bits.next(12).unwrap(), 0x7b3);
+        assert_eq!(bits.next(32).unwrap(), 0x41bd9b02);
+        assert!(bits.next(1).is_err());
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let bitfield = [0b0000_0001, 0b1000_0010];
+        assert!(!bit_set(&bitfield, 0));
+        assert!(bit_set(&bitfield, 1));
+        assert!(bit_set(&bitfield, 7));
+        assert!(bit_set(&bitfield, 8));
+        assert!(!bit_set(&bitfield, 9));
+        assert!(!bit_set(&bitfield, 200));
+
+        assert_eq!(ones_before(&bitfield, 1), 0);
+        assert_eq!(ones_before(&bitfield, 2), 1);
+        assert_eq!(ones_before(&bitfield, 8), 2);
+        assert_eq!(ones_before(&bitfield, 255), 3);
+    }
+
+    #[test]
+    fn test_pad_length() {
+        assert_eq!(pad_length(256), 2);
+        assert_eq!(pad_length(16), 1);
+        assert_eq!(pad_length(1024), 3);
+    }
+}
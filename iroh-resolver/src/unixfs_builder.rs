@@ -0,0 +1,1016 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, ensure, Result};
+use async_recursion::async_recursion;
+use bytes::Bytes;
+use cid::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
+use iroh_rpc_client::Client;
+use prost::Message;
+use tokio::io::AsyncRead;
+
+use crate::{
+    balanced_tree::{stream_balanced_tree, DEFAULT_DEGREE},
+    chunker::{Chunker, Fixed},
+    codecs::Codec,
+    trickle_tree::stream_trickle_tree,
+    unixfs::{dag_pb, hamt, system_time_to_pb, unixfs_pb, DataType, Link, UnixfsNode},
+};
+
+/// Estimated size of a directory node above which it is sharded, same as in go-ipfs.
+pub const DEFAULT_SHARDING_THRESHOLD: usize = 256 * 1024;
+
+enum FileContent {
+    Reader(Box<dyn AsyncRead + Unpin + Send>),
+    /// Opened lazily, when encoding.
+    Path(PathBuf),
+}
+
+impl Debug for FileContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileContent::Reader(_) => write!(f, "FileContent::Reader(Box<AsyncRead>)"),
+            FileContent::Path(path) => write!(f, "FileContent::Path({:?})", path),
+        }
+    }
+}
+
+/// Layout of the DAG a file is encoded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// All leaves at the same depth, the default in go-ipfs.
+    Balanced,
+    /// Optimized for reading from the start and appending, see [`stream_trickle_tree`].
+    Trickle,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Balanced
+    }
+}
+
+/// A file, ready to be encoded into a UnixFS DAG.
+#[derive(Debug)]
+pub struct File {
+    name: Option<String>,
+    content: FileContent,
+    chunker: Chunker,
+    layout: Layout,
+    degree: usize,
+    metadata: NodeMetadata,
+}
+
+impl File {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Chunks the content and encodes it as a DAG with raw leaves, using the configured layout.
+    ///
+    /// Yields `(cid, block, links)` for every block, the root block is always the last one.
+    /// Using the default settings results in the same CIDs as
+    /// `ipfs add --cid-version=1 --raw-leaves` in go-ipfs.
+    /// If a mode or mtime is set, the root is always a dag-pb node, as raw blocks can not
+    /// store them.
+    pub fn encode(self) -> impl Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>> + Send {
+        let chunker = self.chunker;
+        let chunks = match self.content {
+            FileContent::Reader(content) => chunker.chunks(content),
+            FileContent::Path(path) => {
+                stream::once(async move { tokio::fs::File::open(path).await })
+                    .map_ok(move |file| chunker.chunks(file))
+                    .try_flatten()
+                    .boxed()
+            }
+        };
+        let blocks = match self.layout {
+            Layout::Balanced => stream_balanced_tree(chunks, self.degree).boxed(),
+            Layout::Trickle => stream_trickle_tree(chunks, self.degree).boxed(),
+        };
+        self.metadata.apply_to_root(blocks)
+    }
+}
+
+/// Constructs a [`File`].
+#[derive(Debug, Default)]
+pub struct FileBuilder {
+    name: Option<String>,
+    content: Option<FileContent>,
+    chunker: Option<Chunker>,
+    layout: Layout,
+    degree: Option<usize>,
+    metadata: NodeMetadata,
+}
+
+impl FileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the file, required when adding it to a directory.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn content_reader<R: AsyncRead + Unpin + Send + 'static>(mut self, content: R) -> Self {
+        self.content = Some(FileContent::Reader(Box::new(content)));
+        self
+    }
+
+    pub fn content_bytes<B: Into<Bytes>>(mut self, content: B) -> Self {
+        let content = std::io::Cursor::new(content.into());
+        self.content = Some(FileContent::Reader(Box::new(content)));
+        self
+    }
+
+    /// Reads the content from the file at `path`, the file is only opened when encoding.
+    /// Unless set explicitly, the name is taken from the path.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        let path = path.into();
+        if self.name.is_none() {
+            self.name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(ToString::to_string);
+        }
+        self.content = Some(FileContent::Path(path));
+        self
+    }
+
+    /// Uses fixed size chunks of `chunk_size` bytes.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunker = Some(Chunker::Fixed(Fixed { chunk_size }));
+        self
+    }
+
+    /// Chunking strategy, defaults to fixed size chunks of
+    /// [`DEFAULT_CHUNK_SIZE_LIMIT`](crate::chunker::DEFAULT_CHUNK_SIZE_LIMIT) bytes.
+    pub fn chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = Some(chunker);
+        self
+    }
+
+    /// Layout of the DAG, defaults to [`Layout::Balanced`].
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Maximum number of links per node, defaults to [`DEFAULT_DEGREE`].
+    pub fn degree(mut self, degree: usize) -> Self {
+        self.degree = Some(degree);
+        self
+    }
+
+    /// POSIX file mode stored in the root node, not set by default.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.metadata.mode = Some(mode);
+        self
+    }
+
+    /// Modification time stored in the root node, not set by default.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.metadata.mtime = Some(mtime);
+        self
+    }
+
+    pub fn build(self) -> Result<File> {
+        let content = self.content.ok_or_else(|| anyhow!("missing content"))?;
+        let chunker = self.chunker.unwrap_or_default();
+        if let Chunker::Fixed(Fixed { chunk_size }) = chunker {
+            ensure!(chunk_size > 0, "chunk size must be larger than 0");
+        }
+        let degree = self.degree.unwrap_or(DEFAULT_DEGREE);
+        ensure!(degree > 1, "degree must be at least 2");
+
+        Ok(File {
+            name: self.name,
+            content,
+            chunker,
+            layout: self.layout,
+            degree,
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// A symlink, stored as a single `Symlink` node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symlink {
+    name: String,
+    target: PathBuf,
+    metadata: NodeMetadata,
+}
+
+impl Symlink {
+    pub fn new<N: Into<String>, P: Into<PathBuf>>(name: N, target: P) -> Self {
+        Symlink {
+            name: name.into(),
+            target: target.into(),
+            metadata: NodeMetadata::default(),
+        }
+    }
+
+    /// POSIX file mode stored in the node, not set by default.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.metadata.mode = Some(mode);
+        self
+    }
+
+    /// Modification time stored in the node, not set by default.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.metadata.mtime = Some(mtime);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    pub fn encode(self) -> Result<(Cid, Bytes, Vec<Cid>)> {
+        let target = self
+            .target
+            .to_str()
+            .ok_or_else(|| anyhow!("symlink target is not valid utf-8: {:?}", self.target))?;
+        let mut inner = unixfs_pb::Data {
+            r#type: DataType::Symlink as i32,
+            data: Some(Bytes::copy_from_slice(target.as_bytes())),
+            ..Default::default()
+        };
+        self.metadata.apply(&mut inner);
+        Ok(encode_pb(Vec::new(), inner))
+    }
+}
+
+/// A directory, ready to be encoded into a UnixFS DAG.
+#[derive(Debug)]
+pub struct Directory {
+    name: String,
+    entries: Vec<Entry>,
+    sharding_threshold: usize,
+    metadata: NodeMetadata,
+}
+
+impl Directory {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Encodes all entries and then the directory itself.
+    ///
+    /// Blocks are yielded as soon as they are encoded, so only the links of the directories
+    /// currently being encoded are kept in memory. The root block is always the last one.
+    /// Directories with an estimated size above the sharding threshold are encoded as
+    /// HAMT sharded directories.
+    pub fn encode(self) -> BoxStream<'static, Result<(Cid, Bytes, Vec<Cid>)>> {
+        let Directory {
+            entries,
+            sharding_threshold,
+            metadata,
+            ..
+        } = self;
+
+        let blocks = async_stream::try_stream! {
+            let mut links = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let name = entry.name().to_string();
+                let mut root = None;
+                let mut tsize = 0;
+
+                let mut blocks = entry.encode();
+                while let Some(block) = blocks.next().await {
+                    let (cid, bytes, block_links) = block?;
+                    tsize += bytes.len() as u64;
+                    root = Some(cid);
+                    yield (cid, bytes, block_links);
+                }
+
+                let cid = root.ok_or_else(|| anyhow!("no blocks encoded for {:?}", name))?;
+                links.push(Link {
+                    cid,
+                    name: Some(name),
+                    tsize: Some(tsize),
+                });
+            }
+
+            // same estimate as go-ipfs uses
+            let estimated_size: usize = links
+                .iter()
+                .map(|l| l.name.as_deref().unwrap_or_default().len() + l.cid.encoded_len())
+                .sum();
+            if estimated_size > sharding_threshold {
+                let blocks = hamt::encode(links, hamt::DEFAULT_FANOUT)?;
+                for block in blocks {
+                    yield block;
+                }
+            } else {
+                yield encode_dir(links);
+            }
+        };
+        metadata.apply_to_root(blocks.boxed())
+    }
+}
+
+/// Constructs a [`Directory`].
+#[derive(Debug, Default)]
+pub struct DirectoryBuilder {
+    name: Option<String>,
+    entries: Vec<Entry>,
+    sharding_threshold: Option<usize>,
+    metadata: NodeMetadata,
+}
+
+impl DirectoryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the directory, required when adding it to another directory.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn add_file(mut self, file: File) -> Self {
+        self.entries.push(Entry::File(file));
+        self
+    }
+
+    pub fn add_dir(mut self, dir: Directory) -> Self {
+        self.entries.push(Entry::Directory(dir));
+        self
+    }
+
+    pub fn add_symlink(mut self, symlink: Symlink) -> Self {
+        self.entries.push(Entry::Symlink(symlink));
+        self
+    }
+
+    /// Estimated size in bytes of the directory node above which it is sharded,
+    /// defaults to [`DEFAULT_SHARDING_THRESHOLD`]. Does not apply to nested directories.
+    pub fn sharding_threshold(mut self, threshold: usize) -> Self {
+        self.sharding_threshold = Some(threshold);
+        self
+    }
+
+    /// POSIX file mode stored in the root node, not set by default.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.metadata.mode = Some(mode);
+        self
+    }
+
+    /// Modification time stored in the root node, not set by default.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.metadata.mtime = Some(mtime);
+        self
+    }
+
+    pub fn build(self) -> Result<Directory> {
+        let mut names = HashSet::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let name = entry.name();
+            ensure!(
+                !name.is_empty() && !name.contains('/'),
+                "invalid entry name {:?}",
+                name
+            );
+            ensure!(names.insert(name), "duplicate entry name {:?}", name);
+        }
+
+        Ok(Directory {
+            name: self.name.unwrap_or_default(),
+            entries: self.entries,
+            sharding_threshold: self
+                .sharding_threshold
+                .unwrap_or(DEFAULT_SHARDING_THRESHOLD),
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// An entry in a [`Directory`].
+#[derive(Debug)]
+pub enum Entry {
+    File(File),
+    Directory(Directory),
+    Symlink(Symlink),
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        match self {
+            Entry::File(file) => file.name().unwrap_or_default(),
+            Entry::Directory(dir) => dir.name(),
+            Entry::Symlink(symlink) => symlink.name(),
+        }
+    }
+
+    /// Encodes the entry, the root block is always the last one.
+    pub fn encode(self) -> BoxStream<'static, Result<(Cid, Bytes, Vec<Cid>)>> {
+        match self {
+            Entry::File(file) => file.encode().boxed(),
+            Entry::Directory(dir) => dir.encode(),
+            Entry::Symlink(symlink) => stream::once(async move { symlink.encode() }).boxed(),
+        }
+    }
+}
+
+/// Creates a [`Directory`] from the local directory at `path`, including all nested files,
+/// directories and symlinks. Symlinks are not followed, other file types are skipped.
+///
+/// Only the structure is read here, file contents are read when encoding.
+pub async fn make_dir_from_path<P: AsRef<Path>>(path: P) -> Result<Directory> {
+    dir_from_path(path.as_ref().to_path_buf()).await
+}
+
+#[async_recursion]
+async fn dir_from_path(path: PathBuf) -> Result<Directory> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let mut dir = DirectoryBuilder::new().name(name);
+
+    let mut read_dir = tokio::fs::read_dir(&path).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let file_type = entry.file_type().await?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| anyhow!("invalid file name {:?}", name))?;
+
+        if file_type.is_symlink() {
+            let target = tokio::fs::read_link(entry.path()).await?;
+            dir = dir.add_symlink(Symlink::new(name, target));
+        } else if file_type.is_dir() {
+            dir = dir.add_dir(dir_from_path(entry.path()).await?);
+        } else if file_type.is_file() {
+            let file = FileBuilder::new().name(name).path(entry.path()).build()?;
+            dir = dir.add_file(file);
+        }
+    }
+
+    dir.build()
+}
+
+/// Imports the file at `path` into the store, returning the root CID.
+pub async fn add_file(client: &Client, path: &Path) -> Result<Cid> {
+    let file = FileBuilder::new().path(path).build()?;
+    store_blocks(client, file.encode()).await
+}
+
+/// Imports the directory at `path`, including all nested entries, into the store,
+/// returning the root CID.
+pub async fn add_dir(client: &Client, path: &Path) -> Result<Cid> {
+    let dir = make_dir_from_path(path).await?;
+    store_blocks(client, dir.encode()).await
+}
+
+async fn store_blocks(
+    client: &Client,
+    blocks: impl Stream<Item = Result<(Cid, Bytes, Vec<Cid>)>>,
+) -> Result<Cid> {
+    tokio::pin!(blocks);
+
+    let mut root = None;
+    while let Some(block) = blocks.next().await {
+        let (cid, bytes, links) = block?;
+        client.store.put(cid, bytes, links).await?;
+        root = Some(cid);
+    }
+
+    root.ok_or_else(|| anyhow!("nothing to store"))
+}
+
+/// Optional attributes of a node, added in UnixFS 1.5.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct NodeMetadata {
+    mode: Option<u32>,
+    mtime: Option<SystemTime>,
+}
+
+impl NodeMetadata {
+    fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    fn apply(&self, inner: &mut unixfs_pb::Data) {
+        inner.mode = self.mode;
+        inner.mtime = self.mtime.map(system_time_to_pb);
+    }
+
+    /// Stores the metadata in the root, which is the last of the given blocks.
+    fn apply_to_root(
+        self,
+        blocks: BoxStream<'static, Result<(Cid, Bytes, Vec<Cid>)>>,
+    ) -> BoxStream<'static, Result<(Cid, Bytes, Vec<Cid>)>> {
+        if self.is_empty() {
+            return blocks;
+        }
+
+        async_stream::try_stream! {
+            let mut blocks = blocks;
+            let mut last = None;
+            while let Some(block) = blocks.next().await {
+                if let Some(block) = last.replace(block?) {
+                    yield block;
+                }
+            }
+            if let Some(root) = last {
+                yield self.encode_root(root)?;
+            }
+        }
+        .boxed()
+    }
+
+    /// Re-encodes the root with the metadata. A raw root is turned into a `File` node.
+    fn encode_root(&self, root: (Cid, Bytes, Vec<Cid>)) -> Result<(Cid, Bytes, Vec<Cid>)> {
+        let (cid, bytes, links) = root;
+        match UnixfsNode::decode(&cid, bytes)? {
+            UnixfsNode::Raw { data } => {
+                let mut inner = unixfs_pb::Data {
+                    r#type: DataType::File as i32,
+                    filesize: Some(data.len() as u64),
+                    data: Some(data),
+                    ..Default::default()
+                };
+                self.apply(&mut inner);
+                Ok(encode_pb(Vec::new(), inner))
+            }
+            UnixfsNode::Pb {
+                mut outer,
+                mut inner,
+            } => {
+                self.apply(&mut inner);
+                outer.data = Some(inner.encode_to_vec().into());
+                let bytes = UnixfsNode::Pb { outer, inner }.encode();
+                let cid = Cid::new_v1(Codec::DagPb as u64, Code::Sha2_256.digest(&bytes));
+                Ok((cid, bytes, links))
+            }
+        }
+    }
+}
+
+/// Reference to an encoded node of a file, with the sizes needed to link to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TreeLink {
+    pub cid: Cid,
+    /// Size of the file content below this node.
+    pub filesize: u64,
+    /// Cumulative size of all blocks below this node, including itself.
+    pub tsize: u64,
+}
+
+/// Encodes a chunk as a raw leaf.
+pub(crate) fn encode_leaf(chunk: Bytes) -> (TreeLink, (Cid, Bytes, Vec<Cid>)) {
+    let cid = Cid::new_v1(Codec::Raw as u64, Code::Sha2_256.digest(&chunk));
+    let len = chunk.len() as u64;
+    let link = TreeLink {
+        cid,
+        filesize: len,
+        tsize: len,
+    };
+
+    (link, (cid, chunk, Vec::new()))
+}
+
+/// Encodes an intermediate file node, linking to the given children.
+pub(crate) fn encode_stem(links: &[TreeLink]) -> (TreeLink, (Cid, Bytes, Vec<Cid>)) {
+    let blocksizes: Vec<u64> = links.iter().map(|l| l.filesize).collect();
+    let filesize = blocksizes.iter().sum();
+    let inner = unixfs_pb::Data {
+        r#type: DataType::File as i32,
+        filesize: Some(filesize),
+        blocksizes,
+        ..Default::default()
+    };
+    let pb_links = links
+        .iter()
+        .map(|l| Link {
+            cid: l.cid,
+            name: Some(String::new()),
+            tsize: Some(l.tsize),
+        })
+        .collect();
+    let (cid, bytes, child_cids) = encode_pb(pb_links, inner);
+
+    let link = TreeLink {
+        cid,
+        filesize,
+        tsize: bytes.len() as u64 + links.iter().map(|l| l.tsize).sum::<u64>(),
+    };
+
+    (link, (cid, bytes, child_cids))
+}
+
+/// Encodes a flat directory node, the links are sorted by name.
+fn encode_dir(mut links: Vec<Link>) -> (Cid, Bytes, Vec<Cid>) {
+    links.sort_by(|a, b| a.name.cmp(&b.name));
+    let inner = unixfs_pb::Data {
+        r#type: DataType::Directory as i32,
+        ..Default::default()
+    };
+
+    encode_pb(links, inner)
+}
+
+/// Encodes a dag-pb node, containing the given links and unixfs data.
+fn encode_pb(links: Vec<Link>, inner: unixfs_pb::Data) -> (Cid, Bytes, Vec<Cid>) {
+    let child_cids = links.iter().map(|l| l.cid).collect();
+    let outer = dag_pb::PbNode {
+        links: links
+            .into_iter()
+            .map(|l| dag_pb::PbLink {
+                hash: Some(l.cid.to_bytes()),
+                name: l.name,
+                tsize: l.tsize,
+            })
+            .collect(),
+        data: Some(inner.encode_to_vec().into()),
+    };
+    let bytes = UnixfsNode::Pb { outer, inner }.encode();
+    let cid = Cid::new_v1(Codec::DagPb as u64, Code::Sha2_256.digest(&bytes));
+
+    (cid, bytes, child_cids)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{
+        chunker::{Rabin, DEFAULT_CHUNK_SIZE_LIMIT},
+        resolver::UnixfsType,
+        test_utils::{into_resolver, load_fixture, resolver_for},
+    };
+
+    async fn encode(
+        content: &[u8],
+        chunk_size: usize,
+        degree: usize,
+    ) -> Vec<(Cid, Bytes, Vec<Cid>)> {
+        FileBuilder::new()
+            .content_bytes(content.to_vec())
+            .chunk_size(chunk_size)
+            .degree(degree)
+            .build()
+            .unwrap()
+            .encode()
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_encode_go_ipfs_compat() {
+        // ipfs add --cid-version=1 --raw-leaves
+        let blocks = encode(b"", DEFAULT_CHUNK_SIZE_LIMIT, DEFAULT_DEGREE).await;
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+
+        let blocks = encode(b"hello world", DEFAULT_CHUNK_SIZE_LIMIT, DEFAULT_DEGREE).await;
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+
+        // ipfs add --cid-version=1 --raw-leaves --chunker=size-64, with 4 links per node
+        let content: Vec<u8> = (0..1000).map(|i| b'a' + (i % 26) as u8).collect();
+        let blocks = encode(&content, 64, 4).await;
+        let (root, _, _) = blocks.last().unwrap();
+        assert_eq!(
+            root.to_string(),
+            "bafybeicunh5ha3gzmgocluisqanf5pchuo73c6xy42npmqv56z36gxt3hi"
+        );
+        for (cid, bytes, _) in &blocks {
+            let expected = load_fixture(&cid.to_string()).await;
+            assert_eq!(bytes, &expected, "block {}", cid);
+        }
+
+        // not a full tree
+        let content: Vec<u8> = (0..1100).map(|i| b'a' + (i % 26) as u8).collect();
+        let blocks = encode(&content, 64, 4).await;
+        assert_eq!(
+            blocks.last().unwrap().0.to_string(),
+            "bafybeifx2qiqgyju3qhaz7xvmvjd5hvmexmdq3overt4vkkpbbldtpy7ca"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_roundtrip() {
+        let content: Vec<u8> = (0..300_000usize)
+            .map(|i| ((i * 7 + i / 251) % 256) as u8)
+            .collect();
+        let blocks = encode(&content, 1024, 16).await;
+        // 293 leaves, 19 + 2 + 1 nodes
+        assert_eq!(blocks.len(), 315);
+
+        let root = blocks.last().unwrap().0;
+        assert_eq!(
+            root.to_string(),
+            "bafybeihq2in3kpul6uo3xszy6n2q4kxzimbivbt7edo6ijodn4ygka3y4q"
+        );
+
+        for (cid, bytes, links) in &blocks {
+            assert_eq!(crate::verify_hash(cid, bytes), Some(true));
+            assert_eq!(&crate::parse_links(cid, bytes).unwrap(), links);
+        }
+
+        assert_eq!(read_back(blocks).await, content);
+    }
+
+    #[tokio::test]
+    async fn test_encode_trickle() {
+        let content: Vec<u8> = (0..3000).map(|i| b'a' + (i % 26) as u8).collect();
+        let file = FileBuilder::new()
+            .content_bytes(content.clone())
+            .chunk_size(64)
+            .degree(4)
+            .layout(Layout::Trickle)
+            .build()
+            .unwrap();
+        let blocks: Vec<_> = file.encode().try_collect().await.unwrap();
+
+        // ipfs add --cid-version=1 --raw-leaves --trickle --chunker=size-64, with 4 links per node
+        let root = blocks.last().unwrap().0;
+        assert_eq!(
+            root.to_string(),
+            "bafybeihk2qa5rbv5q3fluytxvgzmht6g3ngrcgd4wx6rqu3nsgb4gfwvem"
+        );
+        assert_eq!(read_back(blocks).await, content);
+    }
+
+    #[tokio::test]
+    async fn test_encode_content_defined() {
+        let content: Vec<u8> = (0..300_000usize)
+            .map(|i| ((i * 7 + i / 251) % 256) as u8)
+            .collect();
+        let encode = || async {
+            FileBuilder::new()
+                .content_bytes(content.clone())
+                .chunker(Chunker::Rabin(Rabin::new(256, 1024, 4096).unwrap()))
+                .degree(16)
+                .build()
+                .unwrap()
+                .encode()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+        };
+
+        let blocks = encode().await;
+        // same content and parameters, same root
+        assert_eq!(blocks.last().unwrap().0, encode().await.last().unwrap().0);
+        assert_eq!(read_back(blocks).await, content);
+    }
+
+    /// Reads the content of the file, the root being the last block.
+    async fn read_back(blocks: Vec<(Cid, Bytes, Vec<Cid>)>) -> Vec<u8> {
+        let (root, resolver) = into_resolver(blocks);
+        let out = resolver
+            .resolve(format!("/ipfs/{root}").parse().unwrap())
+            .await
+            .unwrap();
+        let mut content = Vec::new();
+        out.pretty(resolver.loader().clone())
+            .read_to_end(&mut content)
+            .await
+            .unwrap();
+        content
+    }
+
+    fn file(name: &str, content: &'static [u8]) -> File {
+        FileBuilder::new()
+            .name(name)
+            .content_bytes(content)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_encode_dir() {
+        let dir = DirectoryBuilder::new().build().unwrap();
+        let blocks: Vec<_> = dir.encode().try_collect().await.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"
+        );
+
+        let sub2 = DirectoryBuilder::new()
+            .name("sub2")
+            .add_file(file("b.txt", b"b\n"))
+            .build()
+            .unwrap();
+        let dir = DirectoryBuilder::new()
+            .add_dir(sub2)
+            .add_symlink(Symlink::new("link", "a.txt"))
+            .add_file(file("a.txt", b"hello world"))
+            .add_dir(DirectoryBuilder::new().name("sub").build().unwrap())
+            .build()
+            .unwrap();
+        let blocks: Vec<_> = dir.encode().try_collect().await.unwrap();
+        assert_eq!(blocks.len(), 6);
+        let (root, resolver) = into_resolver(blocks);
+        assert_eq!(
+            root.to_string(),
+            "bafybeidygx2pg3rmde7p7aanxtiujnmhbz7b4flzamwcr62iz7rak2b3tu"
+        );
+        let loader = resolver.loader().clone();
+
+        let out = resolver
+            .resolve(format!("/ipfs/{root}/sub2/b.txt").parse().unwrap())
+            .await
+            .unwrap();
+        let mut content = String::new();
+        out.pretty(loader.clone())
+            .read_to_string(&mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, "b\n");
+
+        let out = resolver
+            .resolve(format!("/ipfs/{root}/link").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(out.metadata().unixfs_type, Some(UnixfsType::Symlink));
+
+        let dir = DirectoryBuilder::new()
+            .add_file(file("a.txt", b"a"))
+            .add_file(file("a.txt", b"b"))
+            .build();
+        assert!(dir.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encode_hamt_dir() {
+        let names = [
+            "hello.txt",
+            "world.txt",
+            "README.md",
+            "index.html",
+            "styles.css",
+            "file-297.txt",
+            "file-159140.txt",
+            "file-229541.txt",
+        ];
+
+        let mut dir = DirectoryBuilder::new().sharding_threshold(0);
+        for name in names {
+            let file = FileBuilder::new()
+                .name(name)
+                .content_bytes(format!("content of {}\n", name))
+                .build()
+                .unwrap();
+            dir = dir.add_file(file);
+        }
+        let blocks: Vec<_> = dir.build().unwrap().encode().try_collect().await.unwrap();
+
+        // same as the fixture, sharded by go-ipfs
+        let root = blocks.last().unwrap().0;
+        assert_eq!(
+            root.to_string(),
+            "bafybeigt6qre636hfer4rcbug5z2xuyif3kkxypnqijouy6c63rxcn6uui"
+        );
+        assert_eq!(blocks.len(), 11);
+        for (cid, bytes, _) in &blocks {
+            let expected = load_fixture(&cid.to_string()).await;
+            assert_eq!(bytes, &expected, "block {}", cid);
+        }
+
+        // below the threshold, the directory is not sharded
+        let dir = DirectoryBuilder::new()
+            .add_file(file("a.txt", b"a"))
+            .build()
+            .unwrap();
+        let blocks: Vec<_> = dir.encode().try_collect().await.unwrap();
+        let (cid, bytes, _) = blocks.last().unwrap();
+        let node = UnixfsNode::decode(cid, bytes.clone()).unwrap();
+        assert_eq!(node.typ(), Some(DataType::Directory));
+    }
+
+    #[tokio::test]
+    async fn test_encode_metadata() {
+        use std::time::Duration;
+
+        let mtime = SystemTime::UNIX_EPOCH + Duration::new(1_650_000_000, 500);
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::new(10, 250);
+        let content: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+
+        let small = FileBuilder::new()
+            .name("small.txt")
+            .content_bytes(&b"hello world"[..])
+            .mode(0o644)
+            .mtime(mtime)
+            .build()
+            .unwrap();
+        let large = FileBuilder::new()
+            .name("large.bin")
+            .content_bytes(content.clone())
+            .chunk_size(100)
+            .mode(0o600)
+            .build()
+            .unwrap();
+        let dir = DirectoryBuilder::new()
+            .add_file(small)
+            .add_file(large)
+            .add_file(file("plain.txt", b"hello world"))
+            .add_symlink(Symlink::new("link", "small.txt").mtime(before_epoch))
+            .mode(0o755)
+            .mtime(mtime)
+            .build()
+            .unwrap();
+        let (root, resolver) = resolver_for(dir.encode()).await;
+        let loader = resolver.loader().clone();
+
+        let resolve = |path: &str| {
+            let path = format!("/ipfs/{root}{path}");
+            let resolver = &resolver;
+            async move { resolver.resolve(path.parse().unwrap()).await.unwrap() }
+        };
+
+        let out = resolve("").await;
+        assert_eq!(out.metadata().mode, Some(0o755));
+        assert_eq!(out.metadata().mtime, Some(mtime));
+
+        let out = resolve("/small.txt").await;
+        assert_eq!(out.metadata().mode, Some(0o644));
+        assert_eq!(out.metadata().mtime, Some(mtime));
+        let mut buf = Vec::new();
+        out.pretty(loader.clone())
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, b"hello world");
+
+        let out = resolve("/large.bin").await;
+        assert_eq!(out.metadata().mode, Some(0o600));
+        assert_eq!(out.metadata().mtime, None);
+        let mut buf = Vec::new();
+        out.pretty(loader.clone())
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, content);
+
+        // without metadata the file is still a raw block
+        let out = resolve("/plain.txt").await;
+        assert_eq!(out.metadata().mode, None);
+        assert_eq!(
+            out.metadata().resolved_path.last().unwrap().1.to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+
+        let out = resolve("/link").await;
+        assert_eq!(out.metadata().mode, None);
+        assert_eq!(out.metadata().mtime, Some(before_epoch));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_make_dir_from_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path();
+        std::fs::write(path.join("a.txt"), "hello world").unwrap();
+        std::os::unix::fs::symlink("a.txt", path.join("link")).unwrap();
+        std::fs::create_dir(path.join("sub")).unwrap();
+        std::fs::create_dir(path.join("sub2")).unwrap();
+        std::fs::write(path.join("sub2").join("b.txt"), "b\n").unwrap();
+
+        let dir = make_dir_from_path(path).await.unwrap();
+        assert_eq!(dir.entries().len(), 4);
+        let blocks: Vec<_> = dir.encode().try_collect().await.unwrap();
+
+        // same as in test_encode_dir
+        assert_eq!(
+            blocks.last().unwrap().0.to_string(),
+            "bafybeidygx2pg3rmde7p7aanxtiujnmhbz7b4flzamwcr62iz7rak2b3tu"
+        );
+    }
+}
@@ -53,6 +53,14 @@ impl P2pClient {
         Ok(providers)
     }
 
+    /// Fetches all records stored in the DHT under the given key.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_record(&self, key: Vec<u8>) -> Result<Vec<Bytes>> {
+        let req = iroh_metrics::req::trace_tonic_req(Key { key });
+        let res = self.0.clone().get_record(req).await?;
+        Ok(res.into_inner().records)
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get_listening_addrs(&self) -> Result<(PeerId, Vec<Multiaddr>)> {
         let req = iroh_metrics::req::trace_tonic_req(Empty {});
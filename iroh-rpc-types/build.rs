@@ -2,6 +2,7 @@ fn main() {
     let mut config = prost_build::Config::new();
     config.bytes(&[
         ".p2p.BitswapResponse",
+        ".p2p.GetRecordResponse",
         ".store.PutRequest.blob",
         ".store.GetResponse.data",
     ]);
@@ -9,6 +9,7 @@ use std::{
 use anyhow::{anyhow, bail, Context, Result};
 use cid::Cid;
 use iroh_rpc_client::Client as RpcClient;
+use iroh_util::identity_content;
 use rocksdb::{
     BlockBasedOptions, Cache, DBPinnableSlice, IteratorMode, Options, WriteBatch, DB as RocksDb,
 };
@@ -176,6 +177,10 @@ impl Store {
     {
         self.metrics.put_requests_total.inc();
 
+        // the content is inlined in the cid, there is nothing to persist
+        if identity_content(&cid).is_some() {
+            return Ok(());
+        }
         if self.has(&cid).await? {
             return Ok(());
         }
@@ -264,6 +269,9 @@ impl Store {
 
     #[tracing::instrument(skip(self))]
     pub async fn has(&self, cid: &Cid) -> Result<bool> {
+        if identity_content(cid).is_some() {
+            return Ok(true);
+        }
         match self.get_id(cid).await? {
             Some(id) => {
                 let cf_blobs = self
@@ -442,6 +450,7 @@ mod tests {
     use iroh_rpc_client::RpcClientConfig;
 
     use cid::multihash::{Code, MultihashDigest};
+    use iroh_util::IDENTITY_HASH_CODE;
     const RAW: u64 = 0x55;
 
     #[tokio::test]
@@ -480,6 +489,15 @@ mod tests {
             let links = store.get_links(c).await.unwrap().unwrap();
             assert_eq!(expected_links, &links[..]);
         }
+
+        // identity cids are not persisted, their content is served inline by the rpc
+        let identity = cid::multihash::Multihash::wrap(IDENTITY_HASH_CODE, b"inline").unwrap();
+        let c = cid::Cid::new_v1(RAW, identity);
+        assert_eq!(identity_content(&c).unwrap(), &b"inline"[..]);
+        store.put(c, b"inline", []).await.unwrap();
+        assert!(store.has(&c).await.unwrap());
+        assert!(store.get(&c).await.unwrap().is_none());
+        assert!(store.get_links(&c).await.unwrap().is_none());
     }
 
     #[tokio::test]
@@ -6,6 +6,22 @@ use std::{
     },
 };
 
+use bytes::Bytes;
+use cid::Cid;
+
+/// Multihash code of the identity hash.
+pub const IDENTITY_HASH_CODE: u64 = 0x00;
+
+/// Returns the content of a cid that uses the identity hash, which embeds the content in
+/// the cid itself. Such cids never need to be fetched or stored.
+pub fn identity_content(cid: &Cid) -> Option<Bytes> {
+    if cid.hash().code() == IDENTITY_HASH_CODE {
+        Some(Bytes::copy_from_slice(cid.hash().digest()))
+    } else {
+        None
+    }
+}
+
 /// Blocks current thread until ctrl-c is received
 pub async fn block_until_sigint() {
     let (ctrlc_send, ctrlc_oneshot) = futures::channel::oneshot::channel();